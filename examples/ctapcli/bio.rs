@@ -8,7 +8,8 @@ use crate::str_buf::StrBuf;
 use ctap_hid_fido2::util;
 use ctap_hid_fido2::verifier;
 
-use ctap_hid_fido2::fidokey::{bio::EnrollStatus1, get_info::InfoOption, FidoKeyHid};
+use ctap_hid_fido2::fidokey::{get_info::InfoOption, FidoKeyHid};
+use std::time::Duration;
 
 pub enum Command {
     List,
@@ -83,34 +84,20 @@ fn bio_enrollment(device: &FidoKeyHid, pin: &str) -> Result<Vec<u8>> {
     common::get_input();
     println!();
 
-    let (enroll_status1, enroll_status2) = device.bio_enrollment_begin(pin, Some(10000))?;
-    println!();
-    println!("{}", enroll_status2.message);
-    println!(
-        "- Number of samples required = {:?}",
-        enroll_status2.remaining_samples
-    );
-    println!();
-
-    for _counter in 0..10 {
-        if bio_enrollment_next(device, &enroll_status1)? {
-            break;
-        }
+    let mut enrollment = device
+        .bio_enroll(pin)?
+        .sample_timeout(Duration::from_secs(10));
+    while let Some(sample) = enrollment.next_sample()? {
+        println!();
+        println!("{}", sample.feedback);
+        println!(
+            "- Number of samples required = {:?}",
+            sample.remaining_samples
+        );
+        println!();
     }
     println!("- bio enrollment Success\n");
-    Ok(enroll_status1.template_id)
-}
-
-fn bio_enrollment_next(device: &FidoKeyHid, enroll_status1: &EnrollStatus1) -> Result<bool> {
-    let enroll_status2 = device.bio_enrollment_next(enroll_status1, Some(10000))?;
-    println!();
-    println!("{}", enroll_status2.message);
-    println!(
-        "- Number of samples required = {:?}",
-        enroll_status2.remaining_samples
-    );
-    println!();
-    Ok(enroll_status2.is_finish)
+    Ok(enrollment.template_id().to_vec())
 }
 
 fn is_supported(device: &FidoKeyHid) -> Result<bool> {
@@ -1,7 +1,7 @@
 pub fn get_input() -> String {
     let mut word = String::new();
     std::io::stdin().read_line(&mut word).ok();
-    return word.trim().to_string();
+    word.trim().to_string()
 }
 
 pub fn get_input_with_message(message: &str) -> String {
@@ -1,15 +1,28 @@
 use crate::{common, memo, util};
 use anyhow::{anyhow, Result};
 use ctap_hid_fido2::{
-    fidokey::{get_info::InfoOption, FidoKeyHid},
+    fidokey::{
+        credential_management::credential_management_params::CredentialSelector,
+        get_info::InfoOption, FidoKeyHid,
+    },
     public_key_credential_user_entity::PublicKeyCredentialUserEntity,
 };
 
 pub enum Command {
     Metadata,
     List,
-    Del((String, String)),
-    Update((String, String)),
+    Del {
+        rpid: Option<String>,
+        user_id: Option<String>,
+        user_name: Option<String>,
+        dry_run: bool,
+    },
+    Update {
+        rpid: String,
+        user_id: String,
+        name: String,
+        display_name: String,
+    },
 }
 
 pub fn cred(device: &FidoKeyHid, command: Command, pin: Option<String>) -> Result<()> {
@@ -34,19 +47,32 @@ pub fn cred(device: &FidoKeyHid, command: Command, pin: Option<String>) -> Resul
             println!("Getting Credentials Metadata.");
             metadata(device, &pin)?;
         }
-        Command::Del((rpid, user_id)) => {
-            println!("Delete a Credential.");
-
-            if rpid.is_empty() || user_id.is_empty() {
-                return Err(anyhow!("Need rpid and userid."));
+        Command::Del {
+            rpid,
+            user_id,
+            user_name,
+            dry_run,
+        } => {
+            println!("Delete Credentials.");
+
+            if rpid.is_none() && user_id.is_none() && user_name.is_none() {
+                return Err(anyhow!("Need rpid, userid or user-name."));
             }
 
-            println!("- credential: (rpid: {}, user_id: {})", rpid, user_id);
-            println!();
-
-            delete(device, &pin, &rpid, &util::to_str_hex(&user_id))?;
+            let selector = CredentialSelector {
+                rpid,
+                user_id: user_id.map(|id| util::to_str_hex(&id)),
+                user_name,
+                ..Default::default()
+            };
+            delete(device, &pin, &selector, dry_run)?;
         }
-        Command::Update((rpid, user_id)) => {
+        Command::Update {
+            rpid,
+            user_id,
+            name,
+            display_name,
+        } => {
             println!("Update a Credential.");
 
             if rpid.is_empty() || user_id.is_empty() {
@@ -56,7 +82,14 @@ pub fn cred(device: &FidoKeyHid, command: Command, pin: Option<String>) -> Resul
             println!("- credential: (rpid: {}, user_id: {})", rpid, user_id);
             println!();
 
-            update(device, &pin, &rpid, &util::to_str_hex(&user_id))?;
+            update(
+                device,
+                &pin,
+                &rpid,
+                &util::to_str_hex(&user_id),
+                &name,
+                &display_name,
+            )?;
         }
     }
     Ok(())
@@ -120,25 +153,48 @@ fn enumerate(device: &FidoKeyHid, pin: &str) -> Result<()> {
     Ok(())
 }
 
-fn delete(device: &FidoKeyHid, pin: &str, rpid: &str, user_id: &[u8]) -> Result<()> {
-    if let Some(cred) = memo::search_cred(device, pin, rpid, user_id)? {
-        device.credential_management_delete_credential(
-            Some(pin),
-            cred.public_key_credential_descriptor,
-        )?;
-        println!("Delete Success!");
-    } else {
+fn delete(
+    device: &FidoKeyHid,
+    pin: &str,
+    selector: &CredentialSelector,
+    dry_run: bool,
+) -> Result<()> {
+    let deleted = device.credential_management_delete_credentials(Some(pin), selector, dry_run)?;
+    if deleted.is_empty() {
         println!("Credential not found...");
+        return Ok(());
+    }
+
+    for (rp, cred) in &deleted {
+        println!(
+            "- credential: (rpid: {}, user_id: {}, name: {})",
+            rp.public_key_credential_rp_entity.id,
+            util::to_hex_str(&cred.public_key_credential_user_entity.id),
+            cred.public_key_credential_user_entity.name
+        );
+    }
+
+    if dry_run {
+        println!("{} credential(s) would be deleted.", deleted.len());
+    } else {
+        println!("Delete Success! ({} credential(s))", deleted.len());
     }
     Ok(())
 }
 
-fn update(device: &FidoKeyHid, pin: &str, rpid: &str, user_id: &[u8]) -> Result<()> {
+fn update(
+    device: &FidoKeyHid,
+    pin: &str,
+    rpid: &str,
+    user_id: &[u8],
+    name: &str,
+    display_name: &str,
+) -> Result<()> {
     if let Some(cred) = memo::search_cred(device, pin, rpid, user_id)? {
         let pkcue = PublicKeyCredentialUserEntity {
             id: user_id.to_vec(),
-            name: "test-name".to_string(),
-            display_name: "test-display".to_string(),
+            name: name.to_string(),
+            display_name: display_name.to_string(),
         };
 
         device.credential_management_update_user_information(
@@ -43,6 +43,13 @@ struct AppArg {
     #[clap(short = 'u', long = "user-presence", help = "User Presence Test.")]
     user_presence: bool,
 
+    #[clap(
+        long = "ping",
+        value_name = "LEN",
+        help = "Send CTAPHID_PING with a LEN byte payload 10 times and show the round-trip statistics."
+    )]
+    ping: Option<usize>,
+
     #[clap(subcommand)]
     action: Option<Action>,
 }
@@ -157,14 +164,20 @@ enum Action {
         #[clap(
             short = 'd',
             long = "delete",
-            help = "Delete a discoverable credential."
+            help = "Delete discoverable credentials that match --rpid, --userid and --user-name."
         )]
         delete: bool,
 
+        #[clap(
+            long = "dry-run",
+            help = "List the credentials to be deleted without deleting them."
+        )]
+        dry_run: bool,
+
         #[clap(
             short = 'u',
             long = "update",
-            help = "[Always an error]Update a discoverable credential user info."
+            help = "Update a discoverable credential user info (CTAP 2.1)."
         )]
         update: bool,
 
@@ -182,6 +195,19 @@ enum Action {
         )]
         userid: Option<String>,
 
+        #[clap(
+            long = "user-name",
+            takes_value = true,
+            help = "Pattern of the user name to be deleted (* and ? wildcards)."
+        )]
+        user_name: Option<String>,
+
+        #[clap(long = "name", takes_value = true, help = "New user name.")]
+        name: Option<String>,
+
+        #[clap(long = "display-name", takes_value = true, help = "New user display name.")]
+        display_name: Option<String>,
+
         #[clap(short = 'p')]
         pin: Option<String>,
     },
@@ -234,8 +260,6 @@ fn main() -> Result<()> {
 
     let mut cfg = Cfg::init();
     cfg.enable_log = false;
-    cfg.use_pre_bio_enrollment = true;
-    cfg.use_pre_credential_management = true;
 
     if arg.device {
         println!("Enumerate HID devices.");
@@ -272,6 +296,11 @@ fn main() -> Result<()> {
         println!("Do you see that wink? ;-)\n");
     }
 
+    if let Some(len) = arg.ping {
+        println!("Ping.\n");
+        println!("{}", device.ping_stats(len, 10)?);
+    }
+
     if let Some(action) = arg.action {
         match action {
             Action::Info { item, list } => {
@@ -332,8 +361,8 @@ fn main() -> Result<()> {
                     bio::Command::Info
                 } else if enroll {
                     bio::Command::Enroll
-                } else if let Some(..) = delete_template_id {
-                    bio::Command::Del(delete_template_id.unwrap())
+                } else if let Some(template_id) = delete_template_id {
+                    bio::Command::Del(template_id)
                 } else if test {
                     bio::Command::Test(false)
                 } else if test_with_log {
@@ -348,17 +377,31 @@ fn main() -> Result<()> {
                 list: _,
                 metadata,
                 delete,
+                dry_run,
                 update,
                 rpid,
                 userid,
+                user_name,
+                name,
+                display_name,
                 pin,
             } => {
                 let command = if metadata {
                     cred::Command::Metadata
                 } else if delete {
-                    cred::Command::Del((rpid.unwrap_or_default(), userid.unwrap_or_default()))
+                    cred::Command::Del {
+                        rpid,
+                        user_id: userid,
+                        user_name,
+                        dry_run,
+                    }
                 } else if update {
-                    cred::Command::Update((rpid.unwrap_or_default(), userid.unwrap_or_default()))
+                    cred::Command::Update {
+                        rpid: rpid.unwrap_or_default(),
+                        user_id: userid.unwrap_or_default(),
+                        name: name.unwrap_or_default(),
+                        display_name: display_name.unwrap_or_default(),
+                    }
                 } else {
                     cred::Command::List
                 };
@@ -374,18 +417,14 @@ fn main() -> Result<()> {
             } => {
                 if toggle_always_uv {
                     cfg::config(&device, cfg::Command::ToggleAlwaysUv, pin)?;
-                } else if new_min_pin_length.is_some() {
-                    cfg::config(
-                        &device,
-                        cfg::Command::SetMinPINLength(new_min_pin_length.unwrap()),
-                        pin,
-                    )?;
-                } else if rpids.is_some() {
+                } else if let Some(new_min_pin_length) = new_min_pin_length {
                     cfg::config(
                         &device,
-                        cfg::Command::SetMinPinLengthRPIDs(rpids.unwrap()),
+                        cfg::Command::SetMinPINLength(new_min_pin_length),
                         pin,
                     )?;
+                } else if let Some(rpids) = rpids {
+                    cfg::config(&device, cfg::Command::SetMinPinLengthRPIDs(rpids), pin)?;
                 } else if force_change_pin {
                     cfg::config(&device, cfg::Command::ForceChangePin, pin)?;
                 }
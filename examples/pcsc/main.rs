@@ -1,6 +1,5 @@
-use std::collections::BTreeMap;
-
-use ctap_hid_fido2::pcsc::{get_fido_pcsc_devices, Fido2Session, send_command, SELECT_FIDO2_APDU};
+use ctap_hid_fido2::pcsc::{get_fido_pcsc_devices, select, Fido2Session, send_command};
+use ctap_hid_fido2::quirks::QuirkTable;
 use anyhow::Result;
 use serde_cbor::Value;
 
@@ -12,7 +11,7 @@ fn main() -> Result<()> {
     let mut card = sess.card()?;
     let tx = sess.new_transaction(&mut card)?;
 
-    let resp = send_command(&tx, SELECT_FIDO2_APDU)?;
+    let resp = select(&tx, &QuirkTable::builtin())?;
     println!("resp: {:02x?}", &resp[0..8]);
     let resp = send_command(&tx, &[0x80, 0x10, 0x00, 0x00, 0x01, 0x04])?;
     // let resp = send_command(&tx, &[0x00, 0x04, 0x00, 0x00])?;
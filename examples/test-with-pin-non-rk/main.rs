@@ -276,8 +276,8 @@ fn with_hmac(device: &FidoKeyHid, rpid: &str, pin: &str) -> Result<()> {
     let find = assertions[0]
         .extensions
         .iter()
-        .find(|it| matches!(it, Gext::HmacSecret(_)));
-    if let Some(Gext::HmacSecret(hmac_secret)) = find {
+        .find(|it| matches!(it, Gext::HmacSecret(_, _)));
+    if let Some(Gext::HmacSecret(hmac_secret, _)) = find {
         println!(
             "--- HMAC Secret = {}",
             util::to_hex_str(&hmac_secret.unwrap())
@@ -411,11 +411,13 @@ fn with_large_blob_key(device: &FidoKeyHid, rpid: &str, pin: &str) -> Result<()>
         .extensions
         .iter()
         .find(|it| matches!(it, Gext::LargeBlobKey((_, _))));
-    if let Some(Gext::LargeBlobKey((_, large_blob_key))) = find {
-        println!(
-            "--- Large Blob Key = {}",
-            util::to_hex_str(&large_blob_key.clone().unwrap())
-        );
+    if let Some(Gext::LargeBlobKey((_, Some(large_blob_key)))) = find {
+        println!("--- Large Blob Key = {}", util::to_hex_str(large_blob_key));
+
+        println!("-- Write Large Blob");
+        device.write_large_blob_with_key(Some(pin), large_blob_key, b"large blob data")?;
+        let large_blob = device.get_large_blob_with_key(large_blob_key)?;
+        println!("--- Large Blob = {:?}", large_blob.map(String::from_utf8));
     } else {
         println!("--- Large Blob Key Not Found");
     }
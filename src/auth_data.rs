@@ -1,6 +1,7 @@
 use crate::str_buf::StrBuf;
 use std::fmt;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct Flags {
     pub user_present_result: bool,
@@ -90,8 +90,6 @@ pub fn get_ctap_status_message(status: u8) -> String {
         0xEF => "0xEF CTAP2_ERR_EXTENSION_LAST Extension specific error.".to_string(),
         0xF0 => "0xF0 CTAP2_ERR_VENDOR_FIRST Vendor specific error.".to_string(),
         0xff => "0xFF CTAP2_ERR_VENDOR_LAST   Vendor specific error.".to_string(),
-        // CTAP仕様にない、謎のステータス
-        0x6A => "0x6A BioPass UnKnown Error.".to_string(),
         _ => format!("0x{status:02X}"),
     }
 }
@@ -1,90 +1,274 @@
-use crate::{ctapdef, fidokey::{FidoKeyHid, FidoKey}, util};
+use crate::{ctapdef, error::{CtapHidError, HidErrorCode}, fidokey::{FidoKeyHid, FidoKey}, str_buf::StrBuf, util};
+use crate::ctaphid_frame::{self, Received, ResponseAssembler, CTAP_FRAME_INIT, CTAPHID_KEEPALIVE};
 use anyhow::{anyhow, Error, Result};
-use std::{thread, time};
+use ring::{rand, rand::SecureRandom};
+use std::fmt;
+use std::time::{Duration, Instant};
 
 //pub const USAGE_PAGE_FIDO: u16 = 0xf1d0;
 
-pub const CTAP_FRAME_INIT: u8 = 0x80;
-pub const PACKET_SIZE: usize = 1 + 64;
-const PAYLOAD_SIZE_AN_INITIALIZATION_PACKET: usize = 64 - 7;
-const PAYLOAD_SIZE_A_CONTINUATION_PACKET: usize = 64 - 5;
-
 // CTAPHID Command
+const CTAPHID_PING: u8 = CTAP_FRAME_INIT | 0x01;
 const CTAPHID_MSG: u8 = CTAP_FRAME_INIT | 0x03;
+const CTAPHID_LOCK: u8 = CTAP_FRAME_INIT | 0x04;
 const CTAPHID_INIT: u8 = CTAP_FRAME_INIT | 0x06;
 const CTAPHID_WINK: u8 = CTAP_FRAME_INIT | 0x08;
 const CTAPHID_CBOR: u8 = CTAP_FRAME_INIT | 0x10;
+const CTAPHID_CANCEL: u8 = CTAP_FRAME_INIT | 0x11;
 //This command code is used in response messages only.
 const CTAPHID_ERROR: u8 = CTAP_FRAME_INIT | 0x3F;
-const CTAPHID_KEEPALIVE: u8 = CTAP_FRAME_INIT | 0x3B;
 
-//const CTAPHID_KEEPALIVE_STATUS_PROCESSING = 1;     // The authenticator is still processing the current request.
-//const CTAPHID_KEEPALIVE_STATUS_UPNEEDED = 2;       // The authenticator is waiting for user presence.
+// The authenticator is still processing the current request.
+const CTAPHID_KEEPALIVE_STATUS_PROCESSING: u8 = 1;
+// The authenticator is waiting for user presence.
+const CTAPHID_KEEPALIVE_STATUS_UPNEEDED: u8 = 2;
+
+const BROADCAST_CID: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+
+// CTAPHID_ERROR(CHANNEL_BUSY): another channel is using the authenticator.
+// Retry with exponential backoff (20ms, 40ms, ... 1s) and jitter.
+const CHANNEL_BUSY_RETRIES: u32 = 10;
+const CHANNEL_BUSY_BACKOFF_MIN: Duration = Duration::from_millis(20);
+const CHANNEL_BUSY_BACKOFF_MAX: Duration = Duration::from_millis(1000);
+
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// CTAPHID_INIT capability flags
+const CAPABILITY_WINK: u8 = 0x01;
+const CAPABILITY_CBOR: u8 = 0x04;
+const CAPABILITY_NMSG: u8 = 0x08;
+
+/// CTAPHID_INIT response
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HidDeviceCapabilities {
+    /// CTAPHID protocol version identifier
+    pub protocol_version: u8,
+    pub major_device_version: u8,
+    pub minor_device_version: u8,
+    pub build_device_version: u8,
+    /// Capabilities flags
+    pub capabilities: u8,
+    /// CAPABILITY_WINK: implements CTAPHID_WINK
+    pub wink: bool,
+    /// CAPABILITY_CBOR: implements CTAPHID_CBOR
+    pub cbor: bool,
+    /// CAPABILITY_NMSG: does not implement CTAPHID_MSG
+    pub nmsg: bool,
+}
+
+impl fmt::Display for HidDeviceCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut strbuf = StrBuf::new(24);
+        strbuf
+            .append("- protocol_version", &self.protocol_version)
+            .append(
+                "- device_version",
+                &format!(
+                    "{}.{}.{}",
+                    self.major_device_version, self.minor_device_version, self.build_device_version
+                ),
+            )
+            .append("- capabilities", &format!("0x{:02X}", self.capabilities))
+            .append("- wink", &self.wink)
+            .append("- cbor", &self.cbor)
+            .append("- nmsg", &self.nmsg);
+        write!(f, "{}", strbuf.build())
+    }
+}
 
 pub fn ctaphid_init(device: &FidoKeyHid) -> Result<[u8; 4]> {
-    // CTAPHID_INIT
-    let mut cmd: [u8; 65] = [0; 65];
+    // while the channel is locked, keep using the locked channel
+    if let Some(cid) = device.locked_channel()? {
+        return Ok(cid);
+    }
+    ctaphid_init_with_capabilities(device).map(|(cid, _)| cid)
+}
 
-    // Report ID
-    cmd[0] = 0x00;
+pub fn ctaphid_init_with_capabilities(
+    device: &FidoKeyHid,
+) -> Result<([u8; 4], HidDeviceCapabilities)> {
+    // nonce
+    let mut nonce = [0u8; 8];
+    rand::SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("Failed to create nonce"))?;
 
-    // cid-dmy
-    cmd[1] = 0xff;
-    cmd[2] = 0xff;
-    cmd[3] = 0xff;
-    cmd[4] = 0xff;
+    // CTAPHID_INIT on the broadcast channel
+    let cmd = ctaphid_frame::create_packets(&BROADCAST_CID, CTAPHID_INIT, &nonce)?.remove(0);
 
-    // command
-    cmd[5] = CTAPHID_INIT;
+    //println!("CTAPHID_INIT = {}", util::to_hex_str(&cmd));
 
-    // len
-    cmd[6] = 0x00;
-    cmd[7] = 0x08;
+    device.write(&cmd).map_err(|e| io_error(device, e))?;
 
-    // nonce
-    cmd[8] = 0xfc;
-    cmd[9] = 0x8c;
-    cmd[10] = 0xc9;
-    cmd[11] = 0x91;
-    cmd[12] = 0x14;
-    cmd[13] = 0xb5;
-    cmd[14] = 0x3b;
-    cmd[15] = 0x12;
+    // Other clients may be using the broadcast channel,
+    // so wait for the response with our nonce.
+    let deadline = deadline(device);
+    loop {
+        let buf = read_packet(device, deadline)?
+            .ok_or_else(|| CtapHidError::Timeout(device.timeout().unwrap_or_default()))?;
 
-    //println!("CTAPHID_INIT = {}", util::to_hex_str(&cmd));
+        if let Some(res) = parse_init_response(&buf, &nonce) {
+            return Ok(res);
+        }
+    }
+}
 
-    device.write(&cmd).map_err(Error::msg)?;
-    let buf = device.read().map_err(Error::msg)?;
+// CTAPHID_INIT response
+// - CID(4) = broadcast CID
+// - CMD(1) = CTAPHID_INIT
+// - BCNT(2) >= 17
+// - DATA
+//   - nonce(8)
+//   - CID(4)
+//   - CTAPHID protocol version(1)
+//   - Major device version number(1)
+//   - Minor device version number(1)
+//   - Build device version number(1)
+//   - Capabilities flags(1)
+fn parse_init_response(
+    packet: &[u8],
+    nonce: &[u8; 8],
+) -> Option<([u8; 4], HidDeviceCapabilities)> {
+    if packet.len() < 24 || packet[0..4] != BROADCAST_CID || packet[4] != CTAPHID_INIT {
+        return None;
+    }
+
+    let len = (usize::from(packet[5]) << 8) + usize::from(packet[6]);
+    if len < 17 || packet[7..15] != nonce[..] {
+        return None;
+    }
 
-    // CID
-    Ok([buf[15], buf[16], buf[17], buf[18]])
+    let cid = [packet[15], packet[16], packet[17], packet[18]];
+    let capabilities = packet[23];
+    let caps = HidDeviceCapabilities {
+        protocol_version: packet[19],
+        major_device_version: packet[20],
+        minor_device_version: packet[21],
+        build_device_version: packet[22],
+        capabilities,
+        wink: capabilities & CAPABILITY_WINK != 0,
+        cbor: capabilities & CAPABILITY_CBOR != 0,
+        nmsg: capabilities & CAPABILITY_NMSG != 0,
+    };
+    Some((cid, caps))
 }
 
-fn get_response_status(packet: &[u8]) -> Result<(u8, u16, u8)> {
-    // cid
-    //println!("- cid: {:?}", &packet[0..4]);
-    // cmd
-    //println!("- cmd: 0x{:2X}", packet[4]);
+fn deadline(device: &FidoKeyHid) -> Option<Instant> {
+    device.timeout().map(|timeout| Instant::now() + timeout)
+}
 
-    let command = packet[4];
+// Timeout of the request (see `Timeouts`)
+fn request_timeout(device: &FidoKeyHid, command: u8, payload: &[u8]) -> Option<Duration> {
+    let timeouts = device.timeouts();
+    match payload.first() {
+        Some(ctap_command) if command == CTAPHID_CBOR => timeouts.for_command(*ctap_command),
+        _ => timeouts.default,
+    }
+}
 
-    // response size
-    let payload_size = (u16::from(packet[5]) << 8) + u16::from(packet[6]);
+// Read a packet, waiting until the deadline (None: forever).
+// Ok(None) if the deadline has passed.
+fn read_packet(device: &FidoKeyHid, deadline: Option<Instant>) -> Result<Option<Vec<u8>>> {
+    let res = match deadline {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_millis(0) {
+                return Ok(None);
+            }
+            device.read_timeout(remaining)
+        }
+        None => device.read().map(Some),
+    };
+
+    res.map_err(|_| {
+        io_error(
+            device,
+            format!("read err = {}", ctapdef::get_ctap_status_message(0xfe)),
+        )
+    })
+}
+
+// I/O error, or DeviceDisconnected if the device has been removed
+fn io_error(device: &FidoKeyHid, message: String) -> Error {
+    if device.is_connected() {
+        anyhow!(message)
+    } else {
+        CtapHidError::DeviceDisconnected.into()
+    }
+}
+
+// Read a response packet of the request on cid.
+// On timeout or cancellation, the request is cancelled with CTAPHID_CANCEL.
+fn read_response_packet(
+    device: &FidoKeyHid,
+    cid: &[u8],
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+) -> Result<Vec<u8>> {
+    loop {
+        // wake up periodically to check for cancellation
+        let poll = device
+            .cancel_requested()
+            .map(|_| Instant::now() + CANCEL_POLL_INTERVAL);
+        let until = match (deadline, poll) {
+            (Some(deadline), Some(poll)) => Some(deadline.min(poll)),
+            (deadline, None) => deadline,
+            (None, poll) => poll,
+        };
+
+        if let Some(buf) = read_packet(device, until)? {
+            return Ok(buf);
+        }
+
+        if device.cancel_requested() == Some(true) {
+            ctaphid_cancel(device, cid);
+            return Err(CtapHidError::Cancelled.into());
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            ctaphid_cancel(device, cid);
+            return Err(CtapHidError::Timeout(timeout.unwrap_or_default()).into());
+        }
+    }
+}
 
+// CTAPHID_CANCEL
+// The authenticator answers the pending request with CTAP2_ERR_KEEPALIVE_CANCEL, which is discarded.
+fn ctaphid_cancel(device: &FidoKeyHid, cid: &[u8]) {
+    let cmd = match ctaphid_frame::create_packets(cid, CTAPHID_CANCEL, &[]) {
+        Ok(mut packets) => packets.remove(0),
+        Err(_) => return,
+    };
+    if device.write(&cmd).is_err() {
+        return;
+    }
+
+    let deadline = Some(Instant::now() + Duration::from_millis(500));
+    while let Ok(Some(buf)) = read_packet(device, deadline) {
+        if buf[4] != CTAPHID_KEEPALIVE {
+            break;
+        }
+    }
+}
+
+// (command, data size, status)
+fn get_response_status(command: u8, data: &[u8]) -> Result<(u8, u16, u8)> {
     // status
     let response_status = if command == CTAPHID_MSG {
-        // length check ()
-        if payload_size as usize > packet.len() {
+        // U2F(SW1 = second to last byte of data)
+        if data.len() < 2 {
             return Err(anyhow!("u2f response size error?"));
         }
-        // U2F(last byte of data)
-        packet[(4 + 2 + payload_size - 1) as usize]
+        data[data.len() - 2]
     } else {
         // CTAP(first byte of data)
-        packet[7]
+        *data
+            .first()
+            .ok_or_else(|| anyhow!("{}", ctapdef::get_ctap_status_message(0x03)))?
     };
 
-    Ok((command, payload_size, response_status))
+    Ok((command, data.len() as u16, response_status))
 }
 
 const fn is_response_error(status: (u8, u16, u8)) -> bool {
@@ -103,139 +287,131 @@ fn get_status_message(status: (u8, u16, u8)) -> String {
     }
 }
 
-fn get_data(status: (u8, u16, u8), payload: &[u8]) -> Vec<u8> {
-    let statindex = u16::from(status.0 != CTAPHID_MSG);
-
-    // data size
-    let datasize = if status.0 == CTAPHID_MSG {
+fn get_data(status: (u8, u16, u8), data: &[u8]) -> Vec<u8> {
+    if status.0 == CTAPHID_MSG {
         // remove SW1 , SW2
-        status.1 - 2
+        data[..data.len() - 2].to_vec()
     } else {
-        status.1
-    };
-
-    // get CBOR
-    let mut data: Vec<u8> = vec![];
-    for n in statindex..datasize {
-        let index: usize = n.into();
-        let dat = payload[index];
-        data.push(dat);
+        // remove status
+        data[1..].to_vec()
     }
-    data
-}
-
-fn ctaphid_cbor_response_get_payload_1(packet: &[u8]) -> Vec<u8> {
-    packet[7..64].to_vec()
 }
 
-fn ctaphid_cbor_response_get_payload_2(packet: &[u8]) -> Vec<u8> {
-    packet[5..64].to_vec()
-}
-
-fn create_initialization_packet(cid: &[u8], commoand: u8, payload: &[u8]) -> (Vec<u8>, bool) {
-    let mut cmd: Vec<u8> = vec![0; PACKET_SIZE];
-
-    // Report ID
-    // The first byte of data must contain the Report ID.
-    // For devices which only support a single report, this must be set to 0x0.
-    cmd[0] = 0x00;
-
-    // cid
-    cmd[1] = cid[0];
-    cmd[2] = cid[1];
-    cmd[3] = cid[2];
-    cmd[4] = cid[3];
-
-    // Command identifier (bit 7 always set)
-    // ex. CTAP_FRAME_INIT(0x80) | CTAPHID_CBOR (0x10)
-    cmd[5] = commoand;
-
-    // High part of payload length
-    cmd[6] = ((payload.len() as u16) >> 8) as u8;
-    // Low part of payload length
-    cmd[7] = payload.len() as u8;
+pub fn ctaphid_wink(device: &FidoKeyHid, cid: &[u8]) -> Result<()> {
+    // CTAPHID_WINK: the authenticator responds with an empty CTAPHID_WINK
+    if device.enable_log {
+        println!("- wink");
+    }
 
-    // payload
-    let mut size = payload.len();
-    let next = if payload.len() > PAYLOAD_SIZE_AN_INITIALIZATION_PACKET {
-        size = PAYLOAD_SIZE_AN_INITIALIZATION_PACKET;
-        true
-    } else {
-        false
-    };
+    let data = ctaphid_request(device, cid, CTAPHID_WINK, &[])?;
 
-    cmd[8..(size + 8)].clone_from_slice(&payload[..size]);
+    if device.enable_log {
+        println!(
+            "- response wink({:02})    = {:?}",
+            data.len(),
+            util::to_hex_str(&data)
+        );
+    }
 
-    (cmd, next)
+    Ok(())
 }
 
-fn create_continuation_packet(seqno: u8, cid: &[u8], payload: &[u8]) -> (Vec<u8>, bool) {
-    let mut cmd: Vec<u8> = vec![0; PACKET_SIZE];
-
-    // Report ID
-    cmd[0] = 0x00;
-
-    // cid
-    cmd[1] = cid[0];
-    cmd[2] = cid[1];
-    cmd[3] = cid[2];
-    cmd[4] = cid[3];
-
-    // seq
-    cmd[5] = seqno;
-
-    let index: usize =
-        PAYLOAD_SIZE_AN_INITIALIZATION_PACKET + PAYLOAD_SIZE_A_CONTINUATION_PACKET * seqno as usize;
-
-    // payload
-    let mut size: usize = payload.len() - index;
-    let next = if size > PAYLOAD_SIZE_A_CONTINUATION_PACKET {
-        size = PAYLOAD_SIZE_A_CONTINUATION_PACKET;
-        true
-    } else {
-        false
-    };
+// Send a request and return the response data.
+// CTAPHID_ERROR(CHANNEL_BUSY) is retried with backoff.
+fn ctaphid_request(
+    device: &FidoKeyHid,
+    cid: &[u8],
+    command: u8,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    let mut backoff = CHANNEL_BUSY_BACKOFF_MIN;
+    let mut retries = 0;
+    loop {
+        let res = ctaphid_transaction(device, cid, command, payload);
+
+        let busy = matches!(
+            res.as_ref().err().and_then(|e| e.downcast_ref::<CtapHidError>()),
+            Some(CtapHidError::Hid(HidErrorCode::ChannelBusy))
+        );
+        if !busy || retries >= CHANNEL_BUSY_RETRIES {
+            return res;
+        }
+        retries += 1;
 
-    cmd[6..(size + 6)].clone_from_slice(&payload[index..(size + index)]);
-    (cmd, next)
+        if device.enable_log {
+            println!("- channel busy, retry({}) after {:?}", retries, backoff);
+        }
+        std::thread::sleep(backoff + jitter(backoff));
+        backoff = (backoff * 2).min(CHANNEL_BUSY_BACKOFF_MAX);
+    }
 }
 
-pub fn ctaphid_wink(device: &FidoKeyHid, cid: &[u8]) -> Result<()> {
-    // CTAPHID_WINK
-    let mut cmd: [u8; 65] = [0; 65];
-
-    // Report ID
-    cmd[0] = 0x00;
+// random 0..backoff/2
+fn jitter(backoff: Duration) -> Duration {
+    let mut r = [0u8; 2];
+    if rand::SystemRandom::new().fill(&mut r).is_err() {
+        return Duration::from_millis(0);
+    }
+    backoff * u32::from(u16::from_le_bytes(r)) / (2 * 0x10000)
+}
 
-    // cid-dmy
-    cmd[1] = cid[0];
-    cmd[2] = cid[1];
-    cmd[3] = cid[2];
-    cmd[4] = cid[3];
+fn ctaphid_transaction(
+    device: &FidoKeyHid,
+    cid: &[u8],
+    command: u8,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    // initialization packet + continuation packets
+    for packet in ctaphid_frame::create_packets(cid, command, payload)? {
+        device.write(&packet).map_err(|e| io_error(device, e))?;
+    }
 
-    // command
-    cmd[5] = CTAPHID_WINK;
+    let timeout = request_timeout(device, command, payload);
+    let mut deadline = timeout.map(|timeout| Instant::now() + timeout);
 
-    // len
-    cmd[6] = 0x00;
-    cmd[7] = 0x00;
+    // read
+    // keepalive packets arrive while the authenticator is processing, so wait in read
+    let mut keep_alive_msg_flag = false;
+    let mut assembler = ResponseAssembler::new(cid);
+    loop {
+        let buf = read_response_packet(device, cid, timeout, deadline)?;
+
+        match assembler.push(&buf)? {
+            Received::Complete => break,
+            Received::KeepAlive(status) => {
+                // the authenticator is alive, extend the deadline
+                if status == CTAPHID_KEEPALIVE_STATUS_PROCESSING
+                    || status == CTAPHID_KEEPALIVE_STATUS_UPNEEDED
+                {
+                    deadline = timeout.map(|timeout| Instant::now() + timeout);
+                }
 
-    if device.enable_log {
-        println!("- wink({:02})    = {:?}", cmd.len(), util::to_hex_str(&cmd));
+                if !keep_alive_msg_flag {
+                    if !device.keep_alive_msg.is_empty() {
+                        println!("{}", device.keep_alive_msg);
+                    }
+                    keep_alive_msg_flag = true;
+                }
+            }
+            Received::Ignored | Received::Incomplete => {}
+        }
     }
 
-    device.write(&cmd).map_err(Error::msg)?;
-
-    if device.enable_log {
-        let buf = device.read().map_err(Error::msg)?;
-        println!(
-            "- response wink({:02})    = {:?}",
-            buf.len(),
-            util::to_hex_str(&buf)
-        );
+    let (response_command, data) = assembler
+        .finish()
+        .ok_or_else(|| anyhow!("{}", ctapdef::get_ctap_status_message(0x03)))?;
+
+    if response_command == CTAPHID_ERROR {
+        let code = data.first().copied().unwrap_or_default();
+        return Err(CtapHidError::Hid(HidErrorCode::from_code(code)).into());
+    } else if response_command != command {
+        return Err(anyhow!(
+            "unexpected response command 0x{:02x}",
+            response_command
+        ));
     }
 
-    Ok(())
+    Ok(data)
 }
 
 fn ctaphid_cbormsg(
@@ -251,106 +427,28 @@ fn ctaphid_cbormsg(
         println!("--");
     }
 
-    // initialization_packet
-    let res = create_initialization_packet(cid, command, payload);
-    //println!("CTAPHID_CBOR(0) = {}", util::to_hex_str(&res.0));
-
-    // Write data to device
-    let _res = device.write(&res.0).map_err(Error::msg)?;
-    //println!("Wrote: {:?} byte", res);
-
-    // next
-    if res.1 {
-        for seqno in 0..100 {
-            let res = create_continuation_packet(seqno, cid, payload);
-            //println!("CTAPHID_CBOR(1) = {}", util::to_hex_str(&res.0));
-            let _res = device.write(&res.0).map_err(Error::msg)?;
-            if !res.1 {
-                break;
-            }
-        }
-    }
-
-    // read - 1st packet
-    let mut keep_alive_msg_flag = false;
-    let mut st: (u8, u16, u8) = (0, 0, 0);
-    let mut packet_1st = vec![];
-    for _counter in 0..500 {
-        // println!("counter: {:?}", _counter);
-
-        let buf = match device.read() {
-            Ok(res) => res,
-            Err(_error) => {
-                let msg = format!("read err = {}", ctapdef::get_ctap_status_message(0xfe));
-                return Err(anyhow!(msg));
-            }
-        };
-        //println!("Read: {:?} byte", res);
+    let data = ctaphid_request(device, cid, command, payload)?;
 
-        if command != CTAPHID_CBOR && command != CTAPHID_MSG {
-            return Ok(buf);
-        }
+    let st = get_response_status(command, &data)?;
 
-        st = get_response_status(&buf)?;
-        if st.0 == CTAPHID_CBOR || st.0 == CTAPHID_MSG {
-            packet_1st = buf;
-            break;
-        } else if st.0 == CTAPHID_KEEPALIVE {
-            if !keep_alive_msg_flag {
-                if !device.keep_alive_msg.is_empty() {
-                    println!("{}", device.keep_alive_msg);
-                }
-                keep_alive_msg_flag = true;
-            }
-            thread::sleep(time::Duration::from_millis(100));
-        } else if st.0 == CTAPHID_ERROR {
-            println!("CTAPHID_ERROR Error code = 0x{:02x}", st.2);
-            break;
-        } else {
-            println!("err");
-            break;
-        }
-    }
-
-    //println!("payload_size = {:?} byte", payload_size);
+    //println!("payload_size = {:?} byte", st.1);
     //println!("response_status = 0x{:02X}", st.2);
 
     if is_response_error(st) {
-        Err(anyhow!(format!(
-            "response_status err = {}",
-            get_status_message(st)
-        )))
-    } else {
-        let mut payload = ctaphid_cbor_response_get_payload_1(&packet_1st);
-
-        // Is Exists Next Packet?
-        let payload_size = st.1;
-        if (payload.len() as u16) < payload_size {
-            for _ in 1..=100 {
-                // read next packet
-                let buf = match device.read() {
-                    Ok(res) => res,
-                    Err(_error) => {
-                        let msg = format!("read err = {}", ctapdef::get_ctap_status_message(0xfe));
-                        return Err(anyhow!(msg));
-                    }
-                };
-                //println!("Read: {:?} byte", &buf[..res]);
-
-                let mut p2 = ctaphid_cbor_response_get_payload_2(&buf);
-
-                // payloadに連結
-                payload.append(&mut p2);
-
-                // 次のパケットがある?
-                if payload.len() >= payload_size as usize {
-                    break;
-                }
-            }
+        // vendor specific status (see `Quirks::status_messages`)
+        let message = match device.quirks().status_message(st.2) {
+            Some(message) if st.0 != CTAPHID_MSG => message.to_string(),
+            _ => get_status_message(st),
+        };
+        let message = format!("response_status err = {message}");
+        if st.0 == CTAPHID_MSG {
+            Err(anyhow!(message))
+        } else {
+            Err(Error::new(CtapHidError::Status(st.2)).context(message))
         }
-
+    } else {
         // get data
-        let data = get_data(st, &payload);
+        let data = get_data(st, &data);
 
         if device.enable_log {
             println!();
@@ -363,6 +461,17 @@ fn ctaphid_cbormsg(
     }
 }
 
+// CTAPHID_PING: the authenticator echoes the payload
+pub fn ctaphid_ping(device: &FidoKeyHid, cid: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    ctaphid_request(device, cid, CTAPHID_PING, payload)
+}
+
+// CTAPHID_LOCK
+// seconds: 1-10 locks the authenticator to cid, 0 releases the lock
+pub fn ctaphid_lock(device: &FidoKeyHid, cid: &[u8], seconds: u8) -> Result<()> {
+    ctaphid_request(device, cid, CTAPHID_LOCK, &[seconds]).map(|_| ())
+}
+
 pub fn ctaphid_cbor(device: &FidoKeyHid, cid: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
     ctaphid_cbormsg(device, cid, CTAPHID_CBOR, payload)
 }
@@ -394,6 +503,32 @@ pub fn send_apdu(
     :raise: ApduError
     */
 
+    let apdu = create_apdu(cla, ins, p1, p2, data);
+    ctaphid_msg(device, cid, &apdu)
+}
+
+// Send an APDU and return the response data and the status word (SW1 SW2)
+// without treating a status other than 0x9000 as an error.
+pub fn send_apdu_with_status(
+    device: &FidoKeyHid,
+    cid: &[u8],
+    cla: u8,
+    ins: u8,
+    p1: u8,
+    p2: u8,
+    data: Option<&[u8]>,
+) -> Result<(Vec<u8>, u16)> {
+    let apdu = create_apdu(cla, ins, p1, p2, data);
+    let response = ctaphid_request(device, cid, CTAPHID_MSG, &apdu)?;
+    if response.len() < 2 {
+        return Err(anyhow!("u2f response size error?"));
+    }
+
+    let (data, sw) = response.split_at(response.len() - 2);
+    Ok((data.to_vec(), (u16::from(sw[0]) << 8) + u16::from(sw[1])))
+}
+
+fn create_apdu(cla: u8, ins: u8, p1: u8, p2: u8, data: Option<&[u8]>) -> Vec<u8> {
     let data_length = data.map(<[u8]>::len).unwrap_or_default();
     let mut apdu: Vec<u8> = vec![0; 7 + data_length];
     // reserved
@@ -417,7 +552,7 @@ pub fn send_apdu(
         apdu[7..(data_length + 7)].clone_from_slice(&data[..data_length]);
     }
 
-    ctaphid_msg(device, cid, &apdu)
+    apdu
 }
 
 /*
@@ -444,3 +579,58 @@ https://github.com/Yubico/python-fido2/blob/master/fido2/ctap1.py#L214
             raise ApduError(status, data)
         return data
 */
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_init_response() {
+        let nonce = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        let mut packet = vec![0u8; 64];
+        packet[0..7].copy_from_slice(&[0xff, 0xff, 0xff, 0xff, CTAPHID_INIT, 0x00, 0x11]);
+        packet[7..15].copy_from_slice(&nonce);
+        packet[15..24].copy_from_slice(&[0x11, 0x22, 0x33, 0x44, 0x02, 0x05, 0x04, 0x03, 0x05]);
+
+        let (cid, caps) = parse_init_response(&packet, &nonce).unwrap();
+        assert_eq!(cid, [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(caps.protocol_version, 2);
+        assert_eq!(
+            (
+                caps.major_device_version,
+                caps.minor_device_version,
+                caps.build_device_version
+            ),
+            (5, 4, 3)
+        );
+        assert!(caps.wink && caps.cbor && !caps.nmsg);
+
+        // response to another client
+        let other_nonce = [0u8; 8];
+        assert!(parse_init_response(&packet, &other_nonce).is_none());
+
+        // not an INIT response
+        packet[4] = CTAPHID_KEEPALIVE;
+        assert!(parse_init_response(&packet, &nonce).is_none());
+    }
+
+    #[test]
+    fn test_jitter() {
+        let backoff = Duration::from_millis(100);
+        for _ in 0..10 {
+            assert!(jitter(backoff) <= backoff / 2);
+        }
+    }
+
+    #[test]
+    fn test_hid_error_code() {
+        for code in [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x0A, 0x0B, 0x7F] {
+            assert_eq!(HidErrorCode::from_code(code).code(), code);
+        }
+        assert_eq!(HidErrorCode::from_code(0x06), HidErrorCode::ChannelBusy);
+        assert_eq!(HidErrorCode::from_code(0x7F), HidErrorCode::Other(0x7F));
+    }
+}
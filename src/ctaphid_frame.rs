@@ -0,0 +1,255 @@
+/*!
+CTAPHID message framing
+
+A message is sent as an initialization packet followed by continuation packets.
+- initialization packet : CID(4) | CMD(1) | BCNT(2) | DATA(57)
+- continuation packet : CID(4) | SEQ(1) | DATA(59)
+
+https://fidoalliance.org/specs/fido-v2.1-ps-20210615/fido-client-to-authenticator-protocol-v2.1-ps-20210615.html#usb-message-and-packet-structure
+*/
+use crate::ctapdef;
+use anyhow::{anyhow, Result};
+
+pub const CTAP_FRAME_INIT: u8 = 0x80;
+pub const CTAPHID_KEEPALIVE: u8 = CTAP_FRAME_INIT | 0x3B;
+
+pub const HID_REPORT_SIZE: usize = 64;
+// Report ID + HID report
+pub const PACKET_SIZE: usize = 1 + HID_REPORT_SIZE;
+const INIT_DATA_SIZE: usize = HID_REPORT_SIZE - 7;
+const CONT_DATA_SIZE: usize = HID_REPORT_SIZE - 5;
+// SEQ 0..=127
+const MAX_CONTINUATION_PACKETS: usize = 128;
+/// Maximum message size: 57 + 128 * 59 = 7609 bytes
+pub const MAX_MESSAGE_SIZE: usize = INIT_DATA_SIZE + MAX_CONTINUATION_PACKETS * CONT_DATA_SIZE;
+
+fn invalid_length() -> anyhow::Error {
+    anyhow!("{}", ctapdef::get_ctap_status_message(0x03))
+}
+
+fn invalid_seq() -> anyhow::Error {
+    anyhow!("{}", ctapdef::get_ctap_status_message(0x04))
+}
+
+/// Split a request message into output reports (with Report ID 0x00)
+pub fn create_packets(cid: &[u8], command: u8, payload: &[u8]) -> Result<Vec<Vec<u8>>> {
+    if payload.len() > MAX_MESSAGE_SIZE {
+        return Err(invalid_length());
+    }
+
+    let (init_data, cont_data) = payload.split_at(payload.len().min(INIT_DATA_SIZE));
+
+    // initialization packet
+    let mut packet = vec![0; PACKET_SIZE];
+    packet[1..5].copy_from_slice(cid);
+    packet[5] = command;
+    packet[6] = (payload.len() >> 8) as u8;
+    packet[7] = payload.len() as u8;
+    packet[8..(8 + init_data.len())].copy_from_slice(init_data);
+
+    let mut packets = vec![packet];
+
+    // continuation packets
+    for (seq, data) in cont_data.chunks(CONT_DATA_SIZE).enumerate() {
+        let mut packet = vec![0; PACKET_SIZE];
+        packet[1..5].copy_from_slice(cid);
+        packet[5] = seq as u8;
+        packet[6..(6 + data.len())].copy_from_slice(data);
+        packets.push(packet);
+    }
+
+    Ok(packets)
+}
+
+/// Result of `ResponseAssembler::push`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received {
+    /// A report of another channel
+    Ignored,
+    /// CTAPHID_KEEPALIVE with its status
+    KeepAlive(u8),
+    /// Waiting for continuation packets
+    Incomplete,
+    /// The message is complete
+    Complete,
+}
+
+/// Reassembles the response message of a channel from input reports (without Report ID)
+pub struct ResponseAssembler {
+    cid: [u8; 4],
+    command: Option<u8>,
+    bcnt: usize,
+    next_seq: u8,
+    data: Vec<u8>,
+}
+
+impl ResponseAssembler {
+    pub fn new(cid: &[u8]) -> Self {
+        let mut c = [0u8; 4];
+        c.copy_from_slice(&cid[0..4]);
+        Self {
+            cid: c,
+            command: None,
+            bcnt: 0,
+            next_seq: 0,
+            data: vec![],
+        }
+    }
+
+    pub fn push(&mut self, report: &[u8]) -> Result<Received> {
+        if report.len() < 5 {
+            return Err(invalid_length());
+        }
+
+        if report[0..4] != self.cid {
+            return Ok(Received::Ignored);
+        }
+
+        // keepalive may also arrive between the packets of a message
+        if report[4] == CTAPHID_KEEPALIVE {
+            return Ok(Received::KeepAlive(report.get(7).copied().unwrap_or(0)));
+        }
+
+        let is_init = report[4] & CTAP_FRAME_INIT != 0;
+        match (self.command, is_init) {
+            (None, true) => {
+                if report.len() < 7 {
+                    return Err(invalid_length());
+                }
+
+                let command = report[4];
+                let bcnt = (usize::from(report[5]) << 8) + usize::from(report[6]);
+                if bcnt > MAX_MESSAGE_SIZE {
+                    return Err(invalid_length());
+                }
+
+                let size = bcnt.min(report.len() - 7);
+                self.data.extend_from_slice(&report[7..(7 + size)]);
+                self.command = Some(command);
+                self.bcnt = bcnt;
+            }
+            (Some(_), false) => {
+                if report[4] != self.next_seq {
+                    return Err(invalid_seq());
+                }
+                self.next_seq += 1;
+
+                let size = (self.bcnt - self.data.len()).min(report.len() - 5);
+                self.data.extend_from_slice(&report[5..(5 + size)]);
+            }
+            // continuation packet without initialization packet
+            // or initialization packet before the message is complete
+            _ => return Err(invalid_seq()),
+        }
+
+        if self.data.len() >= self.bcnt {
+            Ok(Received::Complete)
+        } else {
+            Ok(Received::Incomplete)
+        }
+    }
+
+    /// (command, data) of the complete message
+    pub fn finish(self) -> Option<(u8, Vec<u8>)> {
+        match self.command {
+            Some(command) if self.data.len() == self.bcnt => Some((command, self.data)),
+            _ => None,
+        }
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CID: [u8; 4] = [0x01, 0x02, 0x03, 0x04];
+    const CTAPHID_CBOR: u8 = CTAP_FRAME_INIT | 0x10;
+
+    // output reports -> input reports
+    fn to_reports(packets: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        packets.into_iter().map(|p| p[1..].to_vec()).collect()
+    }
+
+    fn assemble(reports: &[Vec<u8>]) -> Result<(u8, Vec<u8>)> {
+        let mut assembler = ResponseAssembler::new(&CID);
+        for report in reports {
+            if assembler.push(report)? == Received::Complete {
+                break;
+            }
+        }
+        assembler.finish().ok_or_else(|| anyhow!("incomplete"))
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for size in [0, 1, 57, 58, 116, 117, 5000, MAX_MESSAGE_SIZE] {
+            let payload: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let packets = create_packets(&CID, CTAPHID_CBOR, &payload).unwrap();
+            assert!(packets.iter().all(|p| p.len() == PACKET_SIZE));
+
+            let (command, data) = assemble(&to_reports(packets)).unwrap();
+            assert_eq!(command, CTAPHID_CBOR);
+            assert_eq!(data, payload);
+        }
+    }
+
+    #[test]
+    fn test_max_message_size() {
+        assert_eq!(MAX_MESSAGE_SIZE, 7609);
+        let packets = create_packets(&CID, CTAPHID_CBOR, &[0; MAX_MESSAGE_SIZE]).unwrap();
+        assert_eq!(packets.len(), 129);
+        assert!(create_packets(&CID, CTAPHID_CBOR, &[0; MAX_MESSAGE_SIZE + 1]).is_err());
+
+        // BCNT too large
+        let mut report = vec![0u8; HID_REPORT_SIZE];
+        report[0..7].copy_from_slice(&[0x01, 0x02, 0x03, 0x04, CTAPHID_CBOR, 0x1d, 0xba]);
+        assert!(ResponseAssembler::new(&CID).push(&report).is_err());
+    }
+
+    #[test]
+    fn test_other_channel_and_keepalive() {
+        let payload = vec![0xaa; 100];
+        let reports = to_reports(create_packets(&CID, CTAPHID_CBOR, &payload).unwrap());
+
+        let mut keepalive = vec![0u8; HID_REPORT_SIZE];
+        keepalive[0..8].copy_from_slice(&[0x01, 0x02, 0x03, 0x04, CTAPHID_KEEPALIVE, 0, 1, 2]);
+        let other = to_reports(create_packets(&[9, 9, 9, 9], CTAPHID_CBOR, &[0xbb; 100]).unwrap());
+
+        let mut assembler = ResponseAssembler::new(&CID);
+        assert_eq!(assembler.push(&keepalive).unwrap(), Received::KeepAlive(2));
+        assert_eq!(assembler.push(&other[0]).unwrap(), Received::Ignored);
+        assert_eq!(assembler.push(&reports[0]).unwrap(), Received::Incomplete);
+        assert_eq!(assembler.push(&other[1]).unwrap(), Received::Ignored);
+        assert_eq!(assembler.push(&reports[1]).unwrap(), Received::Complete);
+        assert_eq!(assembler.finish().unwrap().1, payload);
+
+        // keepalive between the packets of a message
+        let reports = to_reports(create_packets(&CID, CTAPHID_CBOR, &[0xcc; 200]).unwrap());
+        let mut assembler = ResponseAssembler::new(&CID);
+        assert_eq!(assembler.push(&reports[0]).unwrap(), Received::Incomplete);
+        assert_eq!(assembler.push(&keepalive).unwrap(), Received::KeepAlive(2));
+        assert_eq!(assembler.push(&reports[1]).unwrap(), Received::Incomplete);
+        keepalive[7] = 1;
+        assert_eq!(assembler.push(&keepalive).unwrap(), Received::KeepAlive(1));
+        assert_eq!(assembler.push(&reports[2]).unwrap(), Received::Incomplete);
+        assert_eq!(assembler.push(&reports[3]).unwrap(), Received::Complete);
+        assert_eq!(assembler.finish().unwrap().1, vec![0xcc; 200]);
+
+        // out of order
+        let reports = to_reports(create_packets(&CID, CTAPHID_CBOR, &[0xaa; 200]).unwrap());
+        let mut assembler = ResponseAssembler::new(&CID);
+        assembler.push(&reports[0]).unwrap();
+        assert!(assembler.push(&reports[2]).is_err());
+
+        // continuation packet first
+        assert!(ResponseAssembler::new(&CID).push(&reports[1]).is_err());
+
+        // initialization packet before the message is complete
+        let mut assembler = ResponseAssembler::new(&CID);
+        assembler.push(&reports[0]).unwrap();
+        assert!(assembler.push(&reports[0]).is_err());
+    }
+}
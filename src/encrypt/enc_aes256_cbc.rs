@@ -10,13 +10,18 @@ pub fn encrypt_message_str(key: &[u8; 32], message: &str) -> Vec<u8> {
 }
 
 pub fn encrypt_message(key: &[u8; 32], message: &[u8]) -> Vec<u8> {
+    encrypt_message_with_iv(key, &[0u8; 16], message)
+}
+
+// AES256-CBC(key,IV,message)
+pub fn encrypt_message_with_iv(key: &[u8; 32], iv: &[u8; 16], message: &[u8]) -> Vec<u8> {
     if message.len() > 4096 {
         panic!("Message too long");
     }
 
     let mut buffer = message.to_vec();
     let pt_len = message.len();
-    let ciphertext = Aes256CbcEnc::new(key.into(), &[0u8; 16].into())
+    let ciphertext = Aes256CbcEnc::new(key.into(), iv.into())
         .encrypt_padded_mut::<NoPadding>(&mut buffer, pt_len)
         .unwrap();
     ciphertext.to_vec()
@@ -29,12 +34,16 @@ pub fn decrypt_message_str(key: &[u8; 32], message: &[u8]) -> String {
 }
 
 pub fn decrypt_message(key: &[u8; 32], message: &[u8]) -> Vec<u8> {
+    decrypt_message_with_iv(key, &[0u8; 16], message)
+}
+
+pub fn decrypt_message_with_iv(key: &[u8; 32], iv: &[u8; 16], message: &[u8]) -> Vec<u8> {
     if message.len() > 4096 {
         panic!("Message too long");
     }
 
     let mut buffer = message.to_vec();
-    let plaintext = Aes256CbcDec::new(key.into(), &[0u8; 16].into())
+    let plaintext = Aes256CbcDec::new(key.into(), iv.into())
         .decrypt_padded_mut::<NoPadding>(&mut buffer)
         .unwrap();
     plaintext.to_vec()
@@ -0,0 +1,44 @@
+use anyhow::{anyhow, Result};
+use ring::aead;
+
+// AES256-GCM(key,nonce,associated data,message) -> ciphertext || tag
+pub fn encrypt_message(
+    key: &[u8],
+    nonce: &[u8; 12],
+    aad: &[u8],
+    message: &[u8],
+) -> Result<Vec<u8>> {
+    let key = less_safe_key(key)?;
+    let mut in_out = message.to_vec();
+    key.seal_in_place_append_tag(
+        aead::Nonce::assume_unique_for_key(*nonce),
+        aead::Aad::from(aad),
+        &mut in_out,
+    )
+    .map_err(|_| anyhow!("AES-256-GCM encryption failed"))?;
+    Ok(in_out)
+}
+
+pub fn decrypt_message(
+    key: &[u8],
+    nonce: &[u8; 12],
+    aad: &[u8],
+    message: &[u8],
+) -> Result<Vec<u8>> {
+    let key = less_safe_key(key)?;
+    let mut in_out = message.to_vec();
+    let plaintext = key
+        .open_in_place(
+            aead::Nonce::assume_unique_for_key(*nonce),
+            aead::Aad::from(aad),
+            &mut in_out,
+        )
+        .map_err(|_| anyhow!("AES-256-GCM decryption failed"))?;
+    Ok(plaintext.to_vec())
+}
+
+fn less_safe_key(key: &[u8]) -> Result<aead::LessSafeKey> {
+    let key = aead::UnboundKey::new(&aead::AES_256_GCM, key)
+        .map_err(|_| anyhow!("Invalid AES-256-GCM key length"))?;
+    Ok(aead::LessSafeKey::new(key))
+}
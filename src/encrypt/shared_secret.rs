@@ -1,15 +1,29 @@
-use crate::{encrypt::cose::CoseKey, encrypt::enc_aes256_cbc, encrypt::p256, pintoken::PinToken};
-use anyhow::{Error, Result};
-use ring::{agreement, digest, error::Unspecified, rand};
+use crate::{
+    encrypt::cose::CoseKey, encrypt::enc_aes256_cbc, encrypt::enc_hmac_sha_256, encrypt::p256,
+    fidokey::pin::PinUvAuthProtocol, pintoken::PinToken,
+};
+use anyhow::{anyhow, Error, Result};
+use ring::{agreement, digest, error::Unspecified, hkdf, rand, rand::SecureRandom};
 
 #[derive(Debug, Default, Clone)]
 pub struct SharedSecret {
     pub public_key: CoseKey,
+    // PIN/UV Auth Protocol 1: SHA-256(Z)
+    // PIN/UV Auth Protocol 2: AES key
     pub secret: [u8; 32],
+    pub hmac_key: [u8; 32],
+    pub pin_uv_auth_protocol: PinUvAuthProtocol,
 }
 
 impl SharedSecret {
     pub fn new(peer_key: &CoseKey) -> Result<Self> {
+        Self::new_with_protocol(peer_key, PinUvAuthProtocol::One)
+    }
+
+    pub fn new_with_protocol(
+        peer_key: &CoseKey,
+        pin_uv_auth_protocol: PinUvAuthProtocol,
+    ) -> Result<Self> {
         let rng = rand::SystemRandom::new();
         let my_private_key = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng)
             .map_err(Error::msg)?;
@@ -21,17 +35,30 @@ impl SharedSecret {
             agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, peer_public_key)
         };
 
-        let shared_secret =
-            agreement::agree_ephemeral(my_private_key, &peer_public_key, Unspecified, |material| {
-                Ok(digest::digest(&digest::SHA256, material))
-            })
-            .map_err(Error::msg)?;
+        // Z: x-coordinate of the ECDH shared point
+        let z = agreement::agree_ephemeral(my_private_key, &peer_public_key, Unspecified, |z| {
+            Ok(z.to_vec())
+        })
+        .map_err(Error::msg)?;
 
         let mut res = Self {
             public_key: p256::P256Key::from_bytes(my_public_key.as_ref())?.to_cose(),
-            secret: [0; 32],
+            pin_uv_auth_protocol,
+            ..Default::default()
         };
-        res.secret.copy_from_slice(shared_secret.as_ref());
+
+        match pin_uv_auth_protocol {
+            PinUvAuthProtocol::One => {
+                res.secret
+                    .copy_from_slice(digest::digest(&digest::SHA256, &z).as_ref());
+                res.hmac_key = res.secret;
+            }
+            PinUvAuthProtocol::Two => {
+                // HKDF-SHA-256(salt = 32 zero bytes, IKM = Z, L = 32, info = "CTAP2 HMAC key" / "CTAP2 AES key")
+                res.hmac_key = hkdf_sha_256(&z, b"CTAP2 HMAC key")?;
+                res.secret = hkdf_sha_256(&z, b"CTAP2 AES key")?;
+            }
+        }
 
         Ok(res)
     }
@@ -53,4 +80,70 @@ impl SharedSecret {
         let dec = enc_aes256_cbc::decrypt_message(&self.secret, data);
         PinToken::new(&dec)
     }
+
+    /// encrypt(sharedSecret, demPlaintext)
+    /// - Protocol 1: AES-256-CBC with IV = 0
+    /// - Protocol 2: random IV || AES-256-CBC
+    pub fn encrypt_message(&self, message: &[u8]) -> Result<Vec<u8>> {
+        match self.pin_uv_auth_protocol {
+            PinUvAuthProtocol::One => Ok(enc_aes256_cbc::encrypt_message(&self.secret, message)),
+            PinUvAuthProtocol::Two => {
+                let mut iv = [0u8; 16];
+                rand::SystemRandom::new()
+                    .fill(&mut iv)
+                    .map_err(|_| anyhow!("Failed to create IV"))?;
+                let mut enc = iv.to_vec();
+                enc.append(&mut enc_aes256_cbc::encrypt_message_with_iv(
+                    &self.secret,
+                    &iv,
+                    message,
+                ));
+                Ok(enc)
+            }
+        }
+    }
+
+    /// decrypt(sharedSecret, ciphertext)
+    pub fn decrypt_message(&self, message: &[u8]) -> Result<Vec<u8>> {
+        if message.is_empty() || message.len() % 16 != 0 {
+            return Err(anyhow!("Invalid ciphertext length"));
+        }
+        match self.pin_uv_auth_protocol {
+            PinUvAuthProtocol::One => Ok(enc_aes256_cbc::decrypt_message(&self.secret, message)),
+            PinUvAuthProtocol::Two => {
+                if message.len() < 32 {
+                    return Err(anyhow!("Invalid ciphertext length"));
+                }
+                let mut iv = [0u8; 16];
+                iv.copy_from_slice(&message[0..16]);
+                Ok(enc_aes256_cbc::decrypt_message_with_iv(
+                    &self.secret,
+                    &iv,
+                    &message[16..],
+                ))
+            }
+        }
+    }
+
+    /// authenticate(sharedSecret, message)
+    /// - Protocol 1: LEFT(HMAC-SHA-256(key, message), 16)
+    /// - Protocol 2: HMAC-SHA-256(key, message)
+    pub fn authenticate(&self, message: &[u8]) -> Vec<u8> {
+        let sig = enc_hmac_sha_256::authenticate(&self.hmac_key, message);
+        match self.pin_uv_auth_protocol {
+            PinUvAuthProtocol::One => sig[0..16].to_vec(),
+            PinUvAuthProtocol::Two => sig,
+        }
+    }
+}
+
+fn hkdf_sha_256(ikm: &[u8], info: &[u8]) -> Result<[u8; 32]> {
+    let info = [info];
+    let mut out = [0u8; 32];
+    hkdf::Salt::new(hkdf::HKDF_SHA256, &[0u8; 32])
+        .extract(ikm)
+        .expand(&info, hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut out))
+        .map_err(|_| anyhow!("HKDF error"))?;
+    Ok(out)
 }
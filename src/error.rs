@@ -0,0 +1,116 @@
+/*!
+Errors that can be matched by the caller.
+
+They are returned wrapped in `anyhow::Error`.
+
+```ignore
+match err.downcast_ref::<CtapHidError>() {
+    Some(CtapHidError::Timeout(_)) => { /* retry */ }
+    Some(CtapHidError::Hid(HidErrorCode::LockRequired)) => { /* wait */ }
+    _ => {}
+}
+```
+*/
+use crate::ctapdef;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CtapHidError {
+    /// The authenticator did not respond within the timeout.
+    /// CTAPHID_CANCEL has been sent to the authenticator.
+    Timeout(Duration),
+    /// The request was cancelled by the caller.
+    /// CTAPHID_CANCEL has been sent to the authenticator.
+    Cancelled,
+    /// The device has been disconnected.
+    DeviceDisconnected,
+    /// The authenticator responded with CTAPHID_ERROR.
+    Hid(HidErrorCode),
+    /// The authenticator responded with a CTAP2 error status (ex. 0x31 CTAP2_ERR_PIN_INVALID).
+    /// It is wrapped in the context of the message "response_status err = ...".
+    Status(u8),
+    /// No persistent pinUvAuthToken is stored for the device. Obtain one with the PIN.
+    PersistentTokenMissing,
+    /// The persistent pinUvAuthToken has been invalidated (ex. PIN change) and removed from the store.
+    /// Obtain a new one with the PIN.
+    PersistentTokenInvalidated,
+}
+
+impl fmt::Display for CtapHidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(timeout) => write!(f, "Timeout: no response within {timeout:?}"),
+            Self::Cancelled => write!(f, "Cancelled"),
+            Self::DeviceDisconnected => write!(f, "Device disconnected"),
+            Self::Hid(code) => write!(f, "CTAPHID_ERROR: {code}"),
+            Self::Status(status) => write!(f, "{}", ctapdef::get_ctap_status_message(*status)),
+            Self::PersistentTokenMissing => write!(f, "No persistent pinUvAuthToken"),
+            Self::PersistentTokenInvalidated => {
+                write!(f, "The persistent pinUvAuthToken has been invalidated")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CtapHidError {}
+
+/// Error code of a CTAPHID_ERROR response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HidErrorCode {
+    /// 0x01 The command in the request is invalid
+    InvalidCmd,
+    /// 0x02 The parameter(s) in the request is invalid
+    InvalidPar,
+    /// 0x03 The length field (BCNT) is invalid for the request
+    InvalidLen,
+    /// 0x04 The sequence does not match expected value
+    InvalidSeq,
+    /// 0x05 The message has timed out
+    MsgTimeout,
+    /// 0x06 The device is busy for the requesting channel
+    ChannelBusy,
+    /// 0x0A Command requires channel lock
+    LockRequired,
+    /// 0x0B CID is not valid
+    InvalidChannel,
+    /// Unspecified error
+    Other(u8),
+}
+
+impl HidErrorCode {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => Self::InvalidCmd,
+            0x02 => Self::InvalidPar,
+            0x03 => Self::InvalidLen,
+            0x04 => Self::InvalidSeq,
+            0x05 => Self::MsgTimeout,
+            0x06 => Self::ChannelBusy,
+            0x0A => Self::LockRequired,
+            0x0B => Self::InvalidChannel,
+            _ => Self::Other(code),
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::InvalidCmd => 0x01,
+            Self::InvalidPar => 0x02,
+            Self::InvalidLen => 0x03,
+            Self::InvalidSeq => 0x04,
+            Self::MsgTimeout => 0x05,
+            Self::ChannelBusy => 0x06,
+            Self::LockRequired => 0x0A,
+            Self::InvalidChannel => 0x0B,
+            Self::Other(code) => *code,
+        }
+    }
+}
+
+impl fmt::Display for HidErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", ctapdef::get_ctap_status_message(self.code()))
+    }
+}
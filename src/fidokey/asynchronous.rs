@@ -0,0 +1,505 @@
+/*!
+Async API (feature `async`)
+
+`FidoKeyHidAsync` owns a `FidoKeyHid` on a dedicated I/O thread.
+Requests are queued to the thread and their results are awaited,
+so the async runtime is never blocked by HID reads or keepalive waits.
+
+Dropping a pending future cancels the request:
+CTAPHID_CANCEL is sent if it is in progress, or it is skipped if it has not started yet.
+
+```ignore
+let device = FidoKeyHidAsync::create(&Cfg::init())?;
+let info = device.get_info().await?;
+let att = device
+    .run(|d| d.make_credential("example.com", b"challenge", Some("1234")))
+    .await?;
+```
+*/
+use super::{
+    bio::{BioSensorInfo, EnrollStatus1, EnrollStatus2, TemplateInfo},
+    credential_management::credential_management_params::{Credential, CredentialsCount, Rp},
+    get_assertion::get_assertion_params::{Assertion, GetAssertionArgs},
+    get_info::Info,
+    large_blobs::large_blobs_params::LargeBlobData,
+    make_credential::{Attestation, MakeCredentialArgs},
+    FidoKey, FidoKeyHid,
+};
+use crate::{public_key_credential_descriptor::PublicKeyCredentialDescriptor, KeyID, LibCfg};
+use anyhow::{anyhow, Result};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
+use std::thread;
+use tokio::sync::oneshot;
+
+type Job<D> = Box<dyn FnOnce(&D) + Send>;
+
+// Jobs run in order on the I/O thread.
+// Each job has its own cancellation flag, set when its future is dropped.
+struct JobQueue<D> {
+    jobs: mpsc::Sender<(Arc<AtomicBool>, Job<D>)>,
+}
+
+impl<D> JobQueue<D> {
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&D) -> Result<T> + Send + 'static,
+    {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = oneshot::channel();
+        let job: Job<D> = Box::new(move |device| {
+            let _ = tx.send(f(device));
+        });
+
+        let mut guard = CancelOnDrop {
+            cancelled: cancelled.clone(),
+            armed: true,
+        };
+        self.jobs
+            .send((cancelled, job))
+            .map_err(|_| anyhow!("The device thread has stopped"))?;
+
+        let result = rx
+            .await
+            .map_err(|_| anyhow!("The device thread has stopped"))?;
+        guard.armed = false;
+        result
+    }
+}
+
+// Run the jobs in order.
+// A job whose future was dropped before it started is skipped.
+fn serve<D>(
+    jobs: impl IntoIterator<Item = (Arc<AtomicBool>, Job<D>)>,
+    mut run: impl FnMut(Arc<AtomicBool>, Job<D>),
+) {
+    for (cancelled, job) in jobs {
+        if cancelled.load(Ordering::SeqCst) {
+            continue;
+        }
+        run(cancelled, job);
+    }
+}
+
+// Marks the request as cancelled unless disarmed
+struct CancelOnDrop {
+    cancelled: Arc<AtomicBool>,
+    armed: bool,
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if self.armed {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// `FidoKeyHid` with an async API
+pub struct FidoKeyHidAsync {
+    queue: JobQueue<FidoKeyHid>,
+}
+
+impl FidoKeyHidAsync {
+    /// Open the device on a dedicated I/O thread
+    pub fn new(params: &[KeyID], cfg: &LibCfg) -> Result<Self> {
+        let params = params.to_vec();
+        let cfg = cfg.clone();
+        let (jobs, receiver) = mpsc::channel::<(Arc<AtomicBool>, Job<FidoKeyHid>)>();
+        let (opened_tx, opened_rx) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("ctap-hid-fido2".to_string())
+            .spawn(move || {
+                let mut device = match FidoKeyHid::new(&params, &cfg) {
+                    Ok(device) => {
+                        let _ = opened_tx.send(Ok(()));
+                        device
+                    }
+                    Err(e) => {
+                        let _ = opened_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                // runs until FidoKeyHidAsync is dropped
+                serve(receiver, |cancelled, job| {
+                    device.cancel = Some(cancelled);
+                    job(&device);
+                    device.cancel = None;
+                });
+            })?;
+
+        opened_rx
+            .recv()
+            .map_err(|_| anyhow!("The device thread has stopped"))??;
+
+        Ok(Self {
+            queue: JobQueue { jobs },
+        })
+    }
+
+    /// Open the only FIDO device (see `FidoKeyHidFactory::create`)
+    pub fn create(cfg: &LibCfg) -> Result<Self> {
+        let mut devs = crate::get_fidokey_devices();
+        if devs.is_empty() {
+            return Err(anyhow!("FIDO device not found."));
+        }
+        if devs.len() > 1 {
+            return Err(anyhow!("Multiple FIDO devices found."));
+        }
+
+        Self::new(&[devs.pop().unwrap().param], cfg)
+    }
+
+    /// Run `f` on the I/O thread.
+    /// Any `FidoKeyHid` method can be called this way.
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&FidoKeyHid) -> Result<T> + Send + 'static,
+    {
+        self.queue.run(f).await
+    }
+
+    pub async fn get_info(&self) -> Result<Info> {
+        self.run(|d| d.get_info()).await
+    }
+
+    pub async fn wink(&self) -> Result<()> {
+        self.run(|d| d.wink()).await
+    }
+
+    pub async fn get_pin_retries(&self) -> Result<i32> {
+        self.run(|d| d.get_pin_retries()).await
+    }
+
+    pub async fn get_uv_retries(&self) -> Result<i32> {
+        self.run(|d| d.get_uv_retries()).await
+    }
+
+    pub async fn set_new_pin(&self, pin: &str) -> Result<()> {
+        let pin = pin.to_string();
+        self.run(move |d| d.set_new_pin(&pin)).await
+    }
+
+    pub async fn change_pin(&self, current_pin: &str, new_pin: &str) -> Result<()> {
+        let current_pin = current_pin.to_string();
+        let new_pin = new_pin.to_string();
+        self.run(move |d| d.change_pin(&current_pin, &new_pin))
+            .await
+    }
+
+    pub async fn make_credential_with_args(
+        &self,
+        args: MakeCredentialArgs<'_>,
+    ) -> Result<Attestation> {
+        let MakeCredentialArgs {
+            rpid,
+            challenge,
+            pin,
+            key_type,
+            uv,
+            exclude_list,
+            user_entity,
+            rk,
+            extensions,
+        } = args;
+        let pin = pin.map(str::to_string);
+
+        self.run(move |d| {
+            d.make_credential_with_args(&MakeCredentialArgs {
+                rpid,
+                challenge,
+                pin: pin.as_deref(),
+                key_type,
+                uv,
+                exclude_list,
+                user_entity,
+                rk,
+                extensions,
+            })
+        })
+        .await
+    }
+
+    pub async fn get_assertion_with_args(
+        &self,
+        args: GetAssertionArgs<'_>,
+    ) -> Result<Vec<Assertion>> {
+        let GetAssertionArgs {
+            rpid,
+            challenge,
+            pin,
+            credential_ids,
+            uv,
+            extensions,
+        } = args;
+        let pin = pin.map(str::to_string);
+
+        self.run(move |d| {
+            d.get_assertion_with_args(&GetAssertionArgs {
+                rpid,
+                challenge,
+                pin: pin.as_deref(),
+                credential_ids,
+                uv,
+                extensions,
+            })
+        })
+        .await
+    }
+
+    pub async fn credential_management_get_creds_metadata(
+        &self,
+        pin: Option<&str>,
+    ) -> Result<CredentialsCount> {
+        let pin = pin.map(str::to_string);
+        self.run(move |d| d.credential_management_get_creds_metadata(pin.as_deref()))
+            .await
+    }
+
+    pub async fn credential_management_enumerate_rps(&self, pin: Option<&str>) -> Result<Vec<Rp>> {
+        let pin = pin.map(str::to_string);
+        self.run(move |d| d.credential_management_enumerate_rps(pin.as_deref()))
+            .await
+    }
+
+    pub async fn credential_management_enumerate_credentials(
+        &self,
+        pin: Option<&str>,
+        rpid_hash: &[u8],
+    ) -> Result<Vec<Credential>> {
+        let pin = pin.map(str::to_string);
+        let rpid_hash = rpid_hash.to_vec();
+        self.run(move |d| d.credential_management_enumerate_credentials(pin.as_deref(), &rpid_hash))
+            .await
+    }
+
+    pub async fn credential_management_delete_credential(
+        &self,
+        pin: Option<&str>,
+        pkcd: PublicKeyCredentialDescriptor,
+    ) -> Result<()> {
+        let pin = pin.map(str::to_string);
+        self.run(move |d| d.credential_management_delete_credential(pin.as_deref(), pkcd))
+            .await
+    }
+
+    pub async fn bio_enrollment_get_fingerprint_sensor_info(&self) -> Result<BioSensorInfo> {
+        self.run(|d| d.bio_enrollment_get_fingerprint_sensor_info())
+            .await
+    }
+
+    pub async fn bio_enrollment_begin(
+        &self,
+        pin: &str,
+        timeout_milliseconds: Option<u16>,
+    ) -> Result<(EnrollStatus1, EnrollStatus2)> {
+        let pin = pin.to_string();
+        self.run(move |d| d.bio_enrollment_begin(&pin, timeout_milliseconds))
+            .await
+    }
+
+    pub async fn bio_enrollment_next(
+        &self,
+        enroll_status: EnrollStatus1,
+        timeout_milliseconds: Option<u16>,
+    ) -> Result<EnrollStatus2> {
+        self.run(move |d| d.bio_enrollment_next(&enroll_status, timeout_milliseconds))
+            .await
+    }
+
+    pub async fn bio_enrollment_cancel(&self, enroll_status: EnrollStatus1) -> Result<()> {
+        self.run(move |d| d.bio_enrollment_cancel(&enroll_status))
+            .await
+    }
+
+    pub async fn bio_enrollment_enumerate_enrollments(
+        &self,
+        pin: &str,
+    ) -> Result<Vec<TemplateInfo>> {
+        let pin = pin.to_string();
+        self.run(move |d| d.bio_enrollment_enumerate_enrollments(&pin))
+            .await
+    }
+
+    pub async fn bio_enrollment_set_friendly_name(
+        &self,
+        pin: &str,
+        template_id: &[u8],
+        template_name: &str,
+    ) -> Result<()> {
+        let pin = pin.to_string();
+        let template_id = template_id.to_vec();
+        let template_name = template_name.to_string();
+        self.run(move |d| d.bio_enrollment_set_friendly_name(&pin, &template_id, &template_name))
+            .await
+    }
+
+    pub async fn bio_enrollment_remove(&self, pin: &str, template_id: &[u8]) -> Result<()> {
+        let pin = pin.to_string();
+        let template_id = template_id.to_vec();
+        self.run(move |d| d.bio_enrollment_remove(&pin, &template_id))
+            .await
+    }
+
+    pub async fn get_large_blob(&self) -> Result<LargeBlobData> {
+        self.run(|d| d.get_large_blob()).await
+    }
+
+    pub async fn write_large_blob(
+        &self,
+        pin: Option<&str>,
+        write_datas: Vec<u8>,
+    ) -> Result<LargeBlobData> {
+        let pin = pin.map(str::to_string);
+        self.run(move |d| d.write_large_blob(pin.as_deref(), write_datas))
+            .await
+    }
+
+    pub async fn get_large_blob_with_key(&self, large_blob_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let large_blob_key = large_blob_key.to_vec();
+        self.run(move |d| d.get_large_blob_with_key(&large_blob_key))
+            .await
+    }
+
+    pub async fn write_large_blob_with_key(
+        &self,
+        pin: Option<&str>,
+        large_blob_key: &[u8],
+        data: &[u8],
+    ) -> Result<()> {
+        let pin = pin.map(str::to_string);
+        let large_blob_key = large_blob_key.to_vec();
+        let data = data.to_vec();
+        self.run(move |d| d.write_large_blob_with_key(pin.as_deref(), &large_blob_key, &data))
+            .await
+    }
+
+    pub async fn delete_large_blob_with_key(
+        &self,
+        pin: Option<&str>,
+        large_blob_key: &[u8],
+    ) -> Result<bool> {
+        let pin = pin.map(str::to_string);
+        let large_blob_key = large_blob_key.to_vec();
+        self.run(move |d| d.delete_large_blob_with_key(pin.as_deref(), &large_blob_key))
+            .await
+    }
+
+    pub async fn toggle_always_uv(&self, pin: Option<&str>) -> Result<()> {
+        let pin = pin.map(str::to_string);
+        self.run(move |d| d.toggle_always_uv(pin.as_deref())).await
+    }
+
+    pub async fn set_min_pin_length(
+        &self,
+        new_min_pin_length: u8,
+        pin: Option<&str>,
+    ) -> Result<()> {
+        let pin = pin.map(str::to_string);
+        self.run(move |d| d.set_min_pin_length(new_min_pin_length, pin.as_deref()))
+            .await
+    }
+
+    pub async fn set_min_pin_length_rpids(
+        &self,
+        rpids: Vec<String>,
+        pin: Option<&str>,
+    ) -> Result<()> {
+        let pin = pin.map(str::to_string);
+        self.run(move |d| d.set_min_pin_length_rpids(rpids, pin.as_deref()))
+            .await
+    }
+
+    pub async fn force_change_pin(&self, pin: Option<&str>) -> Result<()> {
+        let pin = pin.map(str::to_string);
+        self.run(move |d| d.force_change_pin(pin.as_deref())).await
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Wake};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<FidoKeyHidAsync>();
+    }
+
+    #[test]
+    fn test_cancel_on_drop() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        drop(CancelOnDrop {
+            cancelled: cancelled.clone(),
+            armed: true,
+        });
+        assert!(cancelled.load(Ordering::SeqCst));
+
+        // a completed request is not cancelled
+        let cancelled = Arc::new(AtomicBool::new(false));
+        drop(CancelOnDrop {
+            cancelled: cancelled.clone(),
+            armed: false,
+        });
+        assert!(!cancelled.load(Ordering::SeqCst));
+    }
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn poll<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+        let waker = Arc::new(NoopWaker).into();
+        future.poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn test_drop_queued_futures() {
+        let (jobs, receiver) = mpsc::channel();
+        let queue = JobQueue::<RefCell<Vec<u32>>> { jobs };
+
+        let run = |n: u32| {
+            queue.run(move |log: &RefCell<Vec<u32>>| {
+                log.borrow_mut().push(n);
+                Ok(n)
+            })
+        };
+        let mut f1 = Box::pin(run(1));
+        let mut f2 = Box::pin(run(2));
+        let mut f3 = Box::pin(run(3));
+        let mut f4 = Box::pin(run(4));
+
+        // queued on the first poll
+        assert!(poll(f1.as_mut()).is_pending());
+        assert!(poll(f2.as_mut()).is_pending());
+        assert!(poll(f3.as_mut()).is_pending());
+        assert!(poll(f4.as_mut()).is_pending());
+
+        // drop two queued futures
+        drop(f2);
+        drop(f3);
+
+        let log = RefCell::new(vec![]);
+        serve(receiver.try_iter(), |_, job| job(&log));
+        assert_eq!(*log.borrow(), vec![1, 4]);
+
+        assert!(matches!(poll(f1.as_mut()), Poll::Ready(Ok(1))));
+        assert!(matches!(poll(f4.as_mut()), Poll::Ready(Ok(4))));
+    }
+}
@@ -1,8 +1,10 @@
 mod authenticator_config_command;
 
-use super::{pin::Permission::AuthenticatorConfiguration, FidoKeyHid};
+use super::{
+    pin::Permission::AuthenticatorConfiguration, sub_command_base::SubCommandBase, FidoKeyHid,
+};
 
-use crate::ctaphid;
+use crate::{ctapdef, ctaphid};
 
 use anyhow::{anyhow, Result};
 use authenticator_config_command::SubCommand;
@@ -26,6 +28,8 @@ impl FidoKeyHid {
 
     fn config(&self, pin: Option<&str>, sub_command: &SubCommand) -> Result<()> {
         let pin = pin.ok_or_else(|| anyhow!("need PIN."))?;
+        self.quirks()
+            .check_sub_command(ctapdef::AUTHENTICATOR_CONFIG, sub_command.id()?)?;
 
         let cid = ctaphid::ctaphid_init(self)?;
 
@@ -7,6 +7,7 @@ pub struct EnrollStatus1 {
     pub template_id: Vec<u8>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct BioSensorInfo {
     pub modality: Modality,
@@ -49,6 +50,7 @@ impl fmt::Display for BioSensorInfo {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Copy, Clone, Default)]
 pub enum Modality {
     #[default]
@@ -66,6 +68,7 @@ impl From<u32> for Modality {
 }
 
 #[allow(dead_code)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Copy, Clone, Default)]
 pub enum FingerprintKind {
     #[default]
@@ -125,8 +128,10 @@ impl fmt::Display for BioEnrollmentData {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct TemplateInfo {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub template_id: Vec<u8>,
     pub template_friendly_name: Option<String>,
 }
@@ -0,0 +1,374 @@
+/*!
+Fingerprint enrollment as a state machine
+
+```ignore
+let mut enrollment = device
+    .bio_enroll(pin)?
+    .sample_timeout(Duration::from_secs(10))
+    .friendly_name("right index");
+
+while let Some(sample) = enrollment.next_sample()? {
+    println!("{} (remaining {})", sample.feedback, sample.remaining_samples);
+}
+println!("templateId = {}", hex::encode(enrollment.template_id()));
+if let Some(err) = enrollment.friendly_name_error() {
+    println!("enrolled without a name: {err}");
+}
+```
+
+If the `Enrollment` is dropped before it is completed, the enrollment is cancelled.
+*/
+use super::{bio_enrollment_command::SubCommand as BioCmd, BioEnrollmentData, TemplateInfo};
+use crate::{ctapdef, pintoken::PinToken, FidoKeyHid};
+use anyhow::{anyhow, Result};
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
+
+// The host waits a little longer than the authenticator
+const SAMPLE_TIMEOUT_MARGIN: Duration = Duration::from_secs(2);
+
+/// lastEnrollSampleStatus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFeedback {
+    /// 0x00 CTAP2_ENROLL_FEEDBACK_FP_GOOD
+    Good,
+    /// 0x01
+    TooHigh,
+    /// 0x02
+    TooLow,
+    /// 0x03
+    TooLeft,
+    /// 0x04
+    TooRight,
+    /// 0x05
+    TooFast,
+    /// 0x06
+    TooSlow,
+    /// 0x07
+    PoorQuality,
+    /// 0x08
+    TooSkewed,
+    /// 0x09
+    TooShort,
+    /// 0x0A
+    MergeFailure,
+    /// 0x0B
+    AlreadyExists,
+    /// 0x0D User did not touch/swipe the authenticator
+    NoUserActivity,
+    /// 0x0E User did not lift the finger off the sensor
+    NoUserPresenceTransition,
+    Unknown(u8),
+}
+
+impl From<u8> for SampleFeedback {
+    fn from(from: u8) -> Self {
+        match from {
+            0x00 => Self::Good,
+            0x01 => Self::TooHigh,
+            0x02 => Self::TooLow,
+            0x03 => Self::TooLeft,
+            0x04 => Self::TooRight,
+            0x05 => Self::TooFast,
+            0x06 => Self::TooSlow,
+            0x07 => Self::PoorQuality,
+            0x08 => Self::TooSkewed,
+            0x09 => Self::TooShort,
+            0x0a => Self::MergeFailure,
+            0x0b => Self::AlreadyExists,
+            0x0d => Self::NoUserActivity,
+            0x0e => Self::NoUserPresenceTransition,
+            _ => Self::Unknown(from),
+        }
+    }
+}
+
+impl SampleFeedback {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Good => 0x00,
+            Self::TooHigh => 0x01,
+            Self::TooLow => 0x02,
+            Self::TooLeft => 0x03,
+            Self::TooRight => 0x04,
+            Self::TooFast => 0x05,
+            Self::TooSlow => 0x06,
+            Self::PoorQuality => 0x07,
+            Self::TooSkewed => 0x08,
+            Self::TooShort => 0x09,
+            Self::MergeFailure => 0x0a,
+            Self::AlreadyExists => 0x0b,
+            Self::NoUserActivity => 0x0d,
+            Self::NoUserPresenceTransition => 0x0e,
+            Self::Unknown(code) => *code,
+        }
+    }
+}
+
+impl fmt::Display for SampleFeedback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            ctapdef::get_ctap_last_enroll_sample_status_message(self.code())
+        )
+    }
+}
+
+/// A captured sample
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnrollmentSample {
+    pub feedback: SampleFeedback,
+    /// Number of good samples still required
+    pub remaining_samples: u32,
+}
+
+impl EnrollmentSample {
+    /// The last sample of the enrollment
+    pub fn is_finish(&self) -> bool {
+        self.feedback == SampleFeedback::Good && self.remaining_samples == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnrollmentState {
+    /// `EnrollBegin` has not been sent
+    NotStarted,
+    /// Waiting for the next sample
+    Capturing,
+    /// The template has been enrolled
+    Completed,
+    Cancelled,
+    /// A request failed, the enrollment cannot be continued
+    Failed,
+}
+
+impl EnrollmentState {
+    // The state after a request in this state (sample: None if the request failed)
+    fn next(self, sample: Option<&EnrollmentSample>) -> Self {
+        match (self, sample) {
+            (_, None) => Self::Failed,
+            (_, Some(sample)) if sample.is_finish() => Self::Completed,
+            (Self::NotStarted, Some(_)) => Self::Capturing,
+            (state, Some(_)) => state,
+        }
+    }
+
+    /// No more samples are captured
+    pub fn is_terminal(self) -> bool {
+        matches!(self, Self::Completed | Self::Cancelled | Self::Failed)
+    }
+}
+
+/// Fingerprint enrollment (see `FidoKeyHid::bio_enroll`)
+pub struct Enrollment<'a> {
+    device: &'a FidoKeyHid,
+    cid: [u8; 4],
+    pin_token: Option<PinToken>,
+    template_id: Vec<u8>,
+    state: EnrollmentState,
+    sample_timeout: Option<Duration>,
+    friendly_name: Option<String>,
+    friendly_name_error: Option<anyhow::Error>,
+}
+
+impl<'a> Enrollment<'a> {
+    pub(crate) fn new(device: &'a FidoKeyHid, cid: [u8; 4], pin_token: Option<PinToken>) -> Self {
+        Self {
+            device,
+            cid,
+            pin_token,
+            template_id: vec![],
+            state: EnrollmentState::NotStarted,
+            sample_timeout: None,
+            friendly_name: None,
+            friendly_name_error: None,
+        }
+    }
+
+    /// Timeout of each sample (None: the default of the authenticator)
+    pub fn sample_timeout(mut self, timeout: Duration) -> Self {
+        self.sample_timeout = Some(timeout);
+        self
+    }
+
+    /// Set as the templateFriendlyName when the enrollment is completed.
+    /// If it fails, the template stays enrolled without a name (see `friendly_name_error`).
+    pub fn friendly_name(mut self, name: &str) -> Self {
+        self.friendly_name = Some(name.to_string());
+        self
+    }
+
+    pub fn state(&self) -> EnrollmentState {
+        self.state
+    }
+
+    /// Empty until the first sample has been captured
+    pub fn template_id(&self) -> &[u8] {
+        &self.template_id
+    }
+
+    /// Error of setting the templateFriendlyName after the enrollment was completed
+    pub fn friendly_name_error(&self) -> Option<&anyhow::Error> {
+        self.friendly_name_error.as_ref()
+    }
+
+    /// Capture the next sample. None when the enrollment is completed.
+    /// After an error, the enrollment is cancelled and cannot be continued.
+    pub fn next_sample(&mut self) -> Result<Option<EnrollmentSample>> {
+        let timeout_milliseconds = self
+            .sample_timeout
+            .map(|timeout| u16::try_from(timeout.as_millis()).unwrap_or(u16::MAX));
+
+        let sub_command = match self.state {
+            EnrollmentState::NotStarted => BioCmd::EnrollBegin(timeout_milliseconds),
+            EnrollmentState::Capturing => BioCmd::EnrollCaptureNextSample(
+                TemplateInfo::new(&self.template_id, None),
+                timeout_milliseconds,
+            ),
+            EnrollmentState::Completed => return Ok(None),
+            EnrollmentState::Cancelled => return Err(anyhow!("Enrollment cancelled")),
+            EnrollmentState::Failed => return Err(anyhow!("Enrollment failed")),
+        };
+
+        let result = self.capture(sub_command);
+        let was_capturing = self.state == EnrollmentState::Capturing;
+        self.state = self.state.next(result.as_ref().ok());
+
+        let sample = match result {
+            Ok(sample) => sample,
+            Err(err) => {
+                if was_capturing {
+                    let _ = self.send(BioCmd::CancelCurrentEnrollment);
+                }
+                return Err(err);
+            }
+        };
+
+        if self.state == EnrollmentState::Completed {
+            if let Some(name) = self.friendly_name.clone() {
+                let template_info = TemplateInfo::new(&self.template_id, Some(&name));
+                self.friendly_name_error = self.send(BioCmd::SetFriendlyName(template_info)).err();
+            }
+        }
+
+        Ok(Some(sample))
+    }
+
+    fn capture(&mut self, sub_command: BioCmd) -> Result<EnrollmentSample> {
+        let data = match self.sample_timeout {
+            Some(timeout) => {
+                let device = self.device;
+                device.with_timeout(timeout + SAMPLE_TIMEOUT_MARGIN, |_| self.send(sub_command))?
+            }
+            None => self.send(sub_command)?,
+        };
+
+        if self.state == EnrollmentState::NotStarted {
+            self.template_id = data.template_id;
+        }
+
+        Ok(EnrollmentSample {
+            feedback: SampleFeedback::from(data.last_enroll_sample_status as u8),
+            remaining_samples: data.remaining_samples,
+        })
+    }
+
+    fn send(&self, sub_command: BioCmd) -> Result<BioEnrollmentData> {
+        self.device
+            .bio_enrollment(self.cid, self.pin_token.as_ref(), Some(sub_command))
+    }
+
+    /// Cancel the enrollment in progress
+    pub fn cancel(&mut self) -> Result<()> {
+        if self.state != EnrollmentState::Capturing {
+            return Ok(());
+        }
+        self.state = EnrollmentState::Cancelled;
+        self.send(BioCmd::CancelCurrentEnrollment)?;
+        Ok(())
+    }
+}
+
+impl Iterator for Enrollment<'_> {
+    type Item = Result<EnrollmentSample>;
+
+    // ends after an error
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state.is_terminal() {
+            return None;
+        }
+        self.next_sample().transpose()
+    }
+}
+
+impl Drop for Enrollment<'_> {
+    fn drop(&mut self) {
+        let _ = self.cancel();
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_feedback() {
+        for code in 0x00..=0xff {
+            assert_eq!(SampleFeedback::from(code).code(), code);
+        }
+        assert_eq!(SampleFeedback::from(0x01), SampleFeedback::TooHigh);
+        assert_eq!(SampleFeedback::from(0x0c), SampleFeedback::Unknown(0x0c));
+        assert_eq!(
+            SampleFeedback::TooHigh.to_string(),
+            "Fingerprint was too high."
+        );
+
+        let sample = EnrollmentSample {
+            feedback: SampleFeedback::Good,
+            remaining_samples: 0,
+        };
+        assert!(sample.is_finish());
+        let sample = EnrollmentSample {
+            feedback: SampleFeedback::TooFast,
+            remaining_samples: 0,
+        };
+        assert!(!sample.is_finish());
+    }
+
+    #[test]
+    fn test_enrollment_state() {
+        use EnrollmentState::*;
+
+        let good = EnrollmentSample {
+            feedback: SampleFeedback::Good,
+            remaining_samples: 3,
+        };
+        let retry = EnrollmentSample {
+            feedback: SampleFeedback::TooFast,
+            remaining_samples: 3,
+        };
+        let finish = EnrollmentSample {
+            feedback: SampleFeedback::Good,
+            remaining_samples: 0,
+        };
+
+        assert_eq!(NotStarted.next(Some(&good)), Capturing);
+        assert_eq!(NotStarted.next(Some(&finish)), Completed);
+        assert_eq!(NotStarted.next(None), Failed);
+        assert_eq!(Capturing.next(Some(&good)), Capturing);
+        assert_eq!(Capturing.next(Some(&retry)), Capturing);
+        assert_eq!(Capturing.next(Some(&finish)), Completed);
+        assert_eq!(Capturing.next(None), Failed);
+
+        assert!(!NotStarted.is_terminal());
+        assert!(!Capturing.is_terminal());
+        assert!(Completed.is_terminal());
+        assert!(Cancelled.is_terminal());
+        assert!(Failed.is_terminal());
+    }
+}
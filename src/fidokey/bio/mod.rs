@@ -1,14 +1,17 @@
 mod bio_enrollment_command;
 mod bio_enrollment_params;
 mod bio_enrollment_response;
+mod enrollment;
 use crate::pintoken::PinToken;
 use crate::util;
 use crate::{ctapdef, ctaphid};
-use crate::{fidokey::pin::Permission::BioEnrollment, FidoKeyHid};
+use crate::{
+    fidokey::pin::Permission::BioEnrollment, fidokey::sub_command_base::SubCommandBase, FidoKeyHid,
+};
 use anyhow::Result;
 pub use bio_enrollment_command::SubCommand as BioCmd;
 pub use bio_enrollment_params::*;
-use bio_enrollment_params::{BioEnrollmentData, TemplateInfo};
+pub use enrollment::{Enrollment, EnrollmentSample, EnrollmentState, SampleFeedback};
 
 impl FidoKeyHid {
     /// `BioEnrollment` - `getFingerprintSensorInfo` (CTAP 2.1-PRE)
@@ -36,6 +39,13 @@ impl FidoKeyHid {
         })
     }
 
+    /// Start a fingerprint enrollment.
+    /// The samples are captured with `Enrollment::next_sample`.
+    pub fn bio_enroll(&self, pin: &str) -> Result<Enrollment<'_>> {
+        let init = self.bio_enrollment_init(Some(pin))?;
+        Ok(Enrollment::new(self, init.0, init.1))
+    }
+
     /// `BioEnrollment` - `EnrollBegin`
     pub fn bio_enrollment_begin(
         &self,
@@ -186,11 +196,19 @@ impl FidoKeyHid {
         pin_token: Option<&PinToken>,
         sub_command: Option<bio_enrollment_command::SubCommand>,
     ) -> Result<BioEnrollmentData> {
-        let send_payload = bio_enrollment_command::create_payload(
-            pin_token,
-            sub_command,
-            self.use_pre_bio_enrollment,
-        )?;
+        let use_pre_bio_enrollment = self.is_pre_bio_enrollment()?;
+        if let Some(sub_command) = &sub_command {
+            let command = if use_pre_bio_enrollment {
+                ctapdef::AUTHENTICATOR_BIO_ENROLLMENT_P
+            } else {
+                ctapdef::AUTHENTICATOR_BIO_ENROLLMENT
+            };
+            self.quirks()
+                .check_sub_command(command, sub_command.id()?)?;
+        }
+
+        let send_payload =
+            bio_enrollment_command::create_payload(pin_token, sub_command, use_pre_bio_enrollment)?;
 
         if self.enable_log {
             println!("send(cbor) = {}", util::to_hex_str(&send_payload));
@@ -206,13 +224,16 @@ impl FidoKeyHid {
     }
 
     fn bio_enrollment_init(&self, pin: Option<&str>) -> Result<([u8; 4], Option<PinToken>)> {
+        // decided before init (it may need authenticatorGetInfo)
+        let use_pre_bio_enrollment = self.is_pre_bio_enrollment()?;
+
         // init
         let cid = ctaphid::ctaphid_init(self)?;
 
         // pin token
         let pin_token = {
             if let Some(pin) = pin {
-                if self.use_pre_bio_enrollment {
+                if use_pre_bio_enrollment {
                     Some(self.get_pin_token(&cid, pin)?)
                 } else {
                     Some(self.get_pinuv_auth_token_with_permission(&cid, pin, BioEnrollment)?)
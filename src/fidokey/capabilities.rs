@@ -0,0 +1,10 @@
+use super::FidoKeyHid;
+use crate::ctaphid::{self, HidDeviceCapabilities};
+use anyhow::Result;
+
+impl FidoKeyHid {
+    /// CTAPHID protocol version, device version and capabilities (CTAPHID_INIT)
+    pub fn get_hid_device_capabilities(&self) -> Result<HidDeviceCapabilities> {
+        ctaphid::ctaphid_init_with_capabilities(self).map(|(_, caps)| caps)
+    }
+}
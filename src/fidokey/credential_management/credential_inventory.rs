@@ -0,0 +1,506 @@
+/*!
+Inventory of the discoverable credentials for audits
+
+```ignore
+let inventory = device.credential_inventory(Some(pin))?;
+std::fs::write("inventory.csv", inventory.to_csv())?;
+
+let diff = inventory.diff(&expected);
+for entry in &diff.orphaned {
+    println!("not in the IdP: {} {}", entry.rpid, hex::encode(&entry.credential_id));
+}
+```
+*/
+use super::credential_management_command::SubCommand;
+use super::credential_management_params::{
+    Credential, CredentialProtectionPolicy, CredentialsCount, Rp,
+};
+use super::{CredentialIter, RpIter};
+use crate::fidokey::large_blobs::large_blobs_response;
+use crate::fidokey::FidoKeyHid;
+use anyhow::{anyhow, Result};
+use serde_cbor::Value;
+
+// the device row, then one row per credential
+const CSV_DEVICE_HEADER: &str = "aaguid,serial_number";
+const CSV_HEADER: &str = concat!(
+    "rpid,rp_name,user_id,user_name,user_display_name,",
+    "credential_id,public_key,cred_protect,large_blob"
+);
+
+/// Snapshot of the discoverable credentials of an authenticator
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CredentialInventory {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::hex"))]
+    pub aaguid: Vec<u8>,
+    pub serial_number: Option<String>,
+    pub entries: Vec<InventoryEntry>,
+}
+
+/// A discoverable credential
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InventoryEntry {
+    pub rpid: String,
+    pub rp_name: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
+    pub user_id: Vec<u8>,
+    pub user_name: String,
+    pub user_display_name: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
+    pub credential_id: Vec<u8>,
+    /// Public key (DER)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
+    pub public_key: Vec<u8>,
+    /// credProtect level (0: unknown)
+    pub cred_protect: u32,
+    /// A large blob of the credential is stored
+    pub large_blob: bool,
+}
+
+impl InventoryEntry {
+    // `large_blobs`: elements of the large-blob array
+    pub(crate) fn new(rp: &Rp, credential: &Credential, large_blobs: &[Value]) -> Self {
+        Self {
+            rpid: rp.public_key_credential_rp_entity.id.clone(),
+            rp_name: rp.public_key_credential_rp_entity.name.clone(),
+            user_id: credential.public_key_credential_user_entity.id.clone(),
+            user_name: credential.public_key_credential_user_entity.name.clone(),
+            user_display_name: credential
+                .public_key_credential_user_entity
+                .display_name
+                .clone(),
+            credential_id: credential.public_key_credential_descriptor.id.clone(),
+            public_key: credential.public_key.der.clone(),
+            cred_protect: match credential.cred_protect {
+                CredentialProtectionPolicy::Unknown => 0,
+                CredentialProtectionPolicy::UserVerificationOptional => 1,
+                CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList => 2,
+                CredentialProtectionPolicy::UserVerificationRequired => 3,
+            },
+            large_blob: !credential.large_blob_key.is_empty()
+                && large_blobs.iter().any(|entry| {
+                    matches!(
+                        large_blobs_response::decrypt_large_blob(entry, &credential.large_blob_key),
+                        Some(Ok(_))
+                    )
+                }),
+        }
+    }
+}
+
+/// A credential that should be on the authenticator (ex. registered in the IdP)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExpectedCredential {
+    pub rpid: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
+    pub credential_id: Vec<u8>,
+    /// Checked only if it is set
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::serde_ext::base64url_option")
+    )]
+    pub user_id: Option<Vec<u8>>,
+}
+
+impl From<&InventoryEntry> for ExpectedCredential {
+    fn from(entry: &InventoryEntry) -> Self {
+        Self {
+            rpid: entry.rpid.clone(),
+            credential_id: entry.credential_id.clone(),
+            user_id: Some(entry.user_id.clone()),
+        }
+    }
+}
+
+impl ExpectedCredential {
+    fn matches(&self, entry: &InventoryEntry) -> bool {
+        self.rpid == entry.rpid
+            && self.credential_id == entry.credential_id
+            && self
+                .user_id
+                .as_ref()
+                .map_or(true, |id| *id == entry.user_id)
+    }
+}
+
+/// Result of `CredentialInventory::diff`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InventoryDiff {
+    /// On the authenticator but not expected
+    pub orphaned: Vec<InventoryEntry>,
+    /// Expected but not on the authenticator
+    pub missing: Vec<ExpectedCredential>,
+}
+
+impl InventoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.orphaned.is_empty() && self.missing.is_empty()
+    }
+}
+
+impl CredentialInventory {
+    /// Compare with the expected credentials
+    pub fn diff(&self, expected: &[ExpectedCredential]) -> InventoryDiff {
+        InventoryDiff {
+            orphaned: self
+                .entries
+                .iter()
+                .filter(|entry| !expected.iter().any(|e| e.matches(entry)))
+                .cloned()
+                .collect(),
+            missing: expected
+                .iter()
+                .filter(|e| !self.entries.iter().any(|entry| e.matches(entry)))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The AAGUID and the serial number, then one row per credential.
+    /// Byte strings are lowercase hex.
+    /// Text starting with `=`, `+`, `-` or `@` is prefixed with `'`
+    /// so that spreadsheets do not evaluate it as a formula.
+    pub fn to_csv(&self) -> String {
+        let device = [
+            hex::encode(&self.aaguid),
+            csv_text(self.serial_number.as_deref().unwrap_or_default()),
+        ];
+        let mut csv = format!("{CSV_DEVICE_HEADER}\n{}\n{CSV_HEADER}\n", csv_row(&device));
+
+        for entry in &self.entries {
+            let row = [
+                csv_text(&entry.rpid),
+                csv_text(&entry.rp_name),
+                hex::encode(&entry.user_id),
+                csv_text(&entry.user_name),
+                csv_text(&entry.user_display_name),
+                hex::encode(&entry.credential_id),
+                hex::encode(&entry.public_key),
+                entry.cred_protect.to_string(),
+                entry.large_blob.to_string(),
+            ];
+            csv.push_str(&csv_row(&row));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Read the output of `to_csv`
+    pub fn from_csv(csv: &str) -> Result<Self> {
+        let mut rows = parse_csv(csv)?.into_iter();
+        match rows.next() {
+            Some(header) if header.join(",") == CSV_DEVICE_HEADER => {}
+            _ => return Err(anyhow!("Invalid inventory CSV header")),
+        }
+
+        let mut inventory = Self::default();
+        match rows.next() {
+            Some(row) if row.len() == 2 => {
+                inventory.aaguid = hex::decode(&row[0])?;
+                inventory.serial_number = Some(from_csv_text(&row[1])).filter(|s| !s.is_empty());
+            }
+            _ => return Err(anyhow!("Invalid inventory CSV device row")),
+        }
+
+        match rows.next() {
+            Some(header) if header.join(",") == CSV_HEADER => {}
+            _ => return Err(anyhow!("Invalid inventory CSV header")),
+        }
+
+        for (i, row) in rows.enumerate() {
+            if row.len() != CSV_HEADER.split(',').count() {
+                return Err(anyhow!("Invalid inventory CSV row {}", i + 1));
+            }
+
+            // in the order of CSV_HEADER
+            let mut fields = row.into_iter();
+            let mut field = || fields.next().unwrap_or_default();
+
+            inventory.entries.push(InventoryEntry {
+                rpid: from_csv_text(&field()),
+                rp_name: from_csv_text(&field()),
+                user_id: hex::decode(field())?,
+                user_name: from_csv_text(&field()),
+                user_display_name: from_csv_text(&field()),
+                credential_id: hex::decode(field())?,
+                public_key: hex::decode(field())?,
+                cred_protect: field().parse()?,
+                large_blob: field().parse()?,
+            });
+        }
+        Ok(inventory)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+impl FidoKeyHid {
+    /// Snapshot of the discoverable credentials with the AAGUID and the serial number.
+    /// The credentials are read with one pinUvAuthToken,
+    /// the large-blob array is read once if a credential has a largeBlobKey.
+    pub fn credential_inventory(&self, pin: Option<&str>) -> Result<CredentialInventory> {
+        let info = self.get_info()?;
+        let mut inventory = CredentialInventory {
+            aaguid: info.aaguid,
+            serial_number: self.serial_number(),
+            entries: vec![],
+        };
+
+        let session = self.credential_management_session(pin)?;
+        let count = CredentialsCount::new(&session.send(&SubCommand::GetCredsMetadata)?);
+        if count.existing_resident_credentials_count == 0 {
+            return Ok(inventory);
+        }
+
+        let mut credentials = vec![];
+        let rps: Vec<Rp> = RpIter::begin(session.clone())?.collect::<Result<_>>()?;
+        for rp in rps {
+            for credential in CredentialIter::begin(session.clone(), &rp.rpid_hash)? {
+                credentials.push((rp.clone(), credential?));
+            }
+        }
+
+        let large_blobs = if credentials
+            .iter()
+            .any(|(_, credential)| !credential.large_blob_key.is_empty())
+        {
+            self.get_large_blob_entries()?
+        } else {
+            vec![]
+        };
+
+        inventory.entries = credentials
+            .iter()
+            .map(|(rp, credential)| InventoryEntry::new(rp, credential, &large_blobs))
+            .collect();
+        Ok(inventory)
+    }
+}
+
+fn csv_row(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    fields.join(",")
+}
+
+// Text that a spreadsheet would evaluate as a formula is prefixed with `'`
+// (and so is text starting with `'`, to read it back unchanged)
+fn csv_text(text: &str) -> String {
+    if text.starts_with(['=', '+', '-', '@', '\t', '\r', '\'']) {
+        format!("'{text}")
+    } else {
+        text.to_string()
+    }
+}
+
+fn from_csv_text(field: &str) -> String {
+    field.strip_prefix('\'').unwrap_or(field).to_string()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// RFC 4180
+fn parse_csv(csv: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("Unterminated quoted CSV field"));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inventory() -> CredentialInventory {
+        CredentialInventory {
+            aaguid: vec![0xee; 16],
+            serial_number: Some("12345678".to_string()),
+            entries: vec![
+                InventoryEntry {
+                    rpid: "example.com".to_string(),
+                    rp_name: "Example, Inc.".to_string(),
+                    user_id: vec![0x01],
+                    user_name: "alice \"admin\"".to_string(),
+                    user_display_name: "Alice".to_string(),
+                    credential_id: vec![0xc1; 16],
+                    public_key: vec![0x30, 0x59],
+                    cred_protect: 2,
+                    large_blob: true,
+                },
+                InventoryEntry {
+                    rpid: "example.org".to_string(),
+                    user_id: vec![0x02],
+                    credential_id: vec![0xc2; 16],
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_csv_roundtrip() {
+        let inventory = inventory();
+        let csv = inventory.to_csv();
+        assert!(csv.starts_with(&format!(
+            "{CSV_DEVICE_HEADER}\n{},12345678\n{CSV_HEADER}\n",
+            "ee".repeat(16)
+        )));
+        assert_eq!(CredentialInventory::from_csv(&csv).unwrap(), inventory);
+
+        assert!(CredentialInventory::from_csv("rpid\nexample.com\n").is_err());
+        let device = format!("{CSV_DEVICE_HEADER}\nee,\n");
+        assert!(CredentialInventory::from_csv(&device).is_err());
+        assert!(CredentialInventory::from_csv(&format!("{device}{CSV_HEADER}\na,b\n")).is_err());
+    }
+
+    #[test]
+    fn test_csv_empty_inventory() {
+        let inventory = CredentialInventory {
+            entries: vec![],
+            ..inventory()
+        };
+        let csv = inventory.to_csv();
+        assert_eq!(CredentialInventory::from_csv(&csv).unwrap(), inventory);
+    }
+
+    #[test]
+    fn test_csv_formula() {
+        let mut inventory = inventory();
+        inventory.entries[0].rp_name = "=HYPERLINK(\"http://evil\")".to_string();
+        inventory.entries[0].user_name = "@SUM(1)".to_string();
+        inventory.entries[1].rpid = "+1".to_string();
+        inventory.entries[1].user_display_name = "'-1".to_string();
+
+        let csv = inventory.to_csv();
+        for line in csv.lines() {
+            for field in line.split(',') {
+                let field = field.trim_start_matches('"');
+                assert!(!field.starts_with(['=', '+', '-', '@']), "{}", line);
+            }
+        }
+        assert_eq!(CredentialInventory::from_csv(&csv).unwrap(), inventory);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_roundtrip() {
+        let inventory = inventory();
+        let json = inventory.to_json().unwrap();
+        assert_eq!(CredentialInventory::from_json(&json).unwrap(), inventory);
+
+        // the same encoding as the inventory
+        let expected: Vec<ExpectedCredential> = inventory.entries.iter().map(Into::into).collect();
+        let json = serde_json::to_value(&expected).unwrap();
+        assert_eq!(json[0]["credential_id"], "wcHBwcHBwcHBwcHBwcHBwQ");
+        assert_eq!(json[0]["user_id"], "AQ");
+        let expected2: Vec<ExpectedCredential> = serde_json::from_value(json).unwrap();
+        assert_eq!(expected2, expected);
+
+        // user_id may be omitted
+        let json = r#"{"rpid":"example.com","credential_id":"wcHBwcHBwcHBwcHBwcHBwQ"}"#;
+        let expected: ExpectedCredential = serde_json::from_str(json).unwrap();
+        assert_eq!(expected.user_id, None);
+        assert!(inventory.diff(&[expected]).orphaned.len() == 1);
+    }
+
+    #[test]
+    fn test_large_blob_presence() {
+        let key1 = vec![0x01; 32];
+        let key2 = vec![0x02; 32];
+        let large_blobs = vec![
+            crate::fidokey::large_blobs::large_blobs_command::create_large_blob(&key1, b"blob")
+                .unwrap(),
+        ];
+
+        let rp = Rp::default();
+        let mut credential = Credential::default();
+        assert!(!InventoryEntry::new(&rp, &credential, &large_blobs).large_blob);
+
+        // a largeBlobKey without a large blob
+        credential.large_blob_key = key2;
+        assert!(!InventoryEntry::new(&rp, &credential, &large_blobs).large_blob);
+        assert!(!InventoryEntry::new(&rp, &credential, &[]).large_blob);
+
+        credential.large_blob_key = key1;
+        assert!(InventoryEntry::new(&rp, &credential, &large_blobs).large_blob);
+    }
+
+    #[test]
+    fn test_diff() {
+        let inventory = inventory();
+
+        let expected = vec![
+            ExpectedCredential {
+                rpid: "example.com".to_string(),
+                credential_id: vec![0xc1; 16],
+                user_id: None,
+            },
+            ExpectedCredential {
+                rpid: "example.net".to_string(),
+                credential_id: vec![0xc3; 16],
+                user_id: None,
+            },
+        ];
+        let diff = inventory.diff(&expected);
+        assert_eq!(diff.orphaned, vec![inventory.entries[1].clone()]);
+        assert_eq!(diff.missing, vec![expected[1].clone()]);
+
+        // user handle mismatch
+        let expected = [ExpectedCredential {
+            user_id: Some(vec![0x09]),
+            ..expected[0].clone()
+        }];
+        assert_eq!(inventory.diff(&expected).orphaned.len(), 2);
+
+        // against another snapshot
+        let expected: Vec<ExpectedCredential> = inventory.entries.iter().map(Into::into).collect();
+        assert!(inventory.diff(&expected).is_empty());
+    }
+}
@@ -37,7 +37,7 @@ impl SubCommandBase for SubCommand {
 }
 
 pub fn create_payload(
-    pin_token: Option<pintoken::PinToken>,
+    pin_token: Option<&pintoken::PinToken>,
     sub_command: &SubCommand,
     use_pre_credential_management: bool,
 ) -> Result<Vec<u8>> {
@@ -164,3 +164,50 @@ fn create_public_key_credential_descriptor_pend(
 
     Value::Map(param)
 }
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_user_information_payload() {
+        let pin_token = pintoken::PinToken::new(&[0x11; 32]);
+        let pkcd = PublicKeyCredentialDescriptor {
+            id: vec![0xcc; 16],
+            ctype: "public-key".to_string(),
+        };
+        let pkcue = PublicKeyCredentialUserEntity {
+            id: vec![0x01, 0x02],
+            name: "alice".to_string(),
+            display_name: "Alice".to_string(),
+        };
+        let sub_command = SubCommand::UpdateUserInformation(pkcd.clone(), pkcue.clone());
+
+        let payload = create_payload(Some(&pin_token), &sub_command, false).unwrap();
+        assert_eq!(payload[0], ctapdef::AUTHENTICATOR_CREDENTIAL_MANAGEMENT);
+
+        let map = match serde_cbor::from_slice(&payload[1..]).unwrap() {
+            Value::Map(map) => map,
+            _ => panic!("not a map"),
+        };
+        assert_eq!(map[&Value::Integer(0x01)], Value::Integer(0x07));
+        assert_eq!(map[&Value::Integer(0x03)], Value::Integer(1));
+
+        // pinUvAuthParam = authenticate(pinUvAuthToken, updateUserInformation (0x07) || subCommandParams)
+        let params = &map[&Value::Integer(0x02)];
+        assert_eq!(
+            *params,
+            create_public_key_credential_descriptor_pend(&pkcd, &pkcue)
+        );
+        let mut message = vec![0x07];
+        message.append(&mut to_vec(params).unwrap());
+        let sig = enc_hmac_sha_256::authenticate(&pin_token.key, &message);
+        assert_eq!(
+            map[&Value::Integer(0x04)],
+            Value::Bytes(sig[0..16].to_vec())
+        );
+    }
+}
@@ -3,6 +3,7 @@ use crate::public_key_credential_descriptor::PublicKeyCredentialDescriptor;
 use crate::public_key_credential_rp_entity::PublicKeyCredentialRpEntity;
 use crate::public_key_credential_user_entity::PublicKeyCredentialUserEntity;
 use crate::str_buf::StrBuf;
+use ring::digest;
 use std::fmt;
 
 #[derive(Debug, Default, Clone)]
@@ -18,8 +19,10 @@ pub(crate) struct CredentialManagementData {
     pub total_credentials: u32,
     pub cred_protect: u32,
     pub large_blob_key: Vec<u8>,
+    pub third_party_payment: Option<bool>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct CredentialsCount {
     pub existing_resident_credentials_count: u32,
@@ -50,16 +53,21 @@ impl fmt::Display for CredentialsCount {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct Rp {
     pub public_key_credential_rp_entity: PublicKeyCredentialRpEntity,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub rpid_hash: Vec<u8>,
+    /// Number of RPs on the authenticator (totalRPs)
+    pub total_rps: u32,
 }
 impl Rp {
-    pub(crate) fn new(meta: &CredentialManagementData) -> Self {
+    pub(crate) fn new(meta: &CredentialManagementData, total_rps: u32) -> Self {
         Self {
             public_key_credential_rp_entity: meta.public_key_credential_rp_entity.clone(),
             rpid_hash: meta.rpid_hash.clone(),
+            total_rps,
         }
     }
 }
@@ -71,12 +79,14 @@ impl fmt::Display for Rp {
                 "- public_key_credential_rp_entity",
                 &self.public_key_credential_rp_entity,
             )
-            .appenh("- rpid_hash", &self.rpid_hash);
+            .appenh("- rpid_hash", &self.rpid_hash)
+            .append("- total_rps", &self.total_rps);
         write!(f, "{}", strbuf.build())
     }
 }
 
 #[allow(dead_code)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Copy, Clone, Default)]
 pub enum CredentialProtectionPolicy {
     #[default]
@@ -97,20 +107,31 @@ impl From<u32> for CredentialProtectionPolicy {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct Credential {
     pub public_key_credential_user_entity: PublicKeyCredentialUserEntity,
     pub public_key_credential_descriptor: PublicKeyCredentialDescriptor,
     pub public_key: PublicKey,
     pub cred_protect: CredentialProtectionPolicy,
+    /// largeBlobKey of the credential (empty if it has none)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
+    pub large_blob_key: Vec<u8>,
+    /// thirdPartyPayment extension (None if not reported)
+    pub third_party_payment: Option<bool>,
+    /// Number of credentials of the RP (totalCredentials)
+    pub total_credentials: u32,
 }
 impl Credential {
-    pub(crate) fn new(d: &CredentialManagementData) -> Self {
+    pub(crate) fn new(d: &CredentialManagementData, total_credentials: u32) -> Self {
         Self {
             public_key_credential_user_entity: d.public_key_credential_user_entity.clone(),
             public_key_credential_descriptor: d.public_key_credential_descriptor.clone(),
             public_key: d.public_key.clone(),
             cred_protect: d.cred_protect.into(),
+            large_blob_key: d.large_blob_key.clone(),
+            third_party_payment: d.third_party_payment,
+            total_credentials,
         }
     }
 }
@@ -127,7 +148,172 @@ impl fmt::Display for Credential {
                 &self.public_key_credential_descriptor,
             )
             .append("- public_key", &self.public_key)
-            .append("- cred_protect", &format!("{:?}", self.cred_protect));
+            .append("- cred_protect", &format!("{:?}", self.cred_protect))
+            .appenh("- large_blob_key", &self.large_blob_key)
+            .append(
+                "- third_party_payment",
+                &format!("{:?}", self.third_party_payment),
+            )
+            .append("- total_credentials", &self.total_credentials);
         write!(f, "{}", strbuf.build())
     }
 }
+
+/// Credentials to be deleted by `credential_management_delete_credentials`.
+/// All the conditions that are set must match.
+/// A selector without conditions is rejected unless `all` is set.
+#[derive(Debug, Clone, Default)]
+pub struct CredentialSelector {
+    /// RP ID
+    pub rpid: Option<String>,
+    /// User handle
+    pub user_id: Option<Vec<u8>>,
+    /// Pattern of the user name (`*` and `?` wildcards). displayName is not matched.
+    pub user_name: Option<String>,
+    /// Select every credential when no condition is set
+    pub all: bool,
+}
+
+impl CredentialSelector {
+    /// Every credential on the authenticator
+    pub fn all() -> Self {
+        Self {
+            all: true,
+            ..Default::default()
+        }
+    }
+
+    /// Every credential of the RP
+    pub fn rp(rpid: &str) -> Self {
+        Self {
+            rpid: Some(rpid.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn user_id(user_id: &[u8]) -> Self {
+        Self {
+            user_id: Some(user_id.to_vec()),
+            ..Default::default()
+        }
+    }
+
+    pub fn user_name(pattern: &str) -> Self {
+        Self {
+            user_name: Some(pattern.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// No condition is set
+    pub fn is_empty(&self) -> bool {
+        self.rpid.is_none() && self.user_id.is_none() && self.user_name.is_none()
+    }
+
+    pub fn matches_rp(&self, rp: &Rp) -> bool {
+        self.rpid.as_ref().map_or(true, |rpid| {
+            rp.public_key_credential_rp_entity.id == *rpid
+                || digest::digest(&digest::SHA256, rpid.as_bytes()).as_ref() == rp.rpid_hash
+        })
+    }
+
+    pub fn matches(&self, rp: &Rp, credential: &Credential) -> bool {
+        let user = &credential.public_key_credential_user_entity;
+        self.matches_rp(rp)
+            && self.user_id.as_ref().map_or(true, |id| user.id == *id)
+            && self
+                .user_name
+                .as_ref()
+                .map_or(true, |pattern| wildcard_match(pattern, &user.name))
+    }
+}
+
+// `*`: any string, `?`: any character
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text position it matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("alice", "alice"));
+        assert!(!wildcard_match("alice", "alice2"));
+        assert!(wildcard_match("alice*", "alice2"));
+        assert!(wildcard_match("*@example.com", "bob@example.com"));
+        assert!(!wildcard_match("*@example.com", "bob@example.org"));
+        assert!(wildcard_match("b?b*", "bob@example.com"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("a*b*c", "axxbyyc"));
+        assert!(!wildcard_match("a*b*c", "axxbyy"));
+    }
+
+    #[test]
+    fn test_credential_selector() {
+        let rp = Rp {
+            public_key_credential_rp_entity: PublicKeyCredentialRpEntity {
+                id: "example.com".to_string(),
+                name: String::new(),
+            },
+            rpid_hash: digest::digest(&digest::SHA256, b"example.com")
+                .as_ref()
+                .to_vec(),
+            total_rps: 1,
+        };
+        let credential = Credential {
+            public_key_credential_user_entity: PublicKeyCredentialUserEntity {
+                id: vec![0x01, 0x02],
+                name: "alice@example.com".to_string(),
+                display_name: "Alice".to_string(),
+            },
+            ..Default::default()
+        };
+
+        assert!(CredentialSelector::default().matches(&rp, &credential));
+        assert!(CredentialSelector::rp("example.com").matches(&rp, &credential));
+        assert!(!CredentialSelector::rp("example.org").matches(&rp, &credential));
+        assert!(CredentialSelector::user_id(&[0x01, 0x02]).matches(&rp, &credential));
+        assert!(!CredentialSelector::user_id(&[0x01]).matches(&rp, &credential));
+        assert!(CredentialSelector::user_name("ali*").matches(&rp, &credential));
+        assert!(!CredentialSelector::user_name("bob*").matches(&rp, &credential));
+        // displayName is not matched
+        assert!(!CredentialSelector::user_name("Ali*").matches(&rp, &credential));
+
+        assert!(CredentialSelector::default().is_empty());
+        assert!(CredentialSelector::all().is_empty());
+        assert!(!CredentialSelector::rp("example.com").is_empty());
+
+        let selector = CredentialSelector {
+            rpid: Some("example.com".to_string()),
+            user_name: Some("*@example.com".to_string()),
+            ..Default::default()
+        };
+        assert!(selector.matches(&rp, &credential));
+    }
+}
@@ -44,9 +44,44 @@ pub(crate) fn parse_cbor(
                 0x09 => data.total_credentials = util::cbor_value_to_num(val)?,
                 0x0A => data.cred_protect = util::cbor_value_to_num(val)?,
                 0x0B => data.large_blob_key = util::cbor_value_to_vec_u8(val)?,
+                0x0C => {
+                    if let Value::Bool(b) = val {
+                        data.third_party_payment = Some(*b);
+                    }
+                }
                 _ => println!("parse_cbor_member - unknown member {member:?}"),
             }
         }
     }
     Ok(data)
 }
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_parse_credential() {
+        let mut map = BTreeMap::new();
+        map.insert(Value::Integer(0x09), Value::Integer(3));
+        map.insert(Value::Integer(0x0A), Value::Integer(2));
+        map.insert(Value::Integer(0x0B), Value::Bytes(vec![0xaa; 32]));
+        map.insert(Value::Integer(0x0C), Value::Bool(true));
+        let bytes = serde_cbor::to_vec(&Value::Map(map)).unwrap();
+
+        let data = parse_cbor(&bytes).unwrap();
+        assert_eq!(data.total_credentials, 3);
+        assert_eq!(data.cred_protect, 2);
+        assert_eq!(data.large_blob_key, vec![0xaa; 32]);
+        assert_eq!(data.third_party_payment, Some(true));
+
+        let credential = credential_management_params::Credential::new(&data, 3);
+        assert_eq!(credential.large_blob_key, vec![0xaa; 32]);
+        assert_eq!(credential.third_party_payment, Some(true));
+        assert_eq!(credential.total_credentials, 3);
+    }
+}
@@ -1,15 +1,27 @@
+pub mod credential_inventory;
 pub mod credential_management_command;
 pub mod credential_management_params;
 pub mod credential_management_response;
-use super::{pin::Permission::CredentialManagement, FidoKeyHid};
+use super::{
+    get_info::InfoOption,
+    pin::{
+        Permission::{CredentialManagement, PersistentCredentialManagementReadOnly},
+        PersistentTokenStore,
+    },
+    sub_command_base::SubCommandBase,
+    FidoKeyHid,
+};
 use crate::{
-    ctaphid, public_key_credential_descriptor::PublicKeyCredentialDescriptor,
+    ctapdef, ctaphid, error::CtapHidError, pintoken::PinToken,
+    public_key_credential_descriptor::PublicKeyCredentialDescriptor,
     public_key_credential_user_entity::PublicKeyCredentialUserEntity, util,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use {
     credential_management_command::SubCommand,
-    credential_management_params::{Credential, CredentialManagementData, CredentialsCount, Rp},
+    credential_management_params::{
+        Credential, CredentialManagementData, CredentialSelector, CredentialsCount, Rp,
+    },
 };
 
 impl FidoKeyHid {
@@ -24,18 +36,7 @@ impl FidoKeyHid {
 
     /// `CredentialManagement` - `enumerateRPsBegin` & `enumerateRPsNext` (CTAP 2.1-PRE)
     pub fn credential_management_enumerate_rps(&self, pin: Option<&str>) -> Result<Vec<Rp>> {
-        let mut datas: Vec<Rp> = Vec::new();
-        let data = self.credential_management(pin, &SubCommand::EnumerateRPsBegin)?;
-
-        if data.total_rps > 0 {
-            datas.push(Rp::new(&data));
-            let roop_n = data.total_rps - 1;
-            for _ in 0..roop_n {
-                let data = self.credential_management(pin, &SubCommand::EnumerateRPsGetNextRp)?;
-                datas.push(Rp::new(&data));
-            }
-        }
-        Ok(datas)
+        self.credential_management_rps(pin)?.collect()
     }
 
     /// `CredentialManagement` - `enumerateCredentialsBegin` & `enumerateCredentialsNext` (CTAP 2.1-PRE)
@@ -44,25 +45,24 @@ impl FidoKeyHid {
         pin: Option<&str>,
         rpid_hash: &[u8],
     ) -> Result<Vec<credential_management_params::Credential>> {
-        let mut datas: Vec<Credential> = Vec::new();
+        self.credential_management_credentials(pin, rpid_hash)?
+            .collect()
+    }
 
-        let data = self.credential_management(
-            pin,
-            &SubCommand::EnumerateCredentialsBegin(rpid_hash.to_vec()),
-        )?;
+    /// Iterate over the RPs with one pinUvAuthToken.
+    /// The next RP is read from the authenticator when the iterator advances.
+    pub fn credential_management_rps(&self, pin: Option<&str>) -> Result<RpIter<'_>> {
+        RpIter::begin(self.credential_management_session(pin)?)
+    }
 
-        datas.push(Credential::new(&data));
-        if data.total_credentials > 0 {
-            let roop_n = data.total_credentials - 1;
-            for _ in 0..roop_n {
-                let data = self.credential_management(
-                    pin,
-                    &SubCommand::EnumerateCredentialsGetNextCredential(rpid_hash.to_vec()),
-                )?;
-                datas.push(Credential::new(&data));
-            }
-        }
-        Ok(datas)
+    /// Iterate over the credentials of an RP with one pinUvAuthToken.
+    /// The next credential is read from the authenticator when the iterator advances.
+    pub fn credential_management_credentials(
+        &self,
+        pin: Option<&str>,
+        rpid_hash: &[u8],
+    ) -> Result<CredentialIter<'_>> {
+        CredentialIter::begin(self.credential_management_session(pin)?, rpid_hash)
     }
 
     /// `CredentialManagement` - deleteCredential (CTAP 2.1-PRE)
@@ -75,28 +75,161 @@ impl FidoKeyHid {
         Ok(())
     }
 
-    /// `CredentialManagement` - `updateUserInformation` (CTAP 2.1-PRE)
+    /// `CredentialManagement` - `updateUserInformation` (CTAP 2.1)
+    /// `pkcue.id` must be the user handle of the credential.
     pub fn credential_management_update_user_information(
         &self,
         pin: Option<&str>,
         pkcd: PublicKeyCredentialDescriptor,
         pkcue: PublicKeyCredentialUserEntity,
     ) -> Result<()> {
+        // not defined in CTAP 2.1-PRE (credentialMgmtPreview)
+        if self.is_pre_credential_management()? {
+            return Err(anyhow!(
+                "updateUserInformation is not supported by CTAP 2.1-PRE credential management"
+            ));
+        }
         self.credential_management(pin, &SubCommand::UpdateUserInformation(pkcd, pkcue))?;
         Ok(())
     }
 
+    /// Delete the credentials that match `selector` with one pinUvAuthToken.
+    /// Returns the deleted credentials (with `dry_run`, the credentials that would be deleted).
+    /// Deleting every credential requires `CredentialSelector::all()`.
+    pub fn credential_management_delete_credentials(
+        &self,
+        pin: Option<&str>,
+        selector: &CredentialSelector,
+        dry_run: bool,
+    ) -> Result<Vec<(Rp, Credential)>> {
+        if selector.is_empty() && !selector.all {
+            return Err(anyhow!(
+                "Empty credential selector (use CredentialSelector::all() to delete all)"
+            ));
+        }
+
+        let session = self.credential_management_session(pin)?;
+
+        // the enumeration is finished before deleting
+        let rps: Vec<Rp> = RpIter::begin(session.clone())?
+            .filter(|rp| rp.as_ref().map_or(true, |rp| selector.matches_rp(rp)))
+            .collect::<Result<_>>()?;
+
+        let mut targets = vec![];
+        for rp in rps {
+            for credential in CredentialIter::begin(session.clone(), &rp.rpid_hash)? {
+                let credential = credential?;
+                if selector.matches(&rp, &credential) {
+                    targets.push((rp.clone(), credential));
+                }
+            }
+        }
+
+        if !dry_run {
+            for (_, credential) in &targets {
+                session.send(&SubCommand::DeleteCredential(
+                    credential.public_key_credential_descriptor.clone(),
+                ))?;
+            }
+        }
+
+        Ok(targets)
+    }
+
+    /// Get a persistent pinUvAuthToken with the `perCredMgmtRO` permission (CTAP 2.2)
+    /// and save it in `store`. The token stays valid until the PIN is changed.
+    pub fn obtain_persistent_pin_token(
+        &self,
+        store: &PersistentTokenStore,
+        pin: &str,
+    ) -> Result<()> {
+        if self.enable_info_option(&InfoOption::PerCredMgmtRO)? != Some(true) {
+            return Err(anyhow!(
+                "perCredMgmtRO is not supported by this authenticator"
+            ));
+        }
+
+        let cid = ctaphid::ctaphid_init(self)?;
+        let pin_token = self.get_pinuv_auth_token_with_permission(
+            &cid,
+            pin,
+            PersistentCredentialManagementReadOnly,
+        )?;
+        store.save(&self.persistent_token_id()?, &pin_token)
+    }
+
+    /// `getCredsMetadata` with the persistent pinUvAuthToken in `store` (CTAP 2.2)
+    pub fn credential_management_get_creds_metadata_persistent(
+        &self,
+        store: &PersistentTokenStore,
+    ) -> Result<CredentialsCount> {
+        self.persistent_credential_management(store, |session| {
+            let meta = session.send(&SubCommand::GetCredsMetadata)?;
+            Ok(CredentialsCount::new(&meta))
+        })
+    }
+
+    /// `enumerateRPs` with the persistent pinUvAuthToken in `store` (CTAP 2.2)
+    pub fn credential_management_enumerate_rps_persistent(
+        &self,
+        store: &PersistentTokenStore,
+    ) -> Result<Vec<Rp>> {
+        self.persistent_credential_management(store, |session| RpIter::begin(session)?.collect())
+    }
+
+    /// `enumerateCredentials` with the persistent pinUvAuthToken in `store` (CTAP 2.2)
+    pub fn credential_management_enumerate_credentials_persistent(
+        &self,
+        store: &PersistentTokenStore,
+        rpid_hash: &[u8],
+    ) -> Result<Vec<Credential>> {
+        self.persistent_credential_management(store, |session| {
+            CredentialIter::begin(session, rpid_hash)?.collect()
+        })
+    }
+
+    // The stored token is removed when the authenticator rejects it (the PIN has been changed)
+    fn persistent_credential_management<T>(
+        &self,
+        store: &PersistentTokenStore,
+        f: impl FnOnce(CredentialManagementSession<'_>) -> Result<T>,
+    ) -> Result<T> {
+        let device_id = self.persistent_token_id()?;
+        let pin_token = store
+            .load(&device_id)?
+            .ok_or(CtapHidError::PersistentTokenMissing)?;
+
+        let session = CredentialManagementSession {
+            device: self,
+            cid: ctaphid::ctaphid_init(self)?,
+            pin_token: Some(pin_token),
+            // perCredMgmtRO is not defined in CTAP 2.1-PRE
+            use_pre_credential_management: false,
+        };
+
+        store.check_invalidated(&device_id, f(session))
+    }
+
     fn credential_management(
         &self,
         pin: Option<&str>,
         sub_command: &SubCommand,
     ) -> Result<CredentialManagementData> {
+        self.credential_management_session(pin)?.send(sub_command)
+    }
+
+    fn credential_management_session(
+        &self,
+        pin: Option<&str>,
+    ) -> Result<CredentialManagementSession<'_>> {
+        let use_pre_credential_management = self.is_pre_credential_management()?;
+
         let cid = ctaphid::ctaphid_init(self)?;
 
         // pin token
         let pin_token = {
             if let Some(pin) = pin {
-                if self.use_pre_credential_management {
+                if use_pre_credential_management {
                     Some(self.get_pin_token(&cid, pin)?)
                 } else {
                     Some(self.get_pinuv_auth_token_with_permission(
@@ -110,22 +243,152 @@ impl FidoKeyHid {
             }
         };
 
+        Ok(CredentialManagementSession {
+            device: self,
+            cid,
+            pin_token,
+            use_pre_credential_management,
+        })
+    }
+}
+
+// Subcommands sent on one channel with one pinUvAuthToken
+#[derive(Clone)]
+struct CredentialManagementSession<'a> {
+    device: &'a FidoKeyHid,
+    cid: [u8; 4],
+    pin_token: Option<PinToken>,
+    use_pre_credential_management: bool,
+}
+
+impl CredentialManagementSession<'_> {
+    fn send(&self, sub_command: &SubCommand) -> Result<CredentialManagementData> {
+        let command = if self.use_pre_credential_management {
+            ctapdef::AUTHENTICATOR_CREDENTIAL_MANAGEMENT_P
+        } else {
+            ctapdef::AUTHENTICATOR_CREDENTIAL_MANAGEMENT
+        };
+        self.device
+            .quirks()
+            .check_sub_command(command, sub_command.id()?)?;
+
+        // enumerateRPsGetNextRp and enumerateCredentialsGetNextCredential are not authenticated
+        let pin_token = match sub_command {
+            SubCommand::EnumerateRPsGetNextRp
+            | SubCommand::EnumerateCredentialsGetNextCredential(_) => None,
+            _ => self.pin_token.as_ref(),
+        };
+
         let send_payload = credential_management_command::create_payload(
             pin_token,
             sub_command,
             self.use_pre_credential_management,
         )?;
 
-        if self.enable_log {
+        if self.device.enable_log {
             println!("send(cbor) = {}", util::to_hex_str(&send_payload));
         }
 
-        let response_cbor = ctaphid::ctaphid_cbor(self, &cid, &send_payload)?;
+        let response_cbor = ctaphid::ctaphid_cbor(self.device, &self.cid, &send_payload)?;
 
-        if self.enable_log {
+        if self.device.enable_log {
             println!("response(cbor) = {}", util::to_hex_str(&response_cbor));
         }
 
         credential_management_response::parse_cbor(&response_cbor)
     }
 }
+
+/// Iterator over the RPs (see `FidoKeyHid::credential_management_rps`)
+pub struct RpIter<'a> {
+    session: CredentialManagementSession<'a>,
+    first: Option<CredentialManagementData>,
+    total_rps: u32,
+    remaining: u32,
+}
+
+impl<'a> RpIter<'a> {
+    fn begin(session: CredentialManagementSession<'a>) -> Result<Self> {
+        let first = session.send(&SubCommand::EnumerateRPsBegin)?;
+        Ok(Self {
+            total_rps: first.total_rps,
+            remaining: first.total_rps,
+            first: Some(first),
+            session,
+        })
+    }
+}
+
+impl Iterator for RpIter<'_> {
+    type Item = Result<Rp>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let data = match self.first.take() {
+            Some(data) => Ok(data),
+            None => self.session.send(&SubCommand::EnumerateRPsGetNextRp),
+        };
+        if data.is_err() {
+            self.remaining = 0;
+        }
+        Some(data.map(|data| Rp::new(&data, self.total_rps)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining as usize))
+    }
+}
+
+/// Iterator over the credentials of an RP (see `FidoKeyHid::credential_management_credentials`)
+pub struct CredentialIter<'a> {
+    session: CredentialManagementSession<'a>,
+    rpid_hash: Vec<u8>,
+    first: Option<CredentialManagementData>,
+    total_credentials: u32,
+    remaining: u32,
+}
+
+impl<'a> CredentialIter<'a> {
+    fn begin(session: CredentialManagementSession<'a>, rpid_hash: &[u8]) -> Result<Self> {
+        let first = session.send(&SubCommand::EnumerateCredentialsBegin(rpid_hash.to_vec()))?;
+        Ok(Self {
+            rpid_hash: rpid_hash.to_vec(),
+            total_credentials: first.total_credentials,
+            remaining: first.total_credentials.max(1),
+            first: Some(first),
+            session,
+        })
+    }
+}
+
+impl Iterator for CredentialIter<'_> {
+    type Item = Result<Credential>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let data = match self.first.take() {
+            Some(data) => Ok(data),
+            None => self
+                .session
+                .send(&SubCommand::EnumerateCredentialsGetNextCredential(
+                    self.rpid_hash.clone(),
+                )),
+        };
+        if data.is_err() {
+            self.remaining = 0;
+        }
+        Some(data.map(|data| Credential::new(&data, self.total_credentials)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining as usize))
+    }
+}
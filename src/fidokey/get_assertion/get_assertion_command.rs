@@ -2,6 +2,7 @@ use super::get_assertion_params::Extension;
 use crate::ctapdef;
 use crate::hmac_ext::HmacExt;
 use crate::util;
+use anyhow::Result;
 use serde_cbor::to_vec;
 use serde_cbor::Value;
 use std::collections::BTreeMap;
@@ -28,12 +29,11 @@ impl Params {
     }
 }
 
-#[must_use]
 pub fn create_payload(
     params: Params,
     extensions: Option<&Vec<Extension>>,
     hmac_ext: Option<HmacExt>,
-) -> Vec<u8> {
+) -> Result<Vec<u8>> {
     // 0x01 : rpid
     let rpid = Value::Text(params.rp_id.to_string());
 
@@ -72,28 +72,16 @@ pub fn create_payload(
 
         // HMAC Secret Extension
         if let Some(hmac_ext) = hmac_ext {
-            let mut param = BTreeMap::new();
-
-            // keyAgreement(0x01)
-            let val = hmac_ext.shared_secret.public_key.to_value().unwrap();
-            param.insert(Value::Integer(0x01), val);
-
-            // saltEnc(0x02)
-            param.insert(Value::Integer(0x02), Value::Bytes(hmac_ext.salt_enc));
-
-            // saltAuth(0x03)
-            param.insert(Value::Integer(0x03), Value::Bytes(hmac_ext.salt_auth));
-
             ext_val.insert(
-                Value::Text(Extension::HmacSecret(None).to_string()),
-                Value::Map(param),
+                Value::Text(Extension::HmacSecret(None, None).to_string()),
+                hmac_ext.to_value()?,
             );
         }
 
         if let Some(extensions) = extensions {
             for ext in extensions {
                 match *ext {
-                    Extension::HmacSecret(_) => (),
+                    Extension::HmacSecret(_, _) | Extension::Prf(_, _) => (),
                     Extension::LargeBlobKey((n, _)) | Extension::CredBlob((n, _)) => {
                         ext_val.insert(Value::Text(ext.to_string()), Value::Bool(n.unwrap()));
                     }
@@ -147,7 +135,7 @@ pub fn create_payload(
 
     // Command - authenticatorGetAssertion (0x02)
     let mut payload = [ctapdef::AUTHENTICATOR_GET_ASSERTION].to_vec();
-    payload.append(&mut to_vec(&cbor).unwrap());
+    payload.append(&mut to_vec(&cbor)?);
 
-    payload
+    Ok(payload)
 }
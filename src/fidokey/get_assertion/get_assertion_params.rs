@@ -1,23 +1,31 @@
 use crate::auth_data::Flags;
+use crate::hmac_ext;
 use crate::public_key_credential_user_entity::PublicKeyCredentialUserEntity;
 use crate::str_buf::StrBuf;
+use anyhow::{anyhow, Result};
 use ring::digest;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fmt;
 use strum_macros::AsRefStr;
 
 /// Assertion Object
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct Assertion {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub rpid_hash: Vec<u8>,
     pub flags: Flags,
     pub sign_count: u32,
     pub number_of_credentials: i32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub signature: Vec<u8>,
     pub user: PublicKeyCredentialUserEntity,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub credential_id: Vec<u8>,
     pub extensions: Vec<Extension>,
     // row - audh_data
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub auth_data: Vec<u8>,
     pub user_selected: bool,
 }
@@ -35,9 +43,21 @@ impl fmt::Display for Assertion {
             .appenh("- credential_id", &self.credential_id);
 
         for e in &self.extensions {
-            if let Extension::HmacSecret(Some(output1_enc)) = e {
-                let tmp = format!("- {}", Extension::HmacSecret(None));
-                strbuf.appenh(&tmp, output1_enc.as_ref());
+            if let Extension::HmacSecret(output1, output2) = e {
+                if let Some(output1) = output1 {
+                    let tmp = format!("- {} output1", Extension::HmacSecret(None, None));
+                    strbuf.appenh(&tmp, output1.as_ref());
+                }
+                if let Some(output2) = output2 {
+                    let tmp = format!("- {} output2", Extension::HmacSecret(None, None));
+                    strbuf.appenh(&tmp, output2.as_ref());
+                }
+            }
+            if let Extension::Prf(_, Some(results)) = e {
+                strbuf.appenh("- prf first", &results.first);
+                if let Some(second) = &results.second {
+                    strbuf.appenh("- prf second", second);
+                }
             }
         }
 
@@ -45,22 +65,141 @@ impl fmt::Display for Assertion {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, strum_macros::Display, AsRefStr)]
 pub enum Extension {
     #[strum(serialize = "hmac-secret")]
-    HmacSecret(Option<[u8; 32]>),
+    #[cfg_attr(feature = "serde", serde(rename = "hmac-secret"))]
+    /// (salt1, salt2) on input, (output1, output2) in the assertion
+    HmacSecret(
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url_option"))]
+        Option<[u8; 32]>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url_option"))]
+        Option<[u8; 32]>,
+    ),
     #[strum(serialize = "largeBlobKey")]
-    LargeBlobKey((Option<bool>, Option<Vec<u8>>)),
+    #[cfg_attr(feature = "serde", serde(rename = "largeBlobKey"))]
+    LargeBlobKey(
+        #[cfg_attr(
+            feature = "serde",
+            serde(with = "crate::serde_ext::flag_and_base64url")
+        )]
+        (Option<bool>, Option<Vec<u8>>),
+    ),
     #[strum(serialize = "credBlob")]
-    CredBlob((Option<bool>, Option<Vec<u8>>)),
+    #[cfg_attr(feature = "serde", serde(rename = "credBlob"))]
+    CredBlob(
+        #[cfg_attr(
+            feature = "serde",
+            serde(with = "crate::serde_ext::flag_and_base64url")
+        )]
+        (Option<bool>, Option<Vec<u8>>),
+    ),
+    /// WebAuthn PRF extension, evaluated with hmac-secret.
+    /// (inputs, results)
+    #[strum(serialize = "prf")]
+    #[cfg_attr(feature = "serde", serde(rename = "prf"))]
+    Prf(Option<PrfInputs>, Option<PrfValues>),
+}
+
+/// PRF extension values (AuthenticationExtensionsPRFValues)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PrfValues {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
+    pub first: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url_option"))]
+    pub second: Option<Vec<u8>>,
+}
+
+impl PrfValues {
+    #[must_use]
+    pub fn new(first: &[u8], second: Option<&[u8]>) -> Self {
+        Self {
+            first: first.to_vec(),
+            second: second.map(|x| x.to_vec()),
+        }
+    }
+
+    /// hmac-secret salts: SHA-256("WebAuthn PRF" || 0x00 || input)
+    pub(crate) fn to_salts(&self) -> ([u8; 32], Option<[u8; 32]>) {
+        (
+            hmac_ext::prf_salt(&self.first),
+            self.second.as_ref().map(|x| hmac_ext::prf_salt(x)),
+        )
+    }
+
+    pub(crate) fn from_outputs(output1: &[u8; 32], output2: Option<&[u8; 32]>) -> Self {
+        Self {
+            first: output1.to_vec(),
+            second: output2.map(|x| x.to_vec()),
+        }
+    }
+}
+
+/// PRF extension inputs (AuthenticationExtensionsPRFInputs)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PrfInputs {
+    pub eval: Option<PrfValues>,
+    /// credential id -> values
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url_keys"))]
+    pub eval_by_credential: BTreeMap<Vec<u8>, PrfValues>,
+}
+
+impl PrfInputs {
+    /// Values for the credential that will be asserted.
+    /// An `eval_by_credential` entry is only usable when `credential_ids` names a single credential.
+    pub(crate) fn select(&self, credential_ids: &[Vec<u8>]) -> Result<Option<&PrfValues>> {
+        if let Some(id) = self
+            .eval_by_credential
+            .keys()
+            .find(|id| !credential_ids.contains(id))
+        {
+            return Err(anyhow!(
+                "prf evalByCredential key is not in the allow list: {}",
+                hex::encode(id)
+            ));
+        }
+
+        match credential_ids {
+            [id] => Ok(self.eval_by_credential.get(id).or(self.eval.as_ref())),
+            _ if self.eval_by_credential.is_empty() => Ok(self.eval.as_ref()),
+            _ => Err(anyhow!(
+                "prf evalByCredential needs a single credential id in the allow list"
+            )),
+        }
+    }
 }
 
 impl Extension {
     #[must_use]
     pub fn create_hmac_secret_from_string(message: &str) -> Self {
-        let hasher = digest::digest(&digest::SHA256, message.as_bytes());
-        Self::HmacSecret(Some(<[u8; 32]>::try_from(hasher.as_ref()).unwrap()))
+        Self::HmacSecret(Some(sha256(message)), None)
+    }
+
+    /// hmac-secret with two salts: salt1 = SHA-256(message1), salt2 = SHA-256(message2)
+    #[must_use]
+    pub fn create_hmac_secret_from_strings(message1: &str, message2: &str) -> Self {
+        Self::HmacSecret(Some(sha256(message1)), Some(sha256(message2)))
     }
+
+    /// prf with `eval` only
+    #[must_use]
+    pub fn create_prf(first: &[u8], second: Option<&[u8]>) -> Self {
+        Self::Prf(
+            Some(PrfInputs {
+                eval: Some(PrfValues::new(first, second)),
+                ..Default::default()
+            }),
+            None,
+        )
+    }
+}
+
+fn sha256(message: &str) -> [u8; 32] {
+    let hasher = digest::digest(&digest::SHA256, message.as_bytes());
+    <[u8; 32]>::try_from(hasher.as_ref()).unwrap()
 }
 
 #[derive(Debug)]
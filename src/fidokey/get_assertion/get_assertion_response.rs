@@ -1,11 +1,11 @@
 use super::get_assertion_params;
 use super::get_assertion_params::Extension;
 use crate::auth_data::Flags;
-use crate::encrypt::enc_aes256_cbc;
 use crate::encrypt::shared_secret::SharedSecret;
+use crate::hmac_ext::HmacExt;
 use crate::public_key_credential_user_entity::PublicKeyCredentialUserEntity;
 use crate::util;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use byteorder::{BigEndian, ReadBytesExt};
 use serde_cbor::Value;
 use std::io::Cursor;
@@ -58,28 +58,23 @@ fn parse_cbor_authdata(
         let maps = util::cbor_bytes_to_map(&slice)?;
         for (key, val) in &maps {
             if let Value::Text(member) = key {
-                if *member == Extension::HmacSecret(None).to_string() {
+                if *member == Extension::HmacSecret(None, None).to_string() {
                     // 12.5. HMAC Secret Extension (hmac-secret)
                     // https://fidoalliance.org/specs/fido-v2.1-ps-20210615/fido-client-to-authenticator-protocol-v2.1-ps-20210615.html#sctn-hmac-secret-extension
 
                     // The hmac-secret is created in Authenticator as follows.
                     // > One salt case: "hmac-secret": encrypt(shared secret, output1)
+                    // > Two salt case: "hmac-secret": encrypt(shared secret, output1 || output2)
                     let hmac_secret = util::cbor_value_to_vec_u8(val)?;
-
-                    // decrypt hmac_secret -> output1
-                    let output1 = enc_aes256_cbc::decrypt_message(
-                        &shared_secret.as_ref().unwrap().secret,
-                        &hmac_secret[0..32],
-                    );
+                    let shared_secret = shared_secret
+                        .ok_or_else(|| anyhow!("hmac-secret output without shared secret"))?;
 
                     // The output1 is created in Authenticator as follows.
                     // >output1: HMAC-SHA-256(CredRandom, salt1)
                     // Can't access CredRandom since that is the secret the authenticator uses to derive credential specific private/public keys
-
-                    let mut hmac_secret_0 = [0u8; 32];
-                    hmac_secret_0.copy_from_slice(&output1[0..32]);
+                    let (output1, output2) = HmacExt::decrypt_output(shared_secret, &hmac_secret)?;
                     ass.extensions
-                        .push(Extension::HmacSecret(Some(hmac_secret_0)));
+                        .push(Extension::HmacSecret(Some(output1), output2));
                 } else if *member == Extension::CredBlob((None, None)).to_string() {
                     let cred_blob = util::cbor_value_to_vec_u8(val)?;
                     ass.extensions
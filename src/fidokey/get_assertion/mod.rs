@@ -2,10 +2,16 @@ pub mod get_assertion_command;
 pub mod get_assertion_params;
 pub mod get_assertion_response;
 pub mod get_next_assertion_command;
-use crate::{ctaphid, encrypt::enc_hmac_sha_256, hmac_ext::HmacExt, FidoKeyHid};
-use anyhow::Result;
+use crate::{
+    ctaphid,
+    encrypt::{enc_hmac_sha_256, shared_secret::SharedSecret},
+    fidokey::pin::PinUvAuthProtocol,
+    hmac_ext::{HmacExt, HmacSecretPair},
+    FidoKeyHid,
+};
+use anyhow::{anyhow, Result};
 use get_assertion_params::{Assertion, Extension as Gext, GetAssertionArgs};
-pub use get_assertion_params::{Extension, GetAssertionArgsBuilder};
+pub use get_assertion_params::{Extension, GetAssertionArgsBuilder, PrfInputs, PrfValues};
 
 impl FidoKeyHid {
     /// Create a new assertion manually specifying the args using `GetAssertionArgs`
@@ -18,16 +24,26 @@ impl FidoKeyHid {
             &args.credential_ids
         };
 
-        let extensions = if args.extensions.is_some() {
-            Some(args.extensions.as_ref().unwrap())
+        let extensions = args.extensions.as_ref();
+
+        // hmac-secret uses the first PIN/UV Auth Protocol the authenticator supports
+        let salts = hmac_secret_salts(extensions, credential_ids)?;
+        let pin_uv_auth_protocol = if salts.is_some() {
+            PinUvAuthProtocol::select(&self.cached_info()?.pin_uv_auth_protocols)
         } else {
-            None
+            PinUvAuthProtocol::One
         };
 
         // init
         let cid = ctaphid::ctaphid_init(self)?;
 
-        let hmac_ext = create_hmacext(self, cid, extensions)?;
+        let hmac_ext = if let Some((salt1, salt2)) = salts {
+            let mut hmac_ext = HmacExt::default();
+            hmac_ext.create(self, &cid, pin_uv_auth_protocol, &salt1, salt2.as_ref())?;
+            Some(hmac_ext)
+        } else {
+            None
+        };
 
         // pin token
         let pin_token = {
@@ -54,23 +70,33 @@ impl FidoKeyHid {
                 params.pin_auth = sig[0..16].to_vec();
             }
 
-            get_assertion_command::create_payload(params, extensions, hmac_ext.clone())
+            get_assertion_command::create_payload(params, extensions, hmac_ext.clone())?
         };
 
         // send & response
         let response_cbor = ctaphid::ctaphid_cbor(self, &cid, &send_payload)?;
 
-        let ass = get_assertion_response::parse_cbor(
-            &response_cbor,
-            &hmac_ext.map(|ext| ext.shared_secret),
-        )?;
+        let shared_secret = hmac_ext.map(|ext| ext.shared_secret);
+        let ass = get_assertion_response::parse_cbor(&response_cbor, &shared_secret)?;
 
         let mut asss = vec![ass];
         for _ in 0..(asss[0].number_of_credentials - 1) {
-            let ass = get_next_assertion(self, &cid)?;
+            let ass = get_next_assertion(self, &cid, &shared_secret)?;
             asss.push(ass);
         }
 
+        // prf results are the hmac-secret outputs
+        if is_prf(extensions) {
+            for ass in &mut asss {
+                for ext in &mut ass.extensions {
+                    if let Gext::HmacSecret(Some(output1), output2) = ext {
+                        let results = PrfValues::from_outputs(output1, output2.as_ref());
+                        *ext = Gext::Prf(None, Some(results));
+                    }
+                }
+            }
+        }
+
         Ok(asss)
     }
 
@@ -134,23 +160,43 @@ impl FidoKeyHid {
     }
 }
 
-fn get_next_assertion(device: &FidoKeyHid, cid: &[u8]) -> Result<Assertion> {
+fn get_next_assertion(
+    device: &FidoKeyHid,
+    cid: &[u8],
+    shared_secret: &Option<SharedSecret>,
+) -> Result<Assertion> {
     let send_payload = get_next_assertion_command::create_payload();
     let response_cbor = ctaphid::ctaphid_cbor(device, cid, &send_payload)?;
-    get_assertion_response::parse_cbor(&response_cbor, &None)
+    get_assertion_response::parse_cbor(&response_cbor, shared_secret)
 }
 
-fn create_hmacext(
-    device: &FidoKeyHid,
-    cid: [u8; 4],
+fn is_prf(extensions: Option<&Vec<Gext>>) -> bool {
+    extensions.is_some_and(|exts| exts.iter().any(|ext| matches!(ext, Gext::Prf(Some(_), _))))
+}
+
+/// hmac-secret salts from the hmac-secret or prf extension
+fn hmac_secret_salts(
     extensions: Option<&Vec<Gext>>,
-) -> Result<Option<HmacExt>> {
-    if let Some(extensions) = extensions {
-        if let Some(Gext::HmacSecret(n)) = extensions.iter().next() {
-            let mut hmac_ext = HmacExt::default();
-            hmac_ext.create(device, &cid, &n.unwrap(), None)?;
-            return Ok(Some(hmac_ext));
+    credential_ids: &[Vec<u8>],
+) -> Result<Option<HmacSecretPair>> {
+    let extensions = match extensions {
+        Some(extensions) => extensions,
+        None => return Ok(None),
+    };
+
+    let mut salts = None;
+    for ext in extensions {
+        let s = match ext {
+            Gext::HmacSecret(Some(salt1), salt2) => Some((*salt1, *salt2)),
+            Gext::Prf(Some(inputs), _) => inputs.select(credential_ids)?.map(|v| v.to_salts()),
+            _ => None,
+        };
+        if s.is_some() {
+            if salts.is_some() {
+                return Err(anyhow!("hmac-secret and prf cannot be requested together"));
+            }
+            salts = s;
         }
     }
-    Ok(None)
+    Ok(salts)
 }
@@ -1,21 +1,28 @@
+use super::InfoOption;
+use crate::fidokey::make_credential::CredentialSupportedKeyType;
 use crate::str_buf::StrBuf;
+use serde_cbor::Value;
 use std::fmt;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
 
-#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Info {
     // CTAP 2.0
     pub versions: Vec<String>,
     pub extensions: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::hex"))]
     pub aaguid: Vec<u8>,
-    pub options: Vec<(String, bool)>,
+    pub options: InfoOptions,
     pub max_msg_size: i32,
     //pub pin_protocols: Vec<i32>,
     // CTAP 2.1
     pub pin_uv_auth_protocols: Vec<u32>,
     pub max_credential_count_in_list: u32,
     pub max_credential_id_length: u32,
-    pub transports: Vec<String>,
-    pub algorithms: Vec<(String, String)>,
+    pub transports: Vec<Transport>,
+    pub algorithms: Vec<Algorithm>,
     pub max_serialized_large_blob_array: u32,
     pub force_pin_change: bool,
     pub min_pin_length: u32,
@@ -24,8 +31,231 @@ pub struct Info {
     pub max_rpids_for_set_min_pin_length: u32,
     pub preferred_platform_uv_attempts: u32,
     pub uv_modality: u32,
-    pub certifications: String,
+    pub certifications: Vec<Certification>,
     pub remaining_discoverable_credentials: u32,
+    /// Members not parsed by this library (ex. CTAP 2.2, vendor specific, malformed)
+    pub unknown_members: Vec<(Value, Value)>,
+}
+
+impl Info {
+    /// Key types of `algorithms` that can be used for `make_credential`
+    pub fn key_types(&self) -> Vec<CredentialSupportedKeyType> {
+        self.algorithms
+            .iter()
+            .filter_map(Algorithm::key_type)
+            .collect()
+    }
+
+    /// Level of a certification (ex. "FIDO")
+    pub fn certification(&self, name: &str) -> Option<u32> {
+        self.certifications
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| c.level)
+    }
+}
+
+/// options (0x04). None if the option is absent.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InfoOptions {
+    pub always_uv: Option<bool>,
+    pub authnr_cfg: Option<bool>,
+    pub bio_enroll: Option<bool>,
+    pub client_pin: Option<bool>,
+    pub credential_mgmt_preview: Option<bool>,
+    pub cred_mgmt: Option<bool>,
+    pub ep: Option<bool>,
+    pub large_blobs: Option<bool>,
+    pub make_cred_uv_not_rqd: Option<bool>,
+    pub no_mc_ga_permissions_with_client_pin: Option<bool>,
+    pub per_cred_mgmt_ro: Option<bool>,
+    pub pin_uv_auth_token: Option<bool>,
+    pub plat: Option<bool>,
+    pub rk: Option<bool>,
+    pub set_min_pin_length: Option<bool>,
+    pub up: Option<bool>,
+    pub user_verification_mgmt_preview: Option<bool>,
+    pub uv: Option<bool>,
+    pub uv_acfg: Option<bool>,
+    pub uv_bio_enroll: Option<bool>,
+    pub uv_token: Option<bool>,
+    /// Options not defined in `InfoOption` (ex. vendor specific)
+    pub others: Vec<(String, bool)>,
+    /// Options without a text key or a bool value
+    pub unknown: Vec<(Value, Value)>,
+}
+
+impl InfoOptions {
+    pub fn get(&self, option: &InfoOption) -> Option<bool> {
+        match option {
+            InfoOption::AlwaysUv => self.always_uv,
+            InfoOption::AuthnrCfg => self.authnr_cfg,
+            InfoOption::BioEnroll => self.bio_enroll,
+            InfoOption::ClientPin => self.client_pin,
+            InfoOption::CredentialMgmtPreview => self.credential_mgmt_preview,
+            InfoOption::CredMgmt => self.cred_mgmt,
+            InfoOption::Ep => self.ep,
+            InfoOption::LargeBlobs => self.large_blobs,
+            InfoOption::MakeCredUvNotRqd => self.make_cred_uv_not_rqd,
+            InfoOption::NoMcGaPermissionsWithClientPin => self.no_mc_ga_permissions_with_client_pin,
+            InfoOption::PerCredMgmtRO => self.per_cred_mgmt_ro,
+            InfoOption::PinUvAuthToken => self.pin_uv_auth_token,
+            InfoOption::Plat => self.plat,
+            InfoOption::Rk => self.rk,
+            InfoOption::SetMinPINLength => self.set_min_pin_length,
+            InfoOption::Up => self.up,
+            InfoOption::UserVerificationMgmtPreview => self.user_verification_mgmt_preview,
+            InfoOption::Uv => self.uv,
+            InfoOption::UvAcfg => self.uv_acfg,
+            InfoOption::UvBioEnroll => self.uv_bio_enroll,
+            InfoOption::UvToken => self.uv_token,
+        }
+    }
+
+    /// Set the option by its key (ex. "clientPin")
+    pub fn set(&mut self, key: &str, value: bool) {
+        let option = match InfoOption::from_str(key) {
+            Ok(option) => option,
+            Err(_) => {
+                self.others.retain(|(k, _)| k != key);
+                self.others.push((key.to_string(), value));
+                return;
+            }
+        };
+        let field = match option {
+            InfoOption::AlwaysUv => &mut self.always_uv,
+            InfoOption::AuthnrCfg => &mut self.authnr_cfg,
+            InfoOption::BioEnroll => &mut self.bio_enroll,
+            InfoOption::ClientPin => &mut self.client_pin,
+            InfoOption::CredentialMgmtPreview => &mut self.credential_mgmt_preview,
+            InfoOption::CredMgmt => &mut self.cred_mgmt,
+            InfoOption::Ep => &mut self.ep,
+            InfoOption::LargeBlobs => &mut self.large_blobs,
+            InfoOption::MakeCredUvNotRqd => &mut self.make_cred_uv_not_rqd,
+            InfoOption::NoMcGaPermissionsWithClientPin => {
+                &mut self.no_mc_ga_permissions_with_client_pin
+            }
+            InfoOption::PerCredMgmtRO => &mut self.per_cred_mgmt_ro,
+            InfoOption::PinUvAuthToken => &mut self.pin_uv_auth_token,
+            InfoOption::Plat => &mut self.plat,
+            InfoOption::Rk => &mut self.rk,
+            InfoOption::SetMinPINLength => &mut self.set_min_pin_length,
+            InfoOption::Up => &mut self.up,
+            InfoOption::UserVerificationMgmtPreview => &mut self.user_verification_mgmt_preview,
+            InfoOption::Uv => &mut self.uv,
+            InfoOption::UvAcfg => &mut self.uv_acfg,
+            InfoOption::UvBioEnroll => &mut self.uv_bio_enroll,
+            InfoOption::UvToken => &mut self.uv_token,
+        };
+        *field = Some(value);
+    }
+
+    /// The options that are present, as (key, value)
+    pub fn to_vec(&self) -> Vec<(String, bool)> {
+        InfoOption::iter()
+            .filter_map(|option| {
+                self.get(&option)
+                    .map(|value| (option.as_ref().to_string(), value))
+            })
+            .chain(self.others.iter().cloned())
+            .collect()
+    }
+}
+
+/// transports (0x09)
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "String", into = "String")
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Usb,
+    Nfc,
+    Ble,
+    SmartCard,
+    Hybrid,
+    Internal,
+    Other(String),
+}
+
+impl Transport {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Usb => "usb",
+            Self::Nfc => "nfc",
+            Self::Ble => "ble",
+            Self::SmartCard => "smart-card",
+            Self::Hybrid => "hybrid",
+            Self::Internal => "internal",
+            Self::Other(s) => s,
+        }
+    }
+}
+
+impl From<&str> for Transport {
+    fn from(from: &str) -> Self {
+        match from {
+            "usb" => Self::Usb,
+            "nfc" => Self::Nfc,
+            "ble" => Self::Ble,
+            "smart-card" => Self::SmartCard,
+            "hybrid" => Self::Hybrid,
+            "internal" => Self::Internal,
+            _ => Self::Other(from.to_string()),
+        }
+    }
+}
+
+impl From<String> for Transport {
+    fn from(from: String) -> Self {
+        Self::from(from.as_str())
+    }
+}
+
+impl From<Transport> for String {
+    fn from(from: Transport) -> Self {
+        from.as_str().to_string()
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// algorithms (0x0A): PublicKeyCredentialParameters
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Algorithm {
+    /// "public-key"
+    pub credential_type: String,
+    /// COSE algorithm identifier (ex. -7: ES256)
+    pub alg: i32,
+    /// Members other than "type" and "alg"
+    pub others: Vec<(Value, Value)>,
+}
+
+impl Algorithm {
+    /// None if the algorithm is not supported by this library
+    pub fn key_type(&self) -> Option<CredentialSupportedKeyType> {
+        if self.credential_type == "public-key" {
+            CredentialSupportedKeyType::from_alg(self.alg)
+        } else {
+            None
+        }
+    }
+}
+
+/// certifications (0x13)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Certification {
+    /// ex. "FIDO", "FIPS-CMVP-2"
+    pub name: String,
+    pub level: u32,
 }
 
 impl fmt::Display for Info {
@@ -35,7 +265,7 @@ impl fmt::Display for Info {
             .append("- versions", &format!("{:?}", self.versions))
             .append("- extensions", &format!("{:?}", self.extensions))
             .appenh("- aaguid", &self.aaguid)
-            .append("- options", &format!("{:?}", self.options))
+            .append("- options", &format!("{:?}", self.options.to_vec()))
             .append("- max_msg_size", &self.max_msg_size)
             .append(
                 "- pin_uv_auth_protocols",
@@ -46,8 +276,26 @@ impl fmt::Display for Info {
                 &self.max_credential_count_in_list,
             )
             .append("- max_credential_id_length", &self.max_credential_id_length)
-            .append("- transports", &format!("{:?}", self.transports))
-            .append("- algorithms", &format!("{:?}", self.algorithms))
+            .append(
+                "- transports",
+                &format!(
+                    "{:?}",
+                    self.transports
+                        .iter()
+                        .map(Transport::as_str)
+                        .collect::<Vec<_>>()
+                ),
+            )
+            .append(
+                "- algorithms",
+                &format!(
+                    "{:?}",
+                    self.algorithms
+                        .iter()
+                        .map(|a| (a.credential_type.as_str(), a.alg))
+                        .collect::<Vec<_>>()
+                ),
+            )
             .append(
                 "- max_serialized_large_blob_array",
                 &format!("{:?}", self.max_serialized_large_blob_array),
@@ -76,12 +324,25 @@ impl fmt::Display for Info {
             .append("- uv_modality", &format!("{:?}", self.uv_modality))
             .append(
                 "- certifications",
-                &format!("{:?}", self.certifications),
+                &format!(
+                    "{:?}",
+                    self.certifications
+                        .iter()
+                        .map(|c| (c.name.as_str(), c.level))
+                        .collect::<Vec<_>>()
+                ),
             )
             .append(
                 "- remaining_discoverable_credentials",
                 &format!("{:?}", self.remaining_discoverable_credentials),
             );
+        for (key, val) in &self.unknown_members {
+            let key = match key {
+                Value::Integer(member) => format!("- 0x{member:02X}"),
+                _ => format!("- {key:?}"),
+            };
+            strbuf.append(&key, &format!("{val:?}"));
+        }
 
         write!(f, "{}", strbuf.build())
     }
@@ -1,81 +1,277 @@
-use super::get_info_params;
+use super::get_info_params::{Algorithm, Certification, Info, Transport};
 use crate::util;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use serde_cbor::Value;
 
-pub fn parse_cbor(bytes: &[u8]) -> Result<get_info_params::Info> {
-    let mut info = get_info_params::Info::default();
+pub fn parse_cbor(bytes: &[u8]) -> Result<Info> {
+    let mut info = Info::default();
     let maps = util::cbor_bytes_to_map(bytes)?;
     for (key, val) in &maps {
-        if let Value::Integer(member) = key {
-            match member {
-                0x01 => info.versions = util::cbor_value_to_vec_string(val)?,
-                0x02 => info.extensions = util::cbor_value_to_vec_string(val)?,
-                0x03 => info.aaguid = util::cbor_value_to_vec_u8(val)?,
-                0x04 => {
-                    if let Value::Map(xs) = val {
-                        for (key, val) in xs {
-                            if let Value::Text(s) = key {
-                                if let Value::Bool(b) = val {
-                                    info.options.push((s.to_string(), *b));
-                                }
-                            }
-                        }
-                    }
+        match key {
+            // versions and aaguid are required
+            Value::Integer(member @ (0x01 | 0x03)) => parse_member(&mut info, *member, val)
+                .with_context(|| format!("authenticatorGetInfo member 0x{member:02X}"))?,
+            Value::Integer(member) => {
+                // a malformed optional member is kept as it is
+                if parse_member(&mut info, *member, val).is_err() {
+                    info.unknown_members.push((key.clone(), val.clone()));
                 }
-                0x05 => info.max_msg_size = util::cbor_value_to_num(val)?,
-                0x06 => {
-                    if let Value::Array(xs) = val {
-                        for x in xs {
-                            info.pin_uv_auth_protocols.push(util::cbor_value_to_num(x)?);
-                        }
-                    }
-                }
-                0x07 => info.max_credential_count_in_list = util::cbor_value_to_num(val)?,
-                0x08 => info.max_credential_id_length = util::cbor_value_to_num(val)?,
-                0x09 => info.transports = util::cbor_value_to_vec_string(val)?,
-                0x0A => {
-                    if let Value::Array(xs) = val {
-                        for x in xs {
-                            if let Value::Map(n) = x {
-                                for (key, val) in n {
-                                    let setkey = {
-                                        if let Value::Text(keystr) = key {
-                                            keystr.to_string()
-                                        } else {
-                                            String::new()
-                                        }
-                                    };
-
-                                    let setval = {
-                                        if let Value::Text(valstr) = val {
-                                            valstr.to_string()
-                                        } else if let Value::Integer(valint) = val {
-                                            valint.to_string()
-                                        } else {
-                                            String::new()
-                                        }
-                                    };
-
-                                    info.algorithms.push((setkey, setval));
-                                }
-                            }
-                        }
-                    }
-                }
-                0x0B => info.max_serialized_large_blob_array = util::cbor_value_to_num(val)?,
-                0x0C => info.force_pin_change = util::cbor_value_to_bool(val)?,
-                0x0D => info.min_pin_length = util::cbor_value_to_num(val)?,
-                0x0E => info.firmware_version = util::cbor_value_to_num(val)?,
-                0x0F => info.max_cred_blob_length = util::cbor_value_to_num(val)?,
-                0x10 => info.max_rpids_for_set_min_pin_length = util::cbor_value_to_num(val)?,
-                0x11 => info.preferred_platform_uv_attempts = util::cbor_value_to_num(val)?,
-                0x12 => info.uv_modality = util::cbor_value_to_num(val)?,
-                0x13 => info.certifications = util::cbor_get_string_from_map(val, "FIDO").expect("fuck"),
-                0x14 => info.remaining_discoverable_credentials = util::cbor_value_to_num(val)?,
-                _ => println!("parse_cbor_member - unknown info {member:?}"),
             }
+            _ => info.unknown_members.push((key.clone(), val.clone())),
         }
     }
     Ok(info)
 }
+
+fn parse_member(info: &mut Info, member: i128, val: &Value) -> Result<()> {
+    match member {
+        0x01 => info.versions = util::cbor_value_to_vec_string(val)?,
+        0x02 => info.extensions = util::cbor_value_to_vec_string(val)?,
+        0x03 => info.aaguid = util::cbor_value_to_vec_u8(val)?,
+        0x04 => {
+            for (key, val) in cbor_value_to_map(val)? {
+                if let (Value::Text(key), Value::Bool(b)) = (key, val) {
+                    info.options.set(key, *b);
+                } else {
+                    info.options.unknown.push((key.clone(), val.clone()));
+                }
+            }
+        }
+        0x05 => info.max_msg_size = util::cbor_value_to_num(val)?,
+        0x06 => {
+            info.pin_uv_auth_protocols = cbor_value_to_array(val)?
+                .iter()
+                .map(util::cbor_value_to_num)
+                .collect::<Result<_>>()?
+        }
+        0x07 => info.max_credential_count_in_list = util::cbor_value_to_num(val)?,
+        0x08 => info.max_credential_id_length = util::cbor_value_to_num(val)?,
+        0x09 => {
+            info.transports = util::cbor_value_to_vec_string(val)?
+                .into_iter()
+                .map(Transport::from)
+                .collect()
+        }
+        0x0A => {
+            info.algorithms = cbor_value_to_array(val)?
+                .iter()
+                .map(parse_algorithm)
+                .collect::<Result<_>>()?
+        }
+        0x0B => info.max_serialized_large_blob_array = util::cbor_value_to_num(val)?,
+        0x0C => info.force_pin_change = util::cbor_value_to_bool(val)?,
+        0x0D => info.min_pin_length = util::cbor_value_to_num(val)?,
+        0x0E => info.firmware_version = util::cbor_value_to_num(val)?,
+        0x0F => info.max_cred_blob_length = util::cbor_value_to_num(val)?,
+        0x10 => info.max_rpids_for_set_min_pin_length = util::cbor_value_to_num(val)?,
+        0x11 => info.preferred_platform_uv_attempts = util::cbor_value_to_num(val)?,
+        0x12 => info.uv_modality = util::cbor_value_to_num(val)?,
+        0x13 => {
+            info.certifications = cbor_value_to_map(val)?
+                .iter()
+                .map(|(key, val)| {
+                    Ok(Certification {
+                        name: util::cbor_value_to_str(key)?,
+                        level: util::cbor_value_to_num(val)?,
+                    })
+                })
+                .collect::<Result<_>>()?
+        }
+        0x14 => info.remaining_discoverable_credentials = util::cbor_value_to_num(val)?,
+        _ => info
+            .unknown_members
+            .push((Value::Integer(member), val.clone())),
+    }
+    Ok(())
+}
+
+// PublicKeyCredentialParameters
+fn parse_algorithm(val: &Value) -> Result<Algorithm> {
+    let mut algorithm = Algorithm::default();
+    for (key, val) in cbor_value_to_map(val)? {
+        match key {
+            Value::Text(s) if s == "type" => {
+                algorithm.credential_type = util::cbor_value_to_str(val)?
+            }
+            Value::Text(s) if s == "alg" => algorithm.alg = util::cbor_value_to_num(val)?,
+            _ => algorithm.others.push((key.clone(), val.clone())),
+        }
+    }
+    Ok(algorithm)
+}
+
+fn cbor_value_to_map(value: &Value) -> Result<&std::collections::BTreeMap<Value, Value>> {
+    if let Value::Map(map) = value {
+        Ok(map)
+    } else {
+        Err(anyhow!("Cast Error : Value is not a Map."))
+    }
+}
+
+fn cbor_value_to_array(value: &Value) -> Result<&Vec<Value>> {
+    if let Value::Array(xs) = value {
+        Ok(xs)
+    } else {
+        Err(anyhow!("Cast Error : Value is not Array."))
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fidokey::get_info::InfoOption;
+    use crate::fidokey::make_credential::CredentialSupportedKeyType;
+    use std::collections::BTreeMap;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn map(entries: Vec<(Value, Value)>) -> Value {
+        Value::Map(entries.into_iter().collect())
+    }
+
+    fn to_cbor(entries: Vec<(i128, Value)>) -> Vec<u8> {
+        let map: BTreeMap<Value, Value> = entries
+            .into_iter()
+            .map(|(k, v)| (Value::Integer(k), v))
+            .collect();
+        serde_cbor::to_vec(&map).unwrap()
+    }
+
+    #[test]
+    fn test_parse_info() {
+        let bytes = to_cbor(vec![
+            (0x01, Value::Array(vec![text("FIDO_2_0"), text("FIDO_2_1")])),
+            (0x03, Value::Bytes(vec![0xee; 16])),
+            (
+                0x04,
+                map(vec![
+                    (text("rk"), Value::Bool(true)),
+                    (text("clientPin"), Value::Bool(false)),
+                    (text("vendorOpt"), Value::Bool(true)),
+                    (text("vendorNum"), Value::Integer(3)),
+                ]),
+            ),
+            (
+                0x09,
+                Value::Array(vec![text("usb"), text("nfc"), text("x-ray")]),
+            ),
+            (
+                0x0A,
+                Value::Array(vec![
+                    map(vec![
+                        (text("alg"), Value::Integer(-7)),
+                        (text("type"), text("public-key")),
+                    ]),
+                    map(vec![
+                        (text("alg"), Value::Integer(-257)),
+                        (text("type"), text("public-key")),
+                    ]),
+                    map(vec![
+                        (text("alg"), Value::Integer(-8)),
+                        (text("type"), text("public-key")),
+                        (text("curve"), text("Ed25519")),
+                    ]),
+                ]),
+            ),
+            (
+                0x13,
+                map(vec![
+                    (text("FIDO"), Value::Integer(2)),
+                    (text("FIPS-CMVP-2"), Value::Integer(1)),
+                ]),
+            ),
+            (0x16, Value::Array(vec![text("packed")])),
+        ]);
+
+        let info = parse_cbor(&bytes).unwrap();
+        assert_eq!(info.aaguid, vec![0xee; 16]);
+        assert_eq!(info.option(&InfoOption::Rk), Some(true));
+        assert_eq!(info.option(&InfoOption::ClientPin), Some(false));
+        assert_eq!(info.option(&InfoOption::Uv), None);
+        assert_eq!(info.options.others, vec![("vendorOpt".to_string(), true)]);
+        assert_eq!(
+            info.options.unknown,
+            vec![(text("vendorNum"), Value::Integer(3))]
+        );
+        assert_eq!(
+            info.transports,
+            vec![
+                Transport::Usb,
+                Transport::Nfc,
+                Transport::Other("x-ray".to_string())
+            ]
+        );
+        assert_eq!(info.algorithms[1].alg, -257);
+        assert_eq!(
+            info.algorithms[2].others,
+            vec![(text("curve"), text("Ed25519"))]
+        );
+        assert_eq!(
+            info.key_types(),
+            vec![
+                CredentialSupportedKeyType::Ecdsa256,
+                CredentialSupportedKeyType::Ed25519
+            ]
+        );
+        assert_eq!(info.certification("FIDO"), Some(2));
+        assert_eq!(info.certification("FIPS-CMVP-2"), Some(1));
+        assert_eq!(info.unknown_members.len(), 1);
+        assert_eq!(info.unknown_members[0].0, Value::Integer(0x16));
+    }
+
+    #[test]
+    fn test_parse_malformed_info() {
+        let mut members: BTreeMap<Value, Value> = vec![
+            (0x01, Value::Array(vec![text("FIDO_2_1")])),
+            (0x03, Value::Bytes(vec![0xee; 16])),
+            (0x05, Value::Integer(1200)),
+            // pinUvAuthProtocols is not an array of integers
+            (0x06, Value::Array(vec![Value::Integer(2), text("1")])),
+            // algorithm is not a map
+            (0x0A, Value::Array(vec![Value::Integer(-7)])),
+            // certification level is not an integer
+            (0x13, map(vec![(text("FIDO"), text("L2"))])),
+        ]
+        .into_iter()
+        .map(|(k, v)| (Value::Integer(k), v))
+        .collect();
+        members.insert(text("vendor"), Value::Integer(1));
+        let bytes = serde_cbor::to_vec(&members).unwrap();
+
+        let info = parse_cbor(&bytes).unwrap();
+        assert_eq!(info.max_msg_size, 1200);
+        assert!(info.pin_uv_auth_protocols.is_empty());
+        assert!(info.algorithms.is_empty());
+        assert!(info.certifications.is_empty());
+        let keys: Vec<_> = info.unknown_members.iter().map(|(k, _)| k).collect();
+        assert_eq!(
+            keys,
+            vec![
+                &Value::Integer(0x06),
+                &Value::Integer(0x0A),
+                &Value::Integer(0x13),
+                &text("vendor")
+            ]
+        );
+        assert_eq!(info.unknown_members[1].1, members[&Value::Integer(0x0A)]);
+
+        // certifications is not a map
+        let bytes = to_cbor(vec![(0x13, text("FIDO"))]);
+        let info = parse_cbor(&bytes).unwrap();
+        assert_eq!(
+            info.unknown_members,
+            vec![(Value::Integer(0x13), text("FIDO"))]
+        );
+
+        // required members are not recoverable
+        let bytes = to_cbor(vec![(0x03, text("aaguid"))]);
+        assert!(parse_cbor(&bytes).is_err());
+
+        assert!(parse_cbor(&[0xa1, 0x01]).is_err());
+    }
+}
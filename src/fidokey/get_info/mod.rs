@@ -4,8 +4,17 @@ mod get_info_params;
 mod get_info_response;
 use super::FidoKeyHid;
 use anyhow::{anyhow, Result};
+pub use get_info_params::{Algorithm, Certification, Info, InfoOptions, Transport};
 
-#[derive(Debug, Clone, PartialEq, Eq, strum_macros::AsRefStr)]
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    strum_macros::AsRefStr,
+    strum_macros::EnumIter,
+    strum_macros::EnumString,
+)]
 pub enum InfoOption {
     #[strum(serialize = "alwaysUv")]
     AlwaysUv,
@@ -27,6 +36,8 @@ pub enum InfoOption {
     MakeCredUvNotRqd,
     #[strum(serialize = "noMcGaPermissionsWithClientPin")]
     NoMcGaPermissionsWithClientPin,
+    #[strum(serialize = "perCredMgmtRO")]
+    PerCredMgmtRO,
     #[strum(serialize = "pinUvAuthToken")]
     PinUvAuthToken,
     #[strum(serialize = "plat")]
@@ -71,6 +82,42 @@ pub enum InfoParam {
     ExtensionsHmacSecret,
 }
 
+impl Info {
+    /// None if the option is absent
+    pub fn option(&self, info_option: &InfoOption) -> Option<bool> {
+        self.options.get(info_option)
+    }
+
+    pub fn has_version(&self, info_param: &InfoParam) -> bool {
+        self.versions.iter().any(|v| v == info_param.as_ref())
+    }
+
+    /// The authenticator only supports the CTAP 2.1-PRE bioEnrollment command
+    pub fn use_pre_bio_enrollment(&self) -> bool {
+        if self.option(&InfoOption::BioEnroll).is_some() {
+            false
+        } else if self
+            .option(&InfoOption::UserVerificationMgmtPreview)
+            .is_some()
+        {
+            true
+        } else {
+            !self.has_version(&InfoParam::VersionsFido21)
+        }
+    }
+
+    /// The authenticator only supports the CTAP 2.1-PRE credentialManagement command
+    pub fn use_pre_credential_management(&self) -> bool {
+        if self.option(&InfoOption::CredMgmt).is_some() {
+            false
+        } else if self.option(&InfoOption::CredentialMgmtPreview).is_some() {
+            true
+        } else {
+            !self.has_version(&InfoParam::VersionsFido21)
+        }
+    }
+}
+
 impl FidoKeyHid {
     pub fn get_info(&self) -> Result<get_info_params::Info> {
         let cid = ctaphid::ctaphid_init(self)?;
@@ -86,7 +133,7 @@ impl FidoKeyHid {
         // CTAP1_INS.Version = 3
         match ctaphid::send_apdu(self, &cid, 0, 3, 0, 0, None) {
             Ok(result) => {
-                let version: String = String::from_utf8(result).unwrap();
+                let version: String = String::from_utf8(result)?;
                 Ok(version)
             }
             Err(error) => Err(anyhow!(error)),
@@ -107,15 +154,55 @@ impl FidoKeyHid {
     }
 
     pub fn enable_info_option(&self, info_option: &InfoOption) -> Result<Option<bool>> {
-        let info = self.get_info()?;
-        let ret = info.options.iter().find(|v| v.0 == info_option.as_ref());
-        if let Some(v) = ret {
-            // v.1 == true or false
-            // - present and set to true.
-            // - present and set to false.
-            return Ok(Some(v.1));
+        // - present and set to true.
+        // - present and set to false.
+        // - absent.
+        Ok(self.get_info()?.option(info_option))
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(versions: &[&str], options: &[&str]) -> Info {
+        let mut info = Info {
+            versions: versions.iter().map(|v| v.to_string()).collect(),
+            ..Default::default()
+        };
+        for option in options {
+            info.options.set(option, true);
         }
-        // absent.
-        Ok(None)
+        info
+    }
+
+    #[test]
+    fn test_pre_commands() {
+        // CTAP 2.1
+        let i = info(&["FIDO_2_0", "FIDO_2_1"], &["credMgmt", "bioEnroll"]);
+        assert!(!i.use_pre_bio_enrollment());
+        assert!(!i.use_pre_credential_management());
+
+        // CTAP 2.1-PRE
+        let i = info(
+            &["FIDO_2_0", "FIDO_2_1_PRE"],
+            &["credentialMgmtPreview", "userVerificationMgmtPreview"],
+        );
+        assert!(i.use_pre_bio_enrollment());
+        assert!(i.use_pre_credential_management());
+
+        // both: the final commands
+        let i = info(
+            &["FIDO_2_0", "FIDO_2_1_PRE", "FIDO_2_1"],
+            &["credMgmt", "credentialMgmtPreview"],
+        );
+        assert!(!i.use_pre_credential_management());
+
+        // options absent: versions
+        assert!(!info(&["FIDO_2_1"], &[]).use_pre_bio_enrollment());
+        assert!(info(&["FIDO_2_1_PRE"], &[]).use_pre_credential_management());
     }
 }
@@ -1,14 +1,21 @@
-use crate::{ctapdef, encrypt::enc_hmac_sha_256, pintoken::PinToken};
-use anyhow::Result;
-use ring::digest;
+use crate::{
+    ctapdef,
+    encrypt::{enc_aes256_gcm, enc_hmac_sha_256},
+    pintoken::PinToken,
+};
+use anyhow::{anyhow, Result};
+use flate2::{write::DeflateEncoder, Compression};
+use ring::{digest, rand, rand::SecureRandom};
 use serde_cbor::{to_vec, Value};
 use std::collections::BTreeMap;
+use std::io::Write;
 
 pub fn create_payload(
-    pin_token: Option<PinToken>,
+    pin_token: Option<&PinToken>,
     offset: u32,
     get: Option<u32>,
-    set: Option<Vec<u8>>,
+    set: Option<&[u8]>,
+    length: Option<u32>,
 ) -> Result<Vec<u8>> {
     // create cbor
     let mut map = BTreeMap::new();
@@ -21,17 +28,14 @@ pub fn create_payload(
     // 0x03: offset
     map.insert(Value::Integer(0x03), Value::Integer(i128::from(offset)));
 
-    if let Some(write_data) = set {
-        let large_blob_array = create_large_blob_array(&write_data);
-
+    if let Some(fragment) = set {
         // 0x02: set
-        map.insert(Value::Integer(0x02), Value::Bytes(large_blob_array.clone()));
+        map.insert(Value::Integer(0x02), Value::Bytes(fragment.to_vec()));
 
-        // 0x04: length
-        map.insert(
-            Value::Integer(0x04),
-            Value::Integer(large_blob_array.len() as i128),
-        );
+        // 0x04: length (only in the first fragment)
+        if let Some(length) = length {
+            map.insert(Value::Integer(0x04), Value::Integer(i128::from(length)));
+        }
 
         // 0x05: pinUvAuthParam
         // 0x06: pinUvAuthProtocol
@@ -47,15 +51,11 @@ pub fn create_payload(
             // https://fidoalliance.org/specs/fido-v2.1-ps-20210615/fido-client-to-authenticator-protocol-v2.1-ps-20210615.html#largeBlobsRW
 
             let pin_uv_auth_param = {
-                // println!("- {:?}", util::to_hex_str(&large_blob_array));
-                // println!("- {:?}", offset);
-                // println!("- {:?}", large_blob_array.len());
-
                 let mut message = vec![0xff; 32];
                 message.append(&mut vec![0x0c, 0x00]);
                 message.append(&mut offset.to_le_bytes().to_vec());
 
-                let hash = digest::digest(&digest::SHA256, &large_blob_array);
+                let hash = digest::digest(&digest::SHA256, fragment);
                 message.append(&mut hash.as_ref().to_vec());
 
                 let sig = enc_hmac_sha_256::authenticate(&pin_token.key, &message);
@@ -73,18 +73,56 @@ pub fn create_payload(
     Ok(payload)
 }
 
-fn create_large_blob_array(write_data: &[u8]) -> Vec<u8> {
-    let mut large_blob_array = write_data.to_vec();
+// serialized large-blob array = large-blob array || LEFT(SHA-256(large-blob array), 16)
+pub(crate) fn create_serialized_large_blob_array(large_blob_array: &[u8]) -> Vec<u8> {
+    let mut serialized = large_blob_array.to_vec();
+
+    let hash = digest::digest(&digest::SHA256, large_blob_array);
+    serialized.extend_from_slice(&hash.as_ref()[0..16]);
 
-    let hash = digest::digest(&digest::SHA256, &large_blob_array);
-    let message = &hash.as_ref()[0..16];
+    serialized
+}
 
-    //println!("- data: {:?}", util::to_hex_str(&data));
-    //println!("- message: {:?}", util::to_hex_str(message));
+// largeBlobArray element
+// - 0x01: ciphertext = AES-256-GCM(largeBlobKey, nonce, DEFLATE(data), "blob" || uint64LittleEndian(origSize))
+// - 0x02: nonce (12 bytes)
+// - 0x03: origSize
+// https://fidoalliance.org/specs/fido-v2.1-ps-20210615/fido-client-to-authenticator-protocol-v2.1-ps-20210615.html#large-blob
+pub(crate) fn create_large_blob(large_blob_key: &[u8], data: &[u8]) -> Result<Value> {
+    let compressed = {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        encoder.finish()?
+    };
+
+    let mut nonce = [0u8; 12];
+    rand::SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("Failed to create nonce"))?;
+
+    let orig_size = data.len() as u64;
+    let ciphertext = enc_aes256_gcm::encrypt_message(
+        large_blob_key,
+        &nonce,
+        &large_blob_associated_data(orig_size),
+        &compressed,
+    )?;
 
-    large_blob_array.append(&mut message.to_vec());
+    let mut map = BTreeMap::new();
+    map.insert(Value::Integer(0x01), Value::Bytes(ciphertext));
+    map.insert(Value::Integer(0x02), Value::Bytes(nonce.to_vec()));
+    map.insert(Value::Integer(0x03), Value::Integer(i128::from(orig_size)));
+    Ok(Value::Map(map))
+}
 
-    //println!("- large_blob_array: {:?}", util::to_hex_str(&large_blob_array));
+// "blob" || uint64LittleEndian(origSize)
+pub(crate) fn large_blob_associated_data(orig_size: u64) -> Vec<u8> {
+    let mut aad = b"blob".to_vec();
+    aad.extend_from_slice(&orig_size.to_le_bytes());
+    aad
+}
 
-    large_blob_array
+// CBOR array of largeBlobArray elements (without the trailing hash)
+pub(crate) fn create_large_blob_array(entries: Vec<Value>) -> Result<Vec<u8>> {
+    Ok(to_vec(&Value::Array(entries))?)
 }
@@ -1,12 +1,25 @@
 use crate::str_buf::StrBuf;
+use ring::digest;
 use std::fmt;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct LargeBlobData {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub large_blob_array: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub hash: Vec<u8>,
 }
 
+impl LargeBlobData {
+    /// hash == LEFT(SHA-256(large_blob_array), 16)
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        let hash = digest::digest(&digest::SHA256, &self.large_blob_array);
+        hash.as_ref()[0..16] == self.hash[..]
+    }
+}
+
 impl fmt::Display for LargeBlobData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut strbuf = StrBuf::new(33);
@@ -1,33 +1,100 @@
+use super::large_blobs_command::large_blob_associated_data;
 use super::large_blobs_params::LargeBlobData;
-use crate::util;
-use anyhow::Result;
+use crate::{encrypt::enc_aes256_gcm, util};
+use anyhow::{anyhow, Result};
+use flate2::read::DeflateDecoder;
 use serde_cbor::Value;
+use std::convert::TryFrom;
+use std::io::Read;
 
-pub(crate) fn parse_cbor(bytes: &[u8]) -> Result<LargeBlobData> {
-    let mut large_blobs_data = LargeBlobData::default();
+// authenticatorLargeBlobs response
+// - 0x01: config (fragment of the serialized large-blob array)
+pub(crate) fn parse_cbor(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut config = vec![];
     let maps = util::cbor_bytes_to_map(bytes)?;
     for (key, val) in &maps {
         if let Value::Integer(member) = key {
             match member {
-                0x01 => {
-                    // config -> Byte String
-                    let data = util::cbor_value_to_vec_u8(val)?;
-                    // for Debug
-                    //println!("{:?}", util::to_hex_str(&data));
-
-                    large_blobs_data.large_blob_array = data[0..(data.len() - 16)].to_vec();
-                    large_blobs_data.hash = data[(data.len() - 16)..(data.len())].to_vec();
-                    // for Debug
-                    // println!(
-                    //     "- {:?}",
-                    //     util::to_hex_str(&large_blobs_data.large_blob_array)
-                    // );
-                    // println!("- {:?}", util::to_hex_str(&large_blobs_data.hash));
-                }
+                0x01 => config = util::cbor_value_to_vec_u8(val)?,
                 _ => println!("parse_cbor_member - unknown member {member:?}"),
             }
         }
     }
 
-    Ok(large_blobs_data)
+    Ok(config)
+}
+
+// serialized large-blob array = large-blob array || LEFT(SHA-256(large-blob array), 16)
+pub(crate) fn parse_serialized_large_blob_array(data: &[u8]) -> Result<LargeBlobData> {
+    if data.len() < 17 {
+        return Err(anyhow!("Invalid serialized large-blob array"));
+    }
+
+    Ok(LargeBlobData {
+        large_blob_array: data[0..(data.len() - 16)].to_vec(),
+        hash: data[(data.len() - 16)..].to_vec(),
+    })
+}
+
+// Elements of the large-blob array.
+// A hash mismatch or a malformed array is an error, so that a read-modify-write
+// does not overwrite the entries of the other credentials.
+pub(crate) fn parse_large_blob_array(data: &LargeBlobData) -> Result<Vec<Value>> {
+    if !data.is_valid() {
+        return Err(anyhow!("Large blob array hash mismatch"));
+    }
+
+    match serde_cbor::from_slice(&data.large_blob_array) {
+        Ok(Value::Array(entries)) => Ok(entries),
+        Ok(_) => Err(anyhow!("Large blob array is not a CBOR array")),
+        Err(err) => Err(anyhow!("Invalid large blob array: {}", err)),
+    }
+}
+
+// Open a largeBlobArray element with largeBlobKey (AES-256-GCM only).
+// Returns (DEFLATE(data), origSize), None if the element is not encrypted with this key.
+pub(crate) fn open_large_blob(entry: &Value, large_blob_key: &[u8]) -> Option<(Vec<u8>, u64)> {
+    let map = match entry {
+        Value::Map(map) => map,
+        _ => return None,
+    };
+
+    let ciphertext = util::cbor_value_to_vec_u8(map.get(&Value::Integer(0x01))?).ok()?;
+    let nonce = util::cbor_value_to_vec_u8(map.get(&Value::Integer(0x02))?).ok()?;
+    let orig_size: u64 = util::cbor_value_to_num(map.get(&Value::Integer(0x03))?).ok()?;
+    let nonce = <[u8; 12]>::try_from(nonce.as_slice()).ok()?;
+
+    let compressed = enc_aes256_gcm::decrypt_message(
+        large_blob_key,
+        &nonce,
+        &large_blob_associated_data(orig_size),
+        &ciphertext,
+    )
+    .ok()?;
+    Some((compressed, orig_size))
+}
+
+// Inflate an opened element, an error if it does not inflate to origSize bytes
+pub(crate) fn inflate_large_blob(compressed: &[u8], orig_size: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    DeflateDecoder::new(compressed)
+        .take(orig_size.saturating_add(1))
+        .read_to_end(&mut data)
+        .map_err(|err| anyhow!("Corrupted large blob: {}", err))?;
+    if data.len() as u64 != orig_size {
+        return Err(anyhow!(
+            "Corrupted large blob: {} bytes (origSize {})",
+            data.len(),
+            orig_size
+        ));
+    }
+    Ok(data)
+}
+
+// Decrypt a largeBlobArray element with largeBlobKey.
+// None if the element is not encrypted with this key,
+// Some(Err) if it is but the data is corrupted.
+pub(crate) fn decrypt_large_blob(entry: &Value, large_blob_key: &[u8]) -> Option<Result<Vec<u8>>> {
+    let (compressed, orig_size) = open_large_blob(entry, large_blob_key)?;
+    Some(inflate_large_blob(&compressed, orig_size))
 }
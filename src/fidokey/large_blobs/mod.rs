@@ -1,34 +1,38 @@
 pub mod large_blobs_command;
 pub mod large_blobs_params;
 pub mod large_blobs_response;
-use super::FidoKeyHid;
+use super::{get_info::Info, FidoKeyHid};
 use crate::ctaphid;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use large_blobs_params::LargeBlobData;
+use serde_cbor::Value;
+use std::convert::TryFrom;
 
 impl FidoKeyHid {
+    /// Read the serialized large-blob array.
+    /// It is read in fragments of maxFragmentLength (maxMsgSize - 64) bytes and the trailing hash is verified.
     pub fn get_large_blob(&self) -> Result<LargeBlobData> {
-        let offset = 0; // TODO
-        let read_bytes = 1024; // TODO
-        self.large_blobs(None, offset, Some(read_bytes), None)
+        let large_blob_data = self.read_large_blob()?;
+        if !large_blob_data.is_valid() {
+            return Err(anyhow!("Large blob array hash mismatch"));
+        }
+        Ok(large_blob_data)
     }
 
+    /// Write `write_datas` as the large-blob array (the trailing hash is appended).
+    /// It is written in fragments of maxFragmentLength (maxMsgSize - 64) bytes.
     pub fn write_large_blob(
         &self,
         pin: Option<&str>,
         write_datas: Vec<u8>,
     ) -> Result<LargeBlobData> {
-        let offset = 0; // TODO
-        self.large_blobs(pin, offset, None, Some(write_datas))
-    }
+        let info = self.cached_info()?;
+
+        let serialized = large_blobs_command::create_serialized_large_blob_array(&write_datas);
+        check_max_serialized_large_blob_array(&info, serialized.len())?;
+        let length = u32::try_from(serialized.len())?;
+        let max_fragment_length = self.max_fragment_length(&info);
 
-    fn large_blobs(
-        &self,
-        pin: Option<&str>,
-        offset: u32,
-        get: Option<u32>,
-        set: Option<Vec<u8>>,
-    ) -> Result<LargeBlobData> {
         let cid = ctaphid::ctaphid_init(self)?;
 
         // get pintoken
@@ -42,9 +46,314 @@ impl FidoKeyHid {
             None
         };
 
-        let send_payload = large_blobs_command::create_payload(pin_token, offset, get, set)?;
-        let response_cbor = ctaphid::ctaphid_cbor(self, &cid, &send_payload)?;
+        let mut offset = 0;
+        for fragment in serialized.chunks(max_fragment_length) {
+            // length is only sent with the first fragment
+            let length = if offset == 0 { Some(length) } else { None };
+            let send_payload = large_blobs_command::create_payload(
+                pin_token.as_ref(),
+                offset,
+                None,
+                Some(fragment),
+                length,
+            )?;
+            ctaphid::ctaphid_cbor(self, &cid, &send_payload)?;
+
+            offset += fragment.len() as u32;
+        }
+
+        large_blobs_response::parse_serialized_large_blob_array(&serialized)
+    }
+
+    /// Read the large blob of the credential whose largeBlobKey is `large_blob_key`.
+    /// - `large_blob_key` : `Extension::LargeBlobKey` output of make_credential / get_assertion
+    ///
+    /// An error if the large blob of the credential is corrupted.
+    pub fn get_large_blob_with_key(&self, large_blob_key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let entries = self.get_large_blob_entries()?;
+        entries
+            .iter()
+            .find_map(|entry| large_blobs_response::decrypt_large_blob(entry, large_blob_key))
+            .transpose()
+    }
+
+    /// Write the large blob of the credential whose largeBlobKey is `large_blob_key`.
+    /// Entries of other credentials are preserved.
+    pub fn write_large_blob_with_key(
+        &self,
+        pin: Option<&str>,
+        large_blob_key: &[u8],
+        data: &[u8],
+    ) -> Result<()> {
+        let mut entries = self.get_large_blob_entries()?;
+        remove_large_blob(&mut entries, large_blob_key);
+        entries.push(large_blobs_command::create_large_blob(
+            large_blob_key,
+            data,
+        )?);
+
+        let large_blob_array = large_blobs_command::create_large_blob_array(entries)?;
+        self.write_large_blob(pin, large_blob_array)?;
+        Ok(())
+    }
+
+    /// Delete the large blob of the credential whose largeBlobKey is `large_blob_key`.
+    /// Entries of other credentials are preserved.
+    /// Returns false if the credential has no large blob.
+    pub fn delete_large_blob_with_key(
+        &self,
+        pin: Option<&str>,
+        large_blob_key: &[u8],
+    ) -> Result<bool> {
+        let mut entries = self.get_large_blob_entries()?;
+        if !remove_large_blob(&mut entries, large_blob_key) {
+            return Ok(false);
+        }
+
+        let large_blob_array = large_blobs_command::create_large_blob_array(entries)?;
+        self.write_large_blob(pin, large_blob_array)?;
+        Ok(true)
+    }
+
+    /// Write the initial empty large-blob array.
+    /// The large blobs of all credentials are deleted.
+    /// Use this to recover from a corrupted array (hash mismatch).
+    pub fn reset_large_blob(&self, pin: Option<&str>) -> Result<()> {
+        let large_blob_array = large_blobs_command::create_large_blob_array(vec![])?;
+        self.write_large_blob(pin, large_blob_array)?;
+        Ok(())
+    }
+
+    pub(crate) fn get_large_blob_entries(&self) -> Result<Vec<Value>> {
+        let large_blob_data = self.get_large_blob()?;
+        large_blobs_response::parse_large_blob_array(&large_blob_data)
+    }
+
+    fn read_large_blob(&self) -> Result<LargeBlobData> {
+        let info = self.cached_info()?;
+        let max_fragment_length = self.max_fragment_length(&info);
+
+        let cid = ctaphid::ctaphid_init(self)?;
+
+        // a fragment shorter than maxFragmentLength is the last one
+        let mut serialized = vec![];
+        loop {
+            let send_payload = large_blobs_command::create_payload(
+                None,
+                u32::try_from(serialized.len())?,
+                Some(u32::try_from(max_fragment_length)?),
+                None,
+                None,
+            )?;
+            let response_cbor = ctaphid::ctaphid_cbor(self, &cid, &send_payload)?;
+            let mut fragment = large_blobs_response::parse_cbor(&response_cbor)?;
+
+            let is_last = fragment.len() < max_fragment_length;
+            serialized.append(&mut fragment);
+            check_max_serialized_large_blob_array(&info, serialized.len())?;
+            if is_last {
+                break;
+            }
+        }
+
+        large_blobs_response::parse_serialized_large_blob_array(&serialized)
+    }
+}
+
+impl FidoKeyHid {
+    // limited by `Quirks::max_fragment_length`
+    fn max_fragment_length(&self, info: &Info) -> usize {
+        let max_fragment_length = max_fragment_length(info);
+        self.quirks()
+            .max_fragment_length
+            .map_or(max_fragment_length, |max| {
+                max_fragment_length.min(max).max(1)
+            })
+    }
+}
+
+// maxFragmentLength = maxMsgSize - 64 (maxMsgSize defaults to 1024)
+fn max_fragment_length(info: &Info) -> usize {
+    let max_msg_size = if info.max_msg_size > 0 {
+        info.max_msg_size as usize
+    } else {
+        1024
+    };
+    max_msg_size.saturating_sub(64).max(1)
+}
+
+// Remove the entries encrypted with `large_blob_key`, corrupted ones included
+// (they are selected by the AES-256-GCM tag, not by the inflated data).
+// false if there is none.
+fn remove_large_blob(entries: &mut Vec<Value>, large_blob_key: &[u8]) -> bool {
+    let len = entries.len();
+    entries.retain(|entry| large_blobs_response::open_large_blob(entry, large_blob_key).is_none());
+    entries.len() != len
+}
+
+fn check_max_serialized_large_blob_array(info: &Info, len: usize) -> Result<()> {
+    let max = info.max_serialized_large_blob_array as usize;
+    if max > 0 && len > max {
+        return Err(anyhow!(
+            "Large blob array is too large ({} > maxSerializedLargeBlobArray {})",
+            len,
+            max
+        ));
+    }
+    Ok(())
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::digest;
+
+    fn large_blob_data(large_blob_array: Vec<u8>) -> LargeBlobData {
+        let hash = digest::digest(&digest::SHA256, &large_blob_array);
+        LargeBlobData {
+            large_blob_array,
+            hash: hash.as_ref()[0..16].to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_large_blob_array() {
+        let key1 = [0x01; 32];
+        let key2 = [0x02; 32];
+        let entries = vec![
+            large_blobs_command::create_large_blob(&key1, b"blob of credential 1").unwrap(),
+            large_blobs_command::create_large_blob(&key2, &[0x55; 1000]).unwrap(),
+        ];
+        let array = large_blobs_command::create_large_blob_array(entries).unwrap();
+
+        let entries =
+            large_blobs_response::parse_large_blob_array(&large_blob_data(array)).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let blob1 = entries
+            .iter()
+            .find_map(|e| large_blobs_response::decrypt_large_blob(e, &key1));
+        assert_eq!(blob1.unwrap().unwrap(), b"blob of credential 1");
+
+        let blob2 = entries
+            .iter()
+            .find_map(|e| large_blobs_response::decrypt_large_blob(e, &key2));
+        assert_eq!(blob2.unwrap().unwrap(), vec![0x55; 1000]);
+
+        let blob3 = entries
+            .iter()
+            .find_map(|e| large_blobs_response::decrypt_large_blob(e, &[0x03; 32]));
+        assert!(blob3.is_none());
+    }
+
+    #[test]
+    fn test_corrupted_large_blob() {
+        let key1 = [0x01; 32];
+        let key2 = [0x02; 32];
+
+        // encrypted with key1, but the payload is not DEFLATE
+        let nonce = [0x07; 12];
+        let orig_size = 16;
+        let ciphertext = crate::encrypt::enc_aes256_gcm::encrypt_message(
+            &key1,
+            &nonce,
+            &large_blobs_command::large_blob_associated_data(orig_size),
+            &[0xff; 24],
+        )
+        .unwrap();
+        let corrupted = Value::Map(
+            vec![
+                (Value::Integer(0x01), Value::Bytes(ciphertext)),
+                (Value::Integer(0x02), Value::Bytes(nonce.to_vec())),
+                (Value::Integer(0x03), Value::Integer(orig_size.into())),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let other = large_blobs_command::create_large_blob(&key2, b"blob of credential 2").unwrap();
+
+        assert!(large_blobs_response::open_large_blob(&corrupted, &key1).is_some());
+        assert!(large_blobs_response::open_large_blob(&corrupted, &key2).is_none());
+        assert!(large_blobs_response::decrypt_large_blob(&corrupted, &key1)
+            .unwrap()
+            .is_err());
+        assert!(large_blobs_response::decrypt_large_blob(&corrupted, &key2).is_none());
+
+        // the corrupted entry of key1 is removed, the entry of key2 is kept
+        let mut entries = vec![corrupted.clone(), other.clone()];
+        assert!(remove_large_blob(&mut entries, &key1));
+        assert_eq!(entries, vec![other.clone()]);
+        assert!(!remove_large_blob(&mut entries, &key1));
+        assert_eq!(entries, vec![other]);
+
+        // inflates to a different size than origSize
+        let valid = large_blobs_command::create_large_blob(&key1, b"0123456789").unwrap();
+        let (compressed, _) = large_blobs_response::open_large_blob(&valid, &key1).unwrap();
+        assert!(large_blobs_response::inflate_large_blob(&compressed, 10).is_ok());
+        assert!(large_blobs_response::inflate_large_blob(&compressed, 9).is_err());
+        assert!(large_blobs_response::inflate_large_blob(&compressed, 11).is_err());
+    }
+
+    #[test]
+    fn test_large_blob_array_initial() {
+        // initial serialized large-blob array: h'80' || LEFT(SHA-256(h'80'), 16)
+        let data = large_blob_data(vec![0x80]);
+        assert_eq!(hex::encode(&data.hash), "76be8b528d0075f7aae98d6fa57a6d3c");
+        assert!(large_blobs_response::parse_large_blob_array(&data)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            large_blobs_command::create_large_blob_array(vec![]).unwrap(),
+            vec![0x80]
+        );
+
+        // valid array with one (foreign) entry
+        let data = large_blob_data(vec![0x81, 0xa0]);
+        assert_eq!(
+            large_blobs_response::parse_large_blob_array(&data)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // hash mismatch is an error, not the initial empty array
+        let mut mismatch = data;
+        mismatch.hash[0] ^= 0xff;
+        assert!(large_blobs_response::parse_large_blob_array(&mismatch).is_err());
+
+        // malformed CBOR / not an array
+        assert!(
+            large_blobs_response::parse_large_blob_array(&large_blob_data(vec![0x81])).is_err()
+        );
+        assert!(
+            large_blobs_response::parse_large_blob_array(&large_blob_data(vec![0xa0])).is_err()
+        );
+    }
+
+    #[test]
+    fn test_large_blob_fragments() {
+        let info = Info {
+            max_msg_size: 1200,
+            max_serialized_large_blob_array: 4096,
+            ..Default::default()
+        };
+        assert_eq!(max_fragment_length(&info), 1136);
+        assert_eq!(max_fragment_length(&Info::default()), 960);
+        assert!(check_max_serialized_large_blob_array(&info, 4096).is_ok());
+        assert!(check_max_serialized_large_blob_array(&info, 4097).is_err());
+
+        // length only in the first fragment
+        let first =
+            large_blobs_command::create_payload(None, 0, None, Some(&[0x80]), Some(17)).unwrap();
+        let first = crate::util::cbor_bytes_to_map(&first[1..]).unwrap();
+        assert_eq!(first.get(&Value::Integer(0x04)), Some(&Value::Integer(17)));
 
-        large_blobs_response::parse_cbor(&response_cbor)
+        let next = large_blobs_command::create_payload(None, 1, None, Some(&[0x76]), None).unwrap();
+        let next = crate::util::cbor_bytes_to_map(&next[1..]).unwrap();
+        assert_eq!(next.get(&Value::Integer(0x03)), Some(&Value::Integer(1)));
+        assert_eq!(next.get(&Value::Integer(0x04)), None);
     }
 }
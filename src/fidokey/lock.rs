@@ -0,0 +1,160 @@
+use super::FidoKeyHid;
+use crate::ctaphid;
+use anyhow::{anyhow, Result};
+use std::time::{Duration, Instant};
+
+/// Maximum duration of CTAPHID_LOCK
+pub const MAX_LOCK_SECONDS: u8 = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChannelLock {
+    cid: [u8; 4],
+    seconds: u8,
+    renewed: Instant,
+}
+
+impl ChannelLock {
+    fn duration(&self) -> Duration {
+        Duration::from_secs(u64::from(self.seconds))
+    }
+
+    // Half of the duration has passed
+    fn needs_renewal(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.renewed) >= self.duration() / 2
+    }
+
+    // The authenticator has already released the lock,
+    // other clients may have interleaved commands since then
+    fn check_lapsed(&self, now: Instant) -> Result<()> {
+        let elapsed = now.saturating_duration_since(self.renewed);
+        if elapsed >= self.duration() {
+            return Err(anyhow!(
+                "The channel lock lapsed ({} ms since the last command, lock duration {} s)",
+                elapsed.as_millis(),
+                self.seconds
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// CTAPHID_LOCK held on a channel of the authenticator.
+/// The lock is released when the guard is dropped.
+pub struct HidLockGuard<'a> {
+    device: &'a FidoKeyHid,
+}
+
+impl FidoKeyHid {
+    /// Lock the authenticator to one channel for `seconds` (1-10) with CTAPHID_LOCK,
+    /// so that other clients cannot interleave commands.
+    /// While the guard is alive, all commands are sent on the locked channel
+    /// and the lock is renewed when half of its duration has passed.
+    /// A command sent after the lock has lapsed (no command for `seconds`) is an error.
+    pub fn lock(&self, seconds: u8) -> Result<HidLockGuard<'_>> {
+        if self.channel_lock.get().is_some() {
+            return Err(anyhow!("The channel is already locked"));
+        }
+        if seconds == 0 || seconds > MAX_LOCK_SECONDS {
+            return Err(anyhow!(
+                "Lock duration must be 1 to {} seconds",
+                MAX_LOCK_SECONDS
+            ));
+        }
+
+        let cid = ctaphid::ctaphid_init(self)?;
+        ctaphid::ctaphid_lock(self, &cid, seconds)?;
+        self.channel_lock.set(Some(ChannelLock {
+            cid,
+            seconds,
+            renewed: Instant::now(),
+        }));
+
+        Ok(HidLockGuard { device: self })
+    }
+
+    /// Run `f` while holding CTAPHID_LOCK.
+    /// ex. `device.with_lock(10, |d| d.credential_management_enumerate_rps(pin))`
+    pub fn with_lock<T>(&self, seconds: u8, f: impl FnOnce(&Self) -> Result<T>) -> Result<T> {
+        let _guard = self.lock(seconds)?;
+        f(self)
+    }
+
+    // Channel to use while locked. The lock is renewed if needed,
+    // an error if it has lapsed.
+    pub(crate) fn locked_channel(&self) -> Result<Option<[u8; 4]>> {
+        match self.channel_lock.get() {
+            Some(lock) => {
+                if lock.needs_renewal(Instant::now()) {
+                    self.renew_lock(lock)?;
+                }
+                Ok(Some(lock.cid))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn renew_lock(&self, lock: ChannelLock) -> Result<()> {
+        lock.check_lapsed(Instant::now())?;
+        ctaphid::ctaphid_lock(self, &lock.cid, lock.seconds)?;
+        self.channel_lock.set(Some(ChannelLock {
+            renewed: Instant::now(),
+            ..lock
+        }));
+        Ok(())
+    }
+}
+
+impl HidLockGuard<'_> {
+    /// Extend the lock by its duration from now (an error if it has lapsed)
+    pub fn renew(&self) -> Result<()> {
+        match self.device.channel_lock.get() {
+            Some(lock) => self.device.renew_lock(lock),
+            None => Err(anyhow!("The channel is not locked")),
+        }
+    }
+
+    /// Release the lock
+    pub fn unlock(self) -> Result<()> {
+        match self.device.channel_lock.take() {
+            Some(lock) => ctaphid::ctaphid_lock(self.device, &lock.cid, 0),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for HidLockGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(lock) = self.device.channel_lock.take() {
+            let _ = ctaphid::ctaphid_lock(self.device, &lock.cid, 0);
+        }
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_lock_timing() {
+        let renewed = Instant::now();
+        let lock = ChannelLock {
+            cid: [0x01, 0x02, 0x03, 0x04],
+            seconds: 10,
+            renewed,
+        };
+        let after = |ms| renewed + Duration::from_millis(ms);
+
+        assert!(!lock.needs_renewal(renewed));
+        assert!(!lock.needs_renewal(after(4999)));
+        assert!(lock.needs_renewal(after(5000)));
+
+        assert!(lock.check_lapsed(renewed).is_ok());
+        assert!(lock.check_lapsed(after(9999)).is_ok());
+        // ex. a fingerprint capture that waited longer than the lock
+        assert!(lock.check_lapsed(after(10000)).is_err());
+        assert!(lock.check_lapsed(after(60000)).is_err());
+    }
+}
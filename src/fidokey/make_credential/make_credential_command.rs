@@ -1,6 +1,8 @@
 use super::make_credential_params::{CredentialSupportedKeyType, Extension};
 use crate::ctapdef;
+use crate::hmac_ext::HmacExt;
 use crate::util;
+use anyhow::Result;
 use serde_cbor::to_vec;
 use serde_cbor::Value;
 use std::collections::BTreeMap;
@@ -34,8 +36,13 @@ impl Params {
     }
 }
 
-#[must_use]
-pub fn create_payload(params: Params, extensions: Option<&Vec<Extension>>) -> Vec<u8> {
+pub fn create_payload(
+    params: Params,
+    extensions: Option<&Vec<Extension>>,
+    hmac_ext: Option<HmacExt>,
+) -> Result<Vec<u8>> {
+    let hmac_secret_mc = hmac_ext.as_ref().map(HmacExt::to_value).transpose()?;
+
     // 0x01 : clientDataHash
     let cdh = Value::Bytes(params.client_data_hash);
 
@@ -144,6 +151,19 @@ pub fn create_payload(params: Params, extensions: Option<&Vec<Extension>>) -> Ve
                     | Extension::MinPinLength((n, _)) => {
                         map.insert(Value::Text(ext.to_string()), Value::Bool(n.unwrap()));
                     }
+                    Extension::HmacSecretMc(_, _) | Extension::Prf(_, _) => {
+                        // hmac-secret-mc requires hmac-secret
+                        map.insert(
+                            Value::Text(Extension::HmacSecret(None).to_string()),
+                            Value::Bool(true),
+                        );
+                        if let Some(hmac_secret_mc) = &hmac_secret_mc {
+                            map.insert(
+                                Value::Text(Extension::HmacSecretMc(None, None).to_string()),
+                                hmac_secret_mc.clone(),
+                            );
+                        }
+                    }
                 };
             }
             Some(Value::Map(map))
@@ -206,7 +226,7 @@ pub fn create_payload(params: Params, extensions: Option<&Vec<Extension>>) -> Ve
 
     // Command - authenticatorMakeCredential (0x01)
     let mut payload = [ctapdef::AUTHENTICATOR_MAKE_CREDENTIAL].to_vec();
-    payload.append(&mut to_vec(&cbor).unwrap());
+    payload.append(&mut to_vec(&cbor)?);
 
-    payload
+    Ok(payload)
 }
@@ -1,5 +1,6 @@
 use super::make_credential_params::Extension as Mext;
 use super::CredentialProtectionPolicy;
+use crate::fidokey::get_assertion::get_assertion_params::PrfValues;
 use crate::public_key::PublicKey;
 use crate::public_key_credential_descriptor::PublicKeyCredentialDescriptor;
 use crate::public_key_credential_user_entity::PublicKeyCredentialUserEntity;
@@ -9,23 +10,29 @@ use strum_macros::{AsRefStr, Display};
 
 /// Attestation Object
 /// [https://www.w3.org/TR/webauthn/#sctn-attestation](https://www.w3.org/TR/webauthn/#sctn-attestation)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default)]
 pub struct Attestation {
     pub fmt: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub rpid_hash: Vec<u8>,
     pub flags_user_present_result: bool,
     pub flags_user_verified_result: bool,
     pub flags_attested_credential_data_included: bool,
     pub flags_extension_data_included: bool,
     pub sign_count: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::hex"))]
     pub aaguid: Vec<u8>,
     pub credential_descriptor: PublicKeyCredentialDescriptor,
     pub credential_publickey: PublicKey,
     pub extensions: Vec<Extension>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub auth_data: Vec<u8>,
 
     pub attstmt_alg: i32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub attstmt_sig: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url_vec"))]
     pub attstmt_x5c: Vec<Vec<u8>>,
 }
 
@@ -62,29 +69,68 @@ impl fmt::Display for Attestation {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Display, AsRefStr)]
 pub enum Extension {
     #[strum(serialize = "credBlob")]
-    CredBlob((Option<Vec<u8>>, Option<bool>)),
+    #[cfg_attr(feature = "serde", serde(rename = "credBlob"))]
+    CredBlob(
+        #[cfg_attr(
+            feature = "serde",
+            serde(with = "crate::serde_ext::base64url_and_flag")
+        )]
+        (Option<Vec<u8>>, Option<bool>),
+    ),
     #[strum(serialize = "credProtect")]
+    #[cfg_attr(feature = "serde", serde(rename = "credProtect"))]
     CredProtect(Option<CredentialProtectionPolicy>),
     #[strum(serialize = "hmac-secret")]
+    #[cfg_attr(feature = "serde", serde(rename = "hmac-secret"))]
     HmacSecret(Option<bool>),
+    /// (salt1, salt2) on input, (output1, output2) in the attestation.
+    /// salt1 is required on input.
+    #[strum(serialize = "hmac-secret-mc")]
+    #[cfg_attr(feature = "serde", serde(rename = "hmac-secret-mc"))]
+    HmacSecretMc(
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url_option"))]
+        Option<[u8; 32]>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url_option"))]
+        Option<[u8; 32]>,
+    ),
     #[strum(serialize = "largeBlobKey")]
-    LargeBlobKey((Option<bool>, Option<Vec<u8>>)),
+    #[cfg_attr(feature = "serde", serde(rename = "largeBlobKey"))]
+    LargeBlobKey(
+        #[cfg_attr(
+            feature = "serde",
+            serde(with = "crate::serde_ext::flag_and_base64url")
+        )]
+        (Option<bool>, Option<Vec<u8>>),
+    ),
     #[strum(serialize = "minPinLength")]
+    #[cfg_attr(feature = "serde", serde(rename = "minPinLength"))]
     MinPinLength((Option<bool>, Option<u8>)),
+    /// WebAuthn PRF extension, enabled with hmac-secret and evaluated with hmac-secret-mc.
+    /// (eval, results)
+    #[strum(serialize = "prf")]
+    #[cfg_attr(feature = "serde", serde(rename = "prf"))]
+    Prf(Option<PrfValues>, Option<PrfValues>),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub enum CredentialSupportedKeyType {
+    #[default]
     Ecdsa256 = -7,
     Ed25519 = -8,
 }
 
-impl std::default::Default for CredentialSupportedKeyType {
-    fn default() -> Self {
-        Self::Ecdsa256
+impl CredentialSupportedKeyType {
+    /// From the COSE algorithm identifier (None if not supported)
+    pub fn from_alg(alg: i32) -> Option<Self> {
+        match alg {
+            -7 => Some(Self::Ecdsa256),
+            -8 => Some(Self::Ed25519),
+            _ => None,
+        }
     }
 }
 
@@ -1,8 +1,10 @@
 use super::make_credential_params::{Attestation, Extension};
 use super::CredentialProtectionPolicy;
+use crate::encrypt::shared_secret::SharedSecret;
+use crate::hmac_ext::HmacExt;
 use crate::public_key::PublicKey;
 use crate::util;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use byteorder::{BigEndian, ReadBytesExt};
 use serde_cbor::Value;
 use std::io::Cursor;
@@ -24,7 +26,11 @@ fn parse_cbor_att_stmt(obj: &Value, att: &mut Attestation) -> Result<()> {
     Ok(())
 }
 
-fn parse_cbor_authdata(authdata: &[u8], attestation: &mut Attestation) -> Result<()> {
+fn parse_cbor_authdata(
+    authdata: &[u8],
+    attestation: &mut Attestation,
+    shared_secret: Option<&SharedSecret>,
+) -> Result<()> {
     // copy
     attestation.auth_data = authdata.to_vec();
 
@@ -94,6 +100,15 @@ fn parse_cbor_authdata(authdata: &[u8], attestation: &mut Attestation) -> Result
                 if *member == Extension::HmacSecret(None).to_string() {
                     let v = util::cbor_value_to_bool(val)?;
                     attestation.extensions.push(Extension::HmacSecret(Some(v)));
+                } else if *member == Extension::HmacSecretMc(None, None).to_string() {
+                    // "hmac-secret-mc": encrypt(shared secret, output1 || output2)
+                    let enc = util::cbor_value_to_vec_u8(val)?;
+                    let shared_secret = shared_secret
+                        .ok_or_else(|| anyhow!("hmac-secret-mc output without shared secret"))?;
+                    let (output1, output2) = HmacExt::decrypt_output(shared_secret, &enc)?;
+                    attestation
+                        .extensions
+                        .push(Extension::HmacSecretMc(Some(output1), output2));
                 } else if *member == Extension::CredProtect(None).to_string() {
                     let v: u32 = util::cbor_value_to_num(val)?;
                     attestation.extensions.push(Extension::CredProtect(Some(
@@ -119,14 +134,18 @@ fn parse_cbor_authdata(authdata: &[u8], attestation: &mut Attestation) -> Result
     Ok(())
 }
 
-pub fn parse_cbor(bytes: &[u8]) -> Result<Attestation> {
+pub fn parse_cbor(bytes: &[u8], shared_secret: &Option<SharedSecret>) -> Result<Attestation> {
     let mut attestation = Attestation::default();
     let maps = util::cbor_bytes_to_map(bytes)?;
     for (key, val) in &maps {
         if let Value::Integer(member) = key {
             match member {
                 0x01 => attestation.fmt = util::cbor_value_to_str(val)?,
-                0x02 => parse_cbor_authdata(&util::cbor_value_to_vec_u8(val)?, &mut attestation)?,
+                0x02 => parse_cbor_authdata(
+                    &util::cbor_value_to_vec_u8(val)?,
+                    &mut attestation,
+                    shared_secret.as_ref(),
+                )?,
                 0x03 => parse_cbor_att_stmt(val, &mut attestation)?,
                 0x05 => {
                     let lbk = util::cbor_value_to_vec_u8(val)?;
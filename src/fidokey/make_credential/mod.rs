@@ -4,11 +4,15 @@ pub mod make_credential_response;
 use super::{
     credential_management::credential_management_params::CredentialProtectionPolicy, FidoKeyHid,
 };
+use crate::fidokey::get_assertion::get_assertion_params::PrfValues;
 use crate::{
-    ctaphid, encrypt::enc_hmac_sha_256,
+    ctaphid,
+    encrypt::enc_hmac_sha_256,
+    fidokey::pin::PinUvAuthProtocol,
+    hmac_ext::{HmacExt, HmacSecretPair},
     public_key_credential_user_entity::PublicKeyCredentialUserEntity,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 pub use make_credential_params::{
     Attestation, CredentialSupportedKeyType, Extension, Extension as Mext, MakeCredentialArgs,
     MakeCredentialArgsBuilder,
@@ -16,9 +20,27 @@ pub use make_credential_params::{
 
 impl FidoKeyHid {
     pub fn make_credential_with_args(&self, args: &MakeCredentialArgs) -> Result<Attestation> {
+        let extensions = args.extensions.as_ref();
+
+        // hmac-secret-mc uses the first PIN/UV Auth Protocol the authenticator supports
+        let salts = hmac_secret_mc_salts(extensions)?;
+        let pin_uv_auth_protocol = if salts.is_some() {
+            PinUvAuthProtocol::select(&self.cached_info()?.pin_uv_auth_protocols)
+        } else {
+            PinUvAuthProtocol::One
+        };
+
         // init
         let cid = ctaphid::ctaphid_init(self)?;
 
+        let hmac_ext = if let Some((salt1, salt2)) = salts {
+            let mut hmac_ext = HmacExt::default();
+            hmac_ext.create(self, &cid, pin_uv_auth_protocol, &salt1, salt2.as_ref())?;
+            Some(hmac_ext)
+        } else {
+            None
+        };
+
         let user_id = {
             args.user_entity
                 .as_ref()
@@ -54,20 +76,26 @@ impl FidoKeyHid {
                 }
             }
 
-            // TODO
-            let extensions = if args.extensions.is_some() {
-                Some(args.extensions.as_ref().unwrap())
-            } else {
-                None
-            };
-
-            make_credential_command::create_payload(params, extensions)
+            make_credential_command::create_payload(params, extensions, hmac_ext.clone())?
         };
 
         // send & response
         let response_cbor = ctaphid::ctaphid_cbor(self, &cid, &send_payload)?;
 
-        let att = make_credential_response::parse_cbor(&response_cbor)?;
+        let mut att = make_credential_response::parse_cbor(
+            &response_cbor,
+            &hmac_ext.map(|ext| ext.shared_secret),
+        )?;
+
+        // prf results are the hmac-secret-mc outputs
+        if extensions.is_some_and(|exts| exts.iter().any(|x| matches!(x, Mext::Prf(_, _)))) {
+            for ext in &mut att.extensions {
+                if let Mext::HmacSecretMc(Some(output1), output2) = ext {
+                    let results = PrfValues::from_outputs(output1, output2.as_ref());
+                    *ext = Mext::Prf(None, Some(results));
+                }
+            }
+        }
         Ok(att)
     }
 
@@ -144,6 +172,33 @@ impl FidoKeyHid {
         self.make_credential_with_args(&arg)
     }
 }
+/// hmac-secret-mc salts from the hmac-secret-mc or prf extension
+fn hmac_secret_mc_salts(extensions: Option<&Vec<Mext>>) -> Result<Option<HmacSecretPair>> {
+    let extensions = match extensions {
+        Some(extensions) => extensions,
+        None => return Ok(None),
+    };
+
+    let mut salts = None;
+    for ext in extensions {
+        let s = match ext {
+            Mext::HmacSecretMc(Some(salt1), salt2) => Some((*salt1, *salt2)),
+            Mext::HmacSecretMc(None, _) => return Err(anyhow!("hmac-secret-mc requires salt1")),
+            Mext::Prf(Some(eval), _) => Some(eval.to_salts()),
+            _ => None,
+        };
+        if s.is_some() {
+            if salts.is_some() {
+                return Err(anyhow!(
+                    "hmac-secret-mc and prf cannot be requested together"
+                ));
+            }
+            salts = s;
+        }
+    }
+    Ok(salts)
+}
+
 //
 
 // test
@@ -174,7 +229,7 @@ mod tests {
 
             params.pin_auth = pin_auth;
 
-            make_credential_command::create_payload(params, None)
+            make_credential_command::create_payload(params, None, None).unwrap()
         };
 
         //println!(
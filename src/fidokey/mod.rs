@@ -1,7 +1,16 @@
-use crate::KeyID;
+use crate::quirks::Quirks;
+use crate::{KeyID, Timeouts};
 use anyhow::{anyhow, Result};
+use get_info::Info;
 use hidapi::HidApi;
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
 use std::ffi::CString;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
 
 // Complex Submodules
 pub mod authenticator_config;
@@ -13,11 +22,26 @@ pub mod large_blobs;
 pub mod make_credential;
 pub mod pin;
 
+#[cfg(feature = "async")]
+pub mod asynchronous;
+
 // Simple Submodules
+mod capabilities;
+mod lock;
+mod ping;
 mod selection;
+mod shared;
 mod sub_command_base;
 mod wink;
 
+pub use lock::{HidLockGuard, MAX_LOCK_SECONDS};
+pub use ping::PingStats;
+pub use selection::select_device;
+pub use shared::FidoKeyHidShared;
+
+#[cfg(feature = "async")]
+pub use asynchronous::FidoKeyHidAsync;
+
 pub use get_assertion::{Extension as AssertionExtension, GetAssertionArgsBuilder};
 
 pub use make_credential::{
@@ -28,14 +52,58 @@ pub trait FidoKey {
     fn new(params: &[crate::KeyID], cfg: &crate::LibCfg) -> Result<Self> where Self: std::marker::Sized;
     fn write(&self, cmd: &[u8]) -> Result<usize, String>;
     fn read(&self) -> Result<Vec<u8>, String>;
+    /// Ok(None) if no packet arrives within `timeout`.
+    /// The default implementation ignores `timeout` and blocks in `read`.
+    fn read_timeout(&self, _timeout: Duration) -> Result<Option<Vec<u8>>, String> {
+        self.read().map(Some)
+    }
 }
 
 pub struct FidoKeyHid {
     device_internal: hidapi::HidDevice,
+    path: CString,
     pub enable_log: bool,
-    pub use_pre_bio_enrollment: bool,
-    pub use_pre_credential_management: bool,
+    /// Override of the bioEnrollment command selection (None: automatic)
+    pub use_pre_bio_enrollment: Option<bool>,
+    /// Override of the credentialManagement command selection (None: automatic)
+    pub use_pre_credential_management: Option<bool>,
     pub keep_alive_msg: String,
+    timeouts: Cell<Timeouts>,
+    channel_lock: Cell<Option<lock::ChannelLock>>,
+    cancel: Option<Arc<dyn CancelToken>>,
+    quirks: Quirks,
+    // authenticatorGetInfo (see `cached_info`)
+    cached_info: RefCell<Option<Info>>,
+}
+
+// Restores the value of the cell on drop
+struct Restore<'a, T: Copy> {
+    cell: &'a Cell<T>,
+    saved: T,
+}
+
+impl<'a, T: Copy> Restore<'a, T> {
+    fn replace(cell: &'a Cell<T>, value: T) -> Self {
+        let saved = cell.replace(value);
+        Self { cell, saved }
+    }
+}
+
+impl<T: Copy> Drop for Restore<'_, T> {
+    fn drop(&mut self) {
+        self.cell.set(self.saved);
+    }
+}
+
+// Cancels the request in progress (see `FidoKeyHidAsync` and `select_device`)
+pub(crate) trait CancelToken: Send + Sync {
+    fn is_cancelled(&self) -> bool;
+}
+
+impl CancelToken for AtomicBool {
+    fn is_cancelled(&self) -> bool {
+        self.load(Ordering::SeqCst)
+    }
 }
 
 impl FidoKey for FidoKeyHid {
@@ -47,14 +115,37 @@ impl FidoKey for FidoKeyHid {
                 continue;
             }
 
-            if let Ok(dev) = api.open_path(&path.unwrap()) {
-                let result = Self {
+            let path = path.unwrap();
+            if let Ok(path) = path.to_str() {
+                crate::hid::check_report_size(path)?;
+            }
+            if let Ok(dev) = api.open_path(&path) {
+                let (vid, pid) = api
+                    .device_list()
+                    .find(|x| x.path() == path.as_c_str())
+                    .map_or((0, 0), |x| (x.vendor_id(), x.product_id()));
+
+                let mut result = Self {
                     device_internal: dev,
+                    path,
                     enable_log: cfg.enable_log,
                     use_pre_bio_enrollment: cfg.use_pre_bio_enrollment,
                     use_pre_credential_management: cfg.use_pre_credential_management,
                     keep_alive_msg: cfg.keep_alive_msg.to_string(),
+                    timeouts: Cell::new(cfg.timeouts),
+                    channel_lock: Cell::new(None),
+                    cancel: None,
+                    quirks: cfg.quirks.lookup(vid, pid, None),
+                    cached_info: RefCell::new(None),
                 };
+
+                // AAGUID quirks
+                if cfg.quirks.has_aaguid_entries() {
+                    if let Ok(info) = result.cached_info() {
+                        result.quirks = cfg.quirks.lookup(vid, pid, Some(&info.aaguid));
+                    }
+                }
+
                 return Ok(result);
             }
         }
@@ -74,6 +165,114 @@ impl FidoKey for FidoKeyHid {
             .map(|_| buf)
             .map_err(|_| "read error".into())
     }
+
+    fn read_timeout(&self, timeout: Duration) -> Result<Option<Vec<u8>>, String> {
+        let mut buf: Vec<u8> = vec![0; 64];
+        let timeout_ms = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        match self.device_internal.read_timeout(&mut buf[..], timeout_ms) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(buf)),
+            Err(_) => Err("read error".into()),
+        }
+    }
+}
+
+impl FidoKeyHid {
+    /// Timeout of waiting for a response from the authenticator
+    /// (requests without a specific timeout, see `Timeouts::default`)
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeouts.get().default
+    }
+
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        let mut timeouts = self.timeouts.get();
+        timeouts.default = timeout;
+        self.timeouts.set(timeouts);
+    }
+
+    /// Timeouts of waiting for a response from the authenticator per request
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts.get()
+    }
+
+    pub fn set_timeouts(&self, timeouts: Timeouts) {
+        self.timeouts.set(timeouts);
+    }
+
+    /// Run `f` with `timeout` for all requests instead of the configured timeouts.
+    /// The configured timeouts are restored even if `f` panics.
+    /// ex. `device.with_timeout(Duration::from_secs(3), |d| d.get_info())`
+    pub fn with_timeout<T>(&self, timeout: Duration, f: impl FnOnce(&Self) -> T) -> T {
+        let _restore = Restore::replace(&self.timeouts, Timeouts::all(Some(timeout)));
+        f(self)
+    }
+
+    /// Workarounds applied to this device (see `LibCfg::quirks`)
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    /// The CTAP 2.1-PRE bioEnrollment command (0x40) is used instead of 0x09.
+    /// `use_pre_bio_enrollment` > quirks > authenticatorGetInfo
+    pub fn is_pre_bio_enrollment(&self) -> Result<bool> {
+        match self.use_pre_bio_enrollment.or(self.quirks.use_pre_bio_enrollment) {
+            Some(pre) => Ok(pre),
+            None => self.pre_commands().map(|pre| pre.0),
+        }
+    }
+
+    /// The CTAP 2.1-PRE credentialManagement command (0x41) is used instead of 0x0A.
+    /// `use_pre_credential_management` > quirks > authenticatorGetInfo
+    pub fn is_pre_credential_management(&self) -> Result<bool> {
+        match self
+            .use_pre_credential_management
+            .or(self.quirks.use_pre_credential_management)
+        {
+            Some(pre) => Ok(pre),
+            None => self.pre_commands().map(|pre| pre.1),
+        }
+    }
+
+    fn pre_commands(&self) -> Result<(bool, bool)> {
+        let info = self.cached_info()?;
+        Ok((
+            info.use_pre_bio_enrollment(),
+            info.use_pre_credential_management(),
+        ))
+    }
+
+    // authenticatorGetInfo once per device
+    // (versions, options for the command selection, pinUvAuthProtocols, AAGUID, large blob limits)
+    pub(crate) fn cached_info(&self) -> Result<Info> {
+        if let Some(info) = self.cached_info.borrow().as_ref() {
+            return Ok(info.clone());
+        }
+        let info = self.get_info()?;
+        *self.cached_info.borrow_mut() = Some(info.clone());
+        Ok(info)
+    }
+
+    /// Serial number string of the HID device
+    pub fn serial_number(&self) -> Option<String> {
+        self.device_internal
+            .get_serial_number_string()
+            .ok()
+            .flatten()
+            .filter(|s| !s.is_empty())
+    }
+
+    /// false if the device has been disconnected
+    pub fn is_connected(&self) -> bool {
+        HidApi::new()
+            .map(|api| api.device_list().any(|dev| dev.path() == self.path.as_c_str()))
+            .unwrap_or(true)
+    }
+
+    // None: the request cannot be cancelled
+    // Some(true): cancellation has been requested
+    pub(crate) fn cancel_requested(&self) -> Option<bool> {
+        self.cancel.as_ref().map(|cancel| cancel.is_cancelled())
+    }
 }
 
 /// Abstraction for getting a path from a provided `HidParam`
@@ -101,3 +300,23 @@ fn get_path(api: &hidapi::HidApi, param: &crate::KeyID) -> Option<CString> {
 
     None
 }
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_on_panic() {
+        let timeouts = Cell::new(Timeouts::default());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _restore = Restore::replace(&timeouts, Timeouts::all(None));
+            assert_eq!(timeouts.get(), Timeouts::all(None));
+            panic!("request failed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(timeouts.get(), Timeouts::default());
+    }
+}
@@ -17,6 +17,29 @@ pub enum SubCommand {
     GetPinUvAuthTokenUsingPinWithPermissions = 0x09,
 }
 
+/// PIN/UV Auth Protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PinUvAuthProtocol {
+    #[default]
+    One = 1,
+    Two = 2,
+}
+
+impl PinUvAuthProtocol {
+    /// First protocol in getInfo `pinUvAuthProtocols` that this library supports
+    #[must_use]
+    pub fn select(pin_uv_auth_protocols: &[u32]) -> Self {
+        pin_uv_auth_protocols
+            .iter()
+            .find_map(|p| match p {
+                1 => Some(Self::One),
+                2 => Some(Self::Two),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
 #[allow(dead_code)]
 pub enum Permission {
     MakeCredential = 0x01,
@@ -25,6 +48,8 @@ pub enum Permission {
     BioEnrollment = 0x08,
     LargeBlobWrite = 0x10,
     AuthenticatorConfiguration = 0x20,
+    /// pcmr: persistent credential management read-only (CTAP 2.2)
+    PersistentCredentialManagementReadOnly = 0x40,
 }
 
 fn create_payload_get_uv_retries() -> Vec<u8> {
@@ -41,9 +66,13 @@ fn create_payload_get_retries() -> Vec<u8> {
     to_payload(map)
 }
 
-fn create_payload_get_keyagreement() -> Vec<u8> {
+#[must_use]
+pub fn create_payload_get_keyagreement(pin_uv_auth_protocol: PinUvAuthProtocol) -> Vec<u8> {
     let mut map = BTreeMap::new();
-    insert_pin_protocol(&mut map);
+    map.insert(
+        Value::Integer(0x01),
+        Value::Integer(pin_uv_auth_protocol as i128),
+    );
     insert_sub_command(&mut map, SubCommand::GetKeyAgreement);
     to_payload(map)
 }
@@ -204,7 +233,7 @@ fn insert_pin_hash_enc(map: &mut BTreeMap<Value, Value>, pin_hash_enc: &[u8]) {
 pub fn create_payload(sub_command: SubCommand) -> Result<Vec<u8>> {
     match sub_command {
         SubCommand::GetRetries => Ok(create_payload_get_retries()),
-        SubCommand::GetKeyAgreement => Ok(create_payload_get_keyagreement()),
+        SubCommand::GetKeyAgreement => Ok(create_payload_get_keyagreement(PinUvAuthProtocol::One)),
         SubCommand::SetPin
         | SubCommand::ChangePin
         | SubCommand::GetPinToken
@@ -1,12 +1,14 @@
 mod client_pin;
 mod client_pin_command;
 mod client_pin_response;
+mod persistent_token;
 use super::FidoKeyHid;
 use crate::ctaphid;
 use anyhow::Result;
 use client_pin_command::SubCommand as PinCmd;
 pub use client_pin_command::*;
 pub use client_pin_response::*;
+pub use persistent_token::PersistentTokenStore;
 
 impl FidoKeyHid {
     /// Get PIN retry count
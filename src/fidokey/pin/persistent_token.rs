@@ -0,0 +1,257 @@
+/*!
+Persistent pinUvAuthToken (CTAP 2.2)
+
+A pinUvAuthToken with the `perCredMgmtRO` (pcmr) permission stays valid across power cycles
+until the PIN is changed or the authenticator is reset.
+It is stored on disk encrypted with AES-256-GCM.
+The key is provided by the caller (ex. from the OS keyring).
+
+```ignore
+let store = PersistentTokenStore::new(dir, &key);
+device.obtain_persistent_pin_token(&store, pin)?;
+
+// later, without the PIN
+let rps = device.credential_management_enumerate_rps_persistent(&store)?;
+```
+*/
+use super::FidoKeyHid;
+use crate::encrypt::enc_aes256_gcm;
+use crate::error::CtapHidError;
+use crate::pintoken::PinToken;
+use anyhow::{anyhow, Result};
+use ring::{digest, rand, rand::SecureRandom};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+const FILE_VERSION: u8 = 0x01;
+const NONCE_SIZE: usize = 12;
+
+/// Encrypted storage of persistent pinUvAuthTokens, one file per authenticator
+pub struct PersistentTokenStore {
+    dir: PathBuf,
+    key: [u8; 32],
+}
+
+impl PersistentTokenStore {
+    /// `dir`: directory of the token files, `key`: AES-256 key
+    pub fn new(dir: impl Into<PathBuf>, key: &[u8; 32]) -> Self {
+        Self {
+            dir: dir.into(),
+            key: *key,
+        }
+    }
+
+    /// Remove the token of the device (ex. `FidoKeyHid::persistent_token_id`)
+    pub fn remove(&self, device_id: &[u8]) -> Result<()> {
+        match fs::remove_file(self.file_path(device_id)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn contains(&self, device_id: &[u8]) -> bool {
+        self.file_path(device_id).exists()
+    }
+
+    pub(crate) fn load(&self, device_id: &[u8]) -> Result<Option<PinToken>> {
+        let data = match fs::read(self.file_path(device_id)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        // version(1) || nonce(12) || AES-256-GCM(token)
+        if data.len() <= 1 + NONCE_SIZE || data[0] != FILE_VERSION {
+            return Err(anyhow!("Invalid persistent token file"));
+        }
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&data[1..(1 + NONCE_SIZE)]);
+
+        let token = enc_aes256_gcm::decrypt_message(
+            &self.key,
+            &nonce,
+            device_id,
+            &data[(1 + NONCE_SIZE)..],
+        )?;
+        Ok(Some(PinToken::new(&token)))
+    }
+
+    pub(crate) fn save(&self, device_id: &[u8], token: &PinToken) -> Result<()> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("Failed to generate a nonce"))?;
+
+        let mut data = vec![FILE_VERSION];
+        data.extend_from_slice(&nonce);
+        data.append(&mut enc_aes256_gcm::encrypt_message(
+            &self.key, &nonce, device_id, &token.key,
+        )?);
+
+        // write a new file and rename it over the old one, so that the file is
+        // created with 0600 and a crash does not leave a partial token file
+        fs::create_dir_all(&self.dir)?;
+        let path = self.file_path(device_id);
+        let tmp_path = path.with_extension(format!("pcmr.{}.tmp", hex::encode(nonce)));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let result = options.open(&tmp_path).and_then(|mut file| {
+            file.write_all(&data)?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, &path)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        Ok(result?)
+    }
+
+    /// The token has been invalidated if the authenticator rejected it
+    /// (CTAP2_ERR_PIN_AUTH_INVALID): remove it from the store.
+    pub(crate) fn check_invalidated<T>(&self, device_id: &[u8], result: Result<T>) -> Result<T> {
+        match result {
+            Err(err)
+                if matches!(
+                    err.downcast_ref::<CtapHidError>(),
+                    Some(CtapHidError::Status(0x33)) // CTAP2_ERR_PIN_AUTH_INVALID
+                ) =>
+            {
+                self.remove(device_id)?;
+                Err(err.context(CtapHidError::PersistentTokenInvalidated))
+            }
+            ret => ret,
+        }
+    }
+
+    fn file_path(&self, device_id: &[u8]) -> PathBuf {
+        let hash = digest::digest(&digest::SHA256, device_id);
+        self.dir
+            .join(format!("{}.pcmr", hex::encode(&hash.as_ref()[0..16])))
+    }
+}
+
+impl FidoKeyHid {
+    /// Key of the persistent token of this authenticator (AAGUID || serial number).
+    /// Error if the authenticator has no serial number,
+    /// since authenticators of the same model could not be told apart.
+    pub fn persistent_token_id(&self) -> Result<Vec<u8>> {
+        let serial_number = self.serial_number().ok_or_else(|| {
+            anyhow!("The authenticator has no serial number to bind a persistent token to")
+        })?;
+        let mut id = self.cached_info()?.aaguid;
+        id.extend_from_slice(serial_number.as_bytes());
+        Ok(id)
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persistent_token_store() {
+        let dir = std::env::temp_dir().join(format!("ctap-hid-fido2-pcmr-{}", std::process::id()));
+        let store = PersistentTokenStore::new(&dir, &[0x11; 32]);
+        let id1 = b"device-1";
+        let id2 = b"device-2";
+
+        assert!(store.load(id1).unwrap().is_none());
+        store.save(id1, &PinToken::new(&[0xaa; 32])).unwrap();
+        store.save(id2, &PinToken::new(&[0xbb; 32])).unwrap();
+        assert!(store.contains(id1));
+        assert_eq!(store.load(id1).unwrap().unwrap().key, vec![0xaa; 32]);
+        assert_eq!(store.load(id2).unwrap().unwrap().key, vec![0xbb; 32]);
+
+        // the file is encrypted
+        let data = fs::read(store.file_path(id1)).unwrap();
+        assert!(!data.windows(32).any(|w| w == [0xaa; 32]));
+
+        // wrong key
+        let other = PersistentTokenStore::new(&dir, &[0x22; 32]);
+        assert!(other.load(id1).is_err());
+
+        store.remove(id1).unwrap();
+        assert!(store.load(id1).unwrap().is_none());
+        store.remove(id1).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_persistent_token_overwrite() {
+        let dir = std::env::temp_dir().join(format!(
+            "ctap-hid-fido2-pcmr-overwrite-{}",
+            std::process::id()
+        ));
+        let store = PersistentTokenStore::new(&dir, &[0x11; 32]);
+        let id = b"device-1";
+
+        // an existing file with other permissions
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(store.file_path(id), b"partial").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(store.file_path(id), fs::Permissions::from_mode(0o644)).unwrap();
+        }
+
+        store.save(id, &PinToken::new(&[0xcc; 32])).unwrap();
+        assert_eq!(store.load(id).unwrap().unwrap().key, vec![0xcc; 32]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(store.file_path(id))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // no temporary file is left
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_persistent_token_invalidated() {
+        let dir = std::env::temp_dir().join(format!(
+            "ctap-hid-fido2-pcmr-invalidated-{}",
+            std::process::id()
+        ));
+        let store = PersistentTokenStore::new(&dir, &[0x11; 32]);
+        let id = b"device-1";
+        store.save(id, &PinToken::new(&[0xaa; 32])).unwrap();
+
+        // success and other errors keep the token
+        assert_eq!(store.check_invalidated(id, Ok(1)).unwrap(), 1);
+        let pin_invalid =
+            anyhow::Error::new(CtapHidError::Status(0x31)).context("response_status err");
+        assert!(store.check_invalidated::<()>(id, Err(pin_invalid)).is_err());
+        assert!(store.contains(id));
+
+        // CTAP2_ERR_PIN_AUTH_INVALID removes the token
+        let auth_invalid =
+            anyhow::Error::new(CtapHidError::Status(0x33)).context("response_status err");
+        let err = store
+            .check_invalidated::<()>(id, Err(auth_invalid))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CtapHidError>(),
+            Some(&CtapHidError::PersistentTokenInvalidated)
+        );
+        assert!(!store.contains(id));
+        assert!(store.load(id).unwrap().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
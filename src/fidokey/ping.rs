@@ -0,0 +1,135 @@
+use super::FidoKeyHid;
+use crate::ctaphid;
+use crate::str_buf::StrBuf;
+use anyhow::{anyhow, Result};
+use ring::{rand, rand::SecureRandom};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Round-trip statistics of CTAPHID_PING
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PingStats {
+    pub payload_len: usize,
+    pub count: usize,
+    pub min: Duration,
+    pub max: Duration,
+    pub avg: Duration,
+    /// Bytes per second sent and echoed back
+    pub throughput: u64,
+}
+
+impl PingStats {
+    pub fn from_samples(payload_len: usize, samples: &[Duration]) -> Self {
+        if samples.is_empty() {
+            return Self {
+                payload_len,
+                ..Default::default()
+            };
+        }
+
+        let total: Duration = samples.iter().sum();
+        let bytes = (2 * payload_len * samples.len()) as f64;
+        let throughput = if total.as_secs_f64() > 0.0 {
+            (bytes / total.as_secs_f64()) as u64
+        } else {
+            0
+        };
+
+        Self {
+            payload_len,
+            count: samples.len(),
+            min: samples.iter().min().copied().unwrap_or_default(),
+            max: samples.iter().max().copied().unwrap_or_default(),
+            avg: total / samples.len() as u32,
+            throughput,
+        }
+    }
+}
+
+impl fmt::Display for PingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut strbuf = StrBuf::new(16);
+        strbuf
+            .append("- payload_len", &self.payload_len)
+            .append("- count", &self.count)
+            .append("- min", &format!("{:?}", self.min))
+            .append("- avg", &format!("{:?}", self.avg))
+            .append("- max", &format!("{:?}", self.max))
+            .append("- throughput", &format!("{} bytes/s", self.throughput));
+        write!(f, "{}", strbuf.build())
+    }
+}
+
+impl FidoKeyHid {
+    /// Send CTAPHID_PING with a random payload of `payload_len` bytes (max 7609)
+    /// and verify the echo. Returns the round-trip time.
+    pub fn ping(&self, payload_len: usize) -> Result<Duration> {
+        let cid = ctaphid::ctaphid_init(self)?;
+        ping_on(self, &cid, payload_len)
+    }
+
+    /// Send CTAPHID_PING `count` times and return the round-trip statistics.
+    pub fn ping_stats(&self, payload_len: usize, count: usize) -> Result<PingStats> {
+        let cid = ctaphid::ctaphid_init(self)?;
+
+        let mut samples = vec![];
+        for _ in 0..count {
+            samples.push(ping_on(self, &cid, payload_len)?);
+        }
+        Ok(PingStats::from_samples(payload_len, &samples))
+    }
+}
+
+fn ping_on(device: &FidoKeyHid, cid: &[u8], payload_len: usize) -> Result<Duration> {
+    let mut payload = vec![0u8; payload_len];
+    rand::SystemRandom::new()
+        .fill(&mut payload)
+        .map_err(|_| anyhow!("Failed to create ping payload"))?;
+
+    let start = Instant::now();
+    let echo = ctaphid::ctaphid_ping(device, cid, &payload)?;
+    let elapsed = start.elapsed();
+
+    if echo != payload {
+        let pos = echo
+            .iter()
+            .zip(payload.iter())
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| echo.len().min(payload.len()));
+        return Err(anyhow!(
+            "CTAPHID_PING echo mismatch at byte {} (sent {} bytes, received {} bytes)",
+            pos,
+            payload.len(),
+            echo.len()
+        ));
+    }
+
+    Ok(elapsed)
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_stats() {
+        let samples = [
+            Duration::from_millis(10),
+            Duration::from_millis(30),
+            Duration::from_millis(20),
+        ];
+        let stats = PingStats::from_samples(1000, &samples);
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.min, Duration::from_millis(10));
+        assert_eq!(stats.max, Duration::from_millis(30));
+        assert_eq!(stats.avg, Duration::from_millis(20));
+        // 6000 bytes in 60ms
+        assert_eq!(stats.throughput, 100_000);
+
+        assert_eq!(PingStats::from_samples(1000, &[]).count, 0);
+    }
+}
@@ -1,12 +1,68 @@
-use super::FidoKeyHid;
-use crate::{ctapdef, ctaphid};
-use anyhow::Result;
+use super::{FidoKey, FidoKeyHid};
+use crate::error::CtapHidError;
+use crate::{ctapdef, ctaphid, HidInfo, LibCfg, Timeouts};
+use anyhow::{anyhow, Error, Result};
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+use std::sync::{atomic::AtomicBool, mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// U2F_REGISTER with a dummy challenge and application parameter
+const U2F_REGISTER: u8 = 0x01;
+const U2F_SW_NO_ERROR: u16 = 0x9000;
+const U2F_SW_CONDITIONS_NOT_SATISFIED: u16 = 0x6985;
+const U2F_POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 fn create_payload() -> Vec<u8> {
     // 6.9. authenticatorSelection (0x0B)
     vec![ctapdef::AUTHENTICATOR_SELECTION]
 }
 
+// authenticatorMakeCredential with a zero length pinAuth (CTAP 2.0).
+// The authenticator waits for user presence, then returns
+// CTAP2_ERR_PIN_NOT_SET or CTAP2_ERR_PIN_INVALID without making a credential.
+fn create_dummy_make_credential_payload() -> Result<Vec<u8>> {
+    let text = |s: &str| Value::Text(s.to_string());
+    let map = |entries: Vec<(Value, Value)>| Value::Map(entries.into_iter().collect());
+
+    let mut make_credential = BTreeMap::new();
+    make_credential.insert(Value::Integer(0x01), Value::Bytes(vec![0; 32]));
+    make_credential.insert(
+        Value::Integer(0x02),
+        map(vec![(text("id"), text(".dummy"))]),
+    );
+    make_credential.insert(
+        Value::Integer(0x03),
+        map(vec![
+            (text("id"), Value::Bytes(vec![0x01])),
+            (text("name"), text("dummy")),
+        ]),
+    );
+    make_credential.insert(
+        Value::Integer(0x04),
+        Value::Array(vec![map(vec![
+            (text("alg"), Value::Integer(-7)),
+            (text("type"), text("public-key")),
+        ])]),
+    );
+    make_credential.insert(Value::Integer(0x08), Value::Bytes(vec![]));
+    make_credential.insert(Value::Integer(0x09), Value::Integer(1));
+
+    let mut payload = vec![ctapdef::AUTHENTICATOR_MAKE_CREDENTIAL];
+    payload.append(&mut serde_cbor::to_vec(&Value::Map(make_credential))?);
+    Ok(payload)
+}
+
+// Statuses of the dummy authenticatorMakeCredential after user presence
+fn is_touched_status(status: u8) -> bool {
+    matches!(
+        status,
+        // CTAP2_ERR_PIN_INVALID, CTAP2_ERR_PIN_AUTH_INVALID, CTAP2_ERR_PIN_NOT_SET
+        0x31 | 0x33 | 0x35
+    )
+}
+
 impl FidoKeyHid {
     /// Selection (CTAP 2.1)
     pub fn selection(&self) -> Result<String> {
@@ -15,4 +71,160 @@ impl FidoKeyHid {
         let _response_cbor = ctaphid::ctaphid_cbor(self, &cid, &send_payload)?;
         Ok(String::new())
     }
+
+    // Wait for user presence.
+    // authenticatorSelection if supported, a dummy authenticatorMakeCredential
+    // if U2F is not (NMSG), otherwise a dummy U2F register.
+    fn wait_for_touch(&self, deadline: Instant) -> Result<()> {
+        let caps = self.get_hid_device_capabilities()?;
+        if caps.cbor {
+            let info = self.cached_info()?;
+            if info
+                .versions
+                .iter()
+                .any(|v| v == "FIDO_2_1" || v == "FIDO_2_1_PRE")
+            {
+                self.selection()?;
+                return Ok(());
+            }
+            if caps.nmsg {
+                return self.dummy_make_credential();
+            }
+        }
+
+        let cid = ctaphid::ctaphid_init(self)?;
+        let dummy = [0u8; 64];
+        loop {
+            let (_, sw) =
+                ctaphid::send_apdu_with_status(self, &cid, 0, U2F_REGISTER, 0, 0, Some(&dummy))?;
+            match sw {
+                U2F_SW_NO_ERROR => return Ok(()),
+                U2F_SW_CONDITIONS_NOT_SATISFIED => {}
+                _ => return Err(anyhow!("U2F register error: 0x{:04X}", sw)),
+            }
+
+            if self.cancel_requested() == Some(true) {
+                return Err(CtapHidError::Cancelled.into());
+            }
+            if Instant::now() >= deadline {
+                return Err(CtapHidError::Timeout(self.timeout().unwrap_or_default()).into());
+            }
+            thread::sleep(U2F_POLL_INTERVAL);
+        }
+    }
+
+    fn dummy_make_credential(&self) -> Result<()> {
+        let cid = ctaphid::ctaphid_init(self)?;
+        let send_payload = create_dummy_make_credential_payload()?;
+        match ctaphid::ctaphid_cbor(self, &cid, &send_payload) {
+            Ok(_) => Ok(()),
+            Err(e) => match e.downcast_ref::<CtapHidError>() {
+                Some(CtapHidError::Status(status)) if is_touched_status(*status) => Ok(()),
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+/// Open all `devices` and return the one the user touched within `timeout`.
+/// The requests to the other devices are cancelled.
+///
+/// authenticatorSelection is used for CTAP 2.1 authenticators,
+/// a dummy authenticatorMakeCredential for CTAP 2.0 authenticators without U2F (NMSG),
+/// and a dummy U2F register for the others.
+///
+/// ex. `select_device(&get_fidokey_devices(), &Cfg::init(), Duration::from_secs(30))`
+pub fn select_device(devices: &[HidInfo], cfg: &LibCfg, timeout: Duration) -> Result<FidoKeyHid> {
+    if devices.is_empty() {
+        return Err(anyhow!("FIDO device not found."));
+    }
+
+    let deadline = Instant::now() + timeout;
+    let (tx, rx) = mpsc::channel();
+    let mut cancels = vec![];
+
+    for (index, dev) in devices.iter().enumerate() {
+        let cancel = Arc::new(AtomicBool::new(false));
+        cancels.push(cancel.clone());
+
+        let tx = tx.clone();
+        let param = dev.param.clone();
+        let cfg = LibCfg {
+            keep_alive_msg: String::new(),
+            timeouts: Timeouts::all(Some(timeout)),
+            ..cfg.clone()
+        };
+        thread::spawn(move || {
+            let result = FidoKeyHid::new(&[param], &cfg).and_then(|mut device| {
+                device.cancel = Some(cancel);
+                device.wait_for_touch(deadline)?;
+                Ok(device)
+            });
+            let _ = tx.send((index, result));
+        });
+    }
+    drop(tx);
+
+    let mut error: Option<Error> = None;
+    for (index, result) in rx {
+        match result {
+            Ok(mut device) => {
+                // cancel the others
+                for (i, cancel) in cancels.iter().enumerate() {
+                    if i != index {
+                        cancel.store(true, std::sync::atomic::Ordering::SeqCst);
+                    }
+                }
+
+                device.cancel = None;
+                device.keep_alive_msg = cfg.keep_alive_msg.to_string();
+                device.set_timeouts(cfg.timeouts);
+                return Ok(device);
+            }
+            Err(e) => {
+                // keep the most relevant error (timeout is the least)
+                let is_timeout = matches!(
+                    e.downcast_ref::<CtapHidError>(),
+                    Some(CtapHidError::Timeout(_))
+                );
+                if error.is_none() || !is_timeout {
+                    error = Some(e);
+                }
+            }
+        }
+    }
+
+    Err(error.unwrap_or_else(|| anyhow!("No device was selected.")))
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dummy_make_credential_payload() {
+        let payload = create_dummy_make_credential_payload().unwrap();
+        assert_eq!(payload[0], ctapdef::AUTHENTICATOR_MAKE_CREDENTIAL);
+
+        let map = crate::util::cbor_bytes_to_map(&payload[1..]).unwrap();
+        // zero length pinAuth with pinProtocol 1
+        assert_eq!(map.get(&Value::Integer(0x08)), Some(&Value::Bytes(vec![])));
+        assert_eq!(map.get(&Value::Integer(0x09)), Some(&Value::Integer(1)));
+        assert_eq!(
+            map.get(&Value::Integer(0x01)),
+            Some(&Value::Bytes(vec![0; 32]))
+        );
+        // no options, no extensions
+        assert!(!map.contains_key(&Value::Integer(0x06)));
+        assert!(!map.contains_key(&Value::Integer(0x07)));
+
+        assert!(is_touched_status(0x31));
+        assert!(is_touched_status(0x35));
+        // CTAP2_ERR_OPERATION_DENIED, CTAP2_ERR_KEEPALIVE_CANCEL
+        assert!(!is_touched_status(0x27));
+        assert!(!is_touched_status(0x2D));
+    }
 }
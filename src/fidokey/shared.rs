@@ -0,0 +1,59 @@
+use super::FidoKeyHid;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Clonable `Send + Sync` handle of a `FidoKeyHid`.
+///
+/// Requests from multiple threads are serialized, so a request never
+/// interleaves with another one in flight (e.g. `get_pin_retries` during `get_assertion`).
+///
+/// ```ignore
+/// let device = FidoKeyHidShared::new(FidoKeyHidFactory::create(&Cfg::init())?);
+/// let d = device.clone();
+/// std::thread::spawn(move || d.run(|d| d.get_pin_retries()));
+/// let assertions = device.run(|d| d.get_assertion(rpid, &challenge, &[cid], pin))?;
+/// ```
+#[derive(Clone)]
+pub struct FidoKeyHidShared {
+    inner: Arc<Mutex<FidoKeyHid>>,
+}
+
+impl FidoKeyHidShared {
+    pub fn new(device: FidoKeyHid) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(device)),
+        }
+    }
+
+    /// Run `f` with exclusive use of the device
+    pub fn run<T>(&self, f: impl FnOnce(&FidoKeyHid) -> T) -> T {
+        f(&self.device())
+    }
+
+    /// Exclusive use of the device until the guard is dropped.
+    /// Use this for a sequence of requests that must not be interleaved.
+    pub fn device(&self) -> MutexGuard<'_, FidoKeyHid> {
+        // a panic in another thread does not leave the device in an invalid state
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl From<FidoKeyHid> for FidoKeyHidShared {
+    fn from(device: FidoKeyHid) -> Self {
+        Self::new(device)
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+
+    #[test]
+    fn test_send_sync() {
+        assert_send_sync::<FidoKeyHidShared>();
+    }
+}
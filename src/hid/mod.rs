@@ -1,18 +1,87 @@
 use crate::str_buf::StrBuf;
+use anyhow::{anyhow, Result};
 use hidapi::HidApi;
 use std::path::PathBuf;
 
+mod report_descriptor;
+mod watcher;
+pub use report_descriptor::FidoReport;
+pub use watcher::{DeviceEvent, DeviceId, DeviceWatcher, DEFAULT_POLL_INTERVAL};
+
+pub(crate) const USAGE_PAGE_FIDO: u16 = 0xf1d0;
+
+// CTAPHID packet size
+const DEFAULT_REPORT_SIZE: u16 = 64;
+
 #[derive(Debug, Clone)]
 /// Storage for device related information
 pub struct DeviceInfo {
     pub path: PathBuf,
+    pub vid: u16,
+    pub pid: u16,
     pub usage_page: u16,
     pub usage: u16,
     pub report_size: u16,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    /// USB interface number (-1 if unknown)
+    pub interface_number: i32,
+    /// Device release number in BCD
+    pub release_number: u16,
+    /// Parameter to open this device
+    pub param: KeyID,
+    /// AAGUID from authenticatorGetInfo (None if getInfo was not requested or failed)
+    pub aaguid: Option<Vec<u8>>,
+    /// versions from authenticatorGetInfo
+    pub versions: Option<Vec<String>>,
+}
+
+impl DeviceInfo {
+    /// Supports CTAP 2.1 (requires getInfo)
+    pub fn is_ctap21(&self) -> bool {
+        self.versions
+            .as_ref()
+            .is_some_and(|versions| versions.iter().any(|v| v == "FIDO_2_1"))
+    }
+}
+
+/// Condition to choose a device
+#[derive(Debug, Clone)]
+pub enum DeviceFilter {
+    SerialNumber(String),
+    Path(String),
+    VidPid {
+        vid: u16,
+        pid: u16,
+    },
+    /// requires getInfo
+    Aaguid(Vec<u8>),
+    /// The first CTAP 2.1 authenticator (requires getInfo)
+    Ctap21,
+}
+
+impl DeviceFilter {
+    /// authenticatorGetInfo is needed to evaluate the filter
+    pub fn needs_get_info(&self) -> bool {
+        matches!(self, Self::Aaguid(_) | Self::Ctap21)
+    }
+
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        match self {
+            Self::SerialNumber(serial_number) => {
+                device.serial_number.as_ref() == Some(serial_number)
+            }
+            Self::Path(path) => device.path.to_string_lossy() == path.as_str(),
+            Self::VidPid { vid, pid } => device.vid == *vid && device.pid == *pid,
+            Self::Aaguid(aaguid) => device.aaguid.as_ref() == Some(aaguid),
+            Self::Ctap21 => device.is_ctap21(),
+        }
+    }
 }
 
 /// HID device vendor ID , product ID
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub enum KeyID {
     /// Specified when looking for any FIDO device of a certain kind
     VidPid { vid: u16, pid: u16 },
@@ -97,44 +166,177 @@ pub fn get_hid_devices(usage_page: Option<u16>) -> Vec<HidInfo> {
     let devices = api.device_list();
     for dev in devices {
         if usage_page
-            .map(|u| u == dev.usage_page())
+            .map(|u| has_usage_page(dev, u))
             .unwrap_or_default()
         {
-            let mut memo = StrBuf::new(0);
+            res.push(hid_info(dev));
+        }
+    }
+    res
+}
 
-            if let Some(n) = dev.product_string() {
-                memo.add("product=");
-                memo.add(n);
-            }
-            memo.add(" usage_page=");
-            memo.add(&dev.usage_page().to_string());
+pub fn get_device_infos(usage_page: u16) -> Vec<DeviceInfo> {
+    let api = HidApi::new().expect("Failed to create HidAPI instance");
+    api.device_list()
+        .filter(|dev| has_usage_page(dev, usage_page))
+        .map(device_info)
+        .collect()
+}
 
-            memo.add(" usage=");
-            memo.add(&dev.usage().to_string());
+/// FIDO collection in the report descriptor of the device (hidraw only)
+pub fn get_fido_report(path: &str) -> Option<FidoReport> {
+    report_descriptor::fido_report(path).flatten()
+}
 
-            if let Some(n) = dev.serial_number() {
-                memo.add(" serial_number=");
-                memo.add(n);
-            }
+// hidapi's usage_page() is the first usage page of the report descriptor on hidraw,
+// so a FIDO interface of a composite device may be missed.
+// The FIDO collection is looked up in the report descriptor when it is available.
+pub(crate) fn has_usage_page(dev: &hidapi::DeviceInfo, usage_page: u16) -> bool {
+    if usage_page == USAGE_PAGE_FIDO {
+        if let Some(report) = dev
+            .path()
+            .to_str()
+            .ok()
+            .and_then(report_descriptor::fido_report)
+        {
+            return report.is_some();
+        }
+    }
+    dev.usage_page() == usage_page
+}
 
-            memo.add(format!(" path={:?}", dev.path()).as_str());
-
-            let param = dev.path().to_str().map_or_else(
-                |_| KeyID::VidPid {
-                    vid: dev.vendor_id(),
-                    pid: dev.product_id(),
-                },
-                |s| KeyID::Path(s.to_string()),
-            );
-
-            res.push(HidInfo {
-                pid: dev.product_id(),
-                vid: dev.vendor_id(),
-                product_string: dev.product_string().unwrap_or_default().to_string(),
-                info: memo.build().to_string(),
-                param,
-            });
+/// Check that the FIDO reports of the device fit in CTAPHID packets.
+/// Only a successfully parsed report descriptor is checked;
+/// a device whose descriptor is unavailable or malformed is accepted.
+pub(crate) fn check_report_size(path: &str) -> Result<()> {
+    match get_fido_report(path) {
+        Some(report)
+            if report.input_report_size != DEFAULT_REPORT_SIZE
+                || report.output_report_size != DEFAULT_REPORT_SIZE =>
+        {
+            Err(anyhow!(
+                "Unsupported HID report size: input={} output={} (expected {})",
+                report.input_report_size,
+                report.output_report_size,
+                DEFAULT_REPORT_SIZE
+            ))
         }
+        _ => Ok(()),
+    }
+}
+
+fn device_info(dev: &hidapi::DeviceInfo) -> DeviceInfo {
+    let non_empty = |s: Option<&str>| s.filter(|s| !s.is_empty()).map(str::to_string);
+    let path = dev.path().to_string_lossy().to_string();
+
+    // prefer the FIDO collection of the report descriptor
+    let (usage_page, usage, report_size) = match get_fido_report(&path) {
+        Some(report) => (USAGE_PAGE_FIDO, 0x01, report.input_report_size),
+        None => (dev.usage_page(), dev.usage(), DEFAULT_REPORT_SIZE),
+    };
+
+    DeviceInfo {
+        path: PathBuf::from(path),
+        vid: dev.vendor_id(),
+        pid: dev.product_id(),
+        usage_page,
+        usage,
+        report_size,
+        manufacturer: non_empty(dev.manufacturer_string()),
+        product: non_empty(dev.product_string()),
+        serial_number: non_empty(dev.serial_number()),
+        interface_number: dev.interface_number(),
+        release_number: dev.release_number(),
+        param: hid_info(dev).param,
+        aaguid: None,
+        versions: None,
+    }
+}
+
+pub(crate) fn hid_info(dev: &hidapi::DeviceInfo) -> HidInfo {
+    let mut memo = StrBuf::new(0);
+
+    if let Some(n) = dev.product_string() {
+        memo.add("product=");
+        memo.add(n);
+    }
+    memo.add(" usage_page=");
+    memo.add(&dev.usage_page().to_string());
+
+    memo.add(" usage=");
+    memo.add(&dev.usage().to_string());
+
+    if let Some(n) = dev.serial_number() {
+        memo.add(" serial_number=");
+        memo.add(n);
+    }
+
+    memo.add(format!(" path={:?}", dev.path()).as_str());
+
+    let param = dev.path().to_str().map_or_else(
+        |_| KeyID::VidPid {
+            vid: dev.vendor_id(),
+            pid: dev.product_id(),
+        },
+        |s| KeyID::Path(s.to_string()),
+    );
+
+    HidInfo {
+        pid: dev.product_id(),
+        vid: dev.vendor_id(),
+        product_string: dev.product_string().unwrap_or_default().to_string(),
+        info: memo.build().to_string(),
+        param,
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_info() -> DeviceInfo {
+        DeviceInfo {
+            path: PathBuf::from("/dev/hidraw3"),
+            vid: 0x1050,
+            pid: 0x0407,
+            usage_page: USAGE_PAGE_FIDO,
+            usage: 1,
+            report_size: DEFAULT_REPORT_SIZE,
+            manufacturer: Some("Yubico".to_string()),
+            product: None,
+            serial_number: Some("12345678".to_string()),
+            interface_number: 1,
+            release_number: 0x0543,
+            param: KeyID::Path("/dev/hidraw3".to_string()),
+            aaguid: Some(vec![0xee; 16]),
+            versions: Some(vec!["FIDO_2_0".to_string(), "FIDO_2_1".to_string()]),
+        }
+    }
+
+    #[test]
+    fn test_device_filter() {
+        let dev = device_info();
+        assert!(DeviceFilter::SerialNumber("12345678".to_string()).matches(&dev));
+        assert!(!DeviceFilter::SerialNumber("1234".to_string()).matches(&dev));
+        assert!(DeviceFilter::Path("/dev/hidraw3".to_string()).matches(&dev));
+        assert!(DeviceFilter::VidPid {
+            vid: 0x1050,
+            pid: 0x0407
+        }
+        .matches(&dev));
+        assert!(DeviceFilter::Aaguid(vec![0xee; 16]).matches(&dev));
+        assert!(DeviceFilter::Ctap21.matches(&dev));
+
+        let dev = DeviceInfo {
+            aaguid: None,
+            versions: None,
+            ..dev
+        };
+        assert!(!DeviceFilter::Aaguid(vec![0xee; 16]).matches(&dev));
+        assert!(!DeviceFilter::Ctap21.matches(&dev));
+        assert!(DeviceFilter::Ctap21.needs_get_info());
     }
-    res
 }
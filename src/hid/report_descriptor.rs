@@ -0,0 +1,251 @@
+/*!
+HID report descriptor parser
+
+Finds the FIDO collection (usage page 0xF1D0, usage 0x01) and its report sizes.
+On Linux, the descriptor is read from `/sys/class/hidraw/<hidrawN>/device/report_descriptor`.
+
+https://www.usb.org/sites/default/files/hid1_11.pdf 6.2.2 Report Descriptor
+*/
+use super::USAGE_PAGE_FIDO;
+use std::convert::TryFrom;
+
+const USAGE_CTAPHID: u16 = 0x01;
+
+// item type
+const TYPE_MAIN: u8 = 0;
+const TYPE_GLOBAL: u8 = 1;
+const TYPE_LOCAL: u8 = 2;
+
+// main item tags
+const TAG_INPUT: u8 = 0x8;
+const TAG_OUTPUT: u8 = 0x9;
+const TAG_COLLECTION: u8 = 0xA;
+const TAG_END_COLLECTION: u8 = 0xC;
+
+// global item tags
+const TAG_USAGE_PAGE: u8 = 0x0;
+const TAG_REPORT_SIZE: u8 = 0x7;
+const TAG_REPORT_ID: u8 = 0x8;
+const TAG_REPORT_COUNT: u8 = 0x9;
+
+// local item tags
+const TAG_USAGE: u8 = 0x0;
+
+const LONG_ITEM: u8 = 0xFE;
+
+/// FIDO collection found in a report descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FidoReport {
+    /// Input report size in bytes (without Report ID)
+    pub input_report_size: u16,
+    /// Output report size in bytes (without Report ID)
+    pub output_report_size: u16,
+    /// The collection uses Report IDs
+    pub report_id: bool,
+}
+
+/// Find the FIDO collection in a report descriptor.
+/// None if it is not found or the descriptor is malformed.
+pub fn parse_fido_report(descriptor: &[u8]) -> Option<FidoReport> {
+    let mut usage_page: u16 = 0;
+    let mut usage: Option<(u16, u16)> = None;
+    let mut report_size: u32 = 0;
+    let mut report_count: u32 = 0;
+    let mut report_id = false;
+
+    // depth of the collections, and the depth at which the FIDO collection starts
+    let mut depth: u32 = 0;
+    let mut fido_depth: Option<u32> = None;
+    let mut input_bits: u32 = 0;
+    let mut output_bits: u32 = 0;
+
+    let mut i = 0;
+    while i < descriptor.len() {
+        let prefix = descriptor[i];
+        if prefix == LONG_ITEM {
+            // bDataSize, bLongItemTag, data
+            let size = usize::from(*descriptor.get(i + 1)?);
+            i = i.checked_add(3 + size)?;
+            continue;
+        }
+
+        let size = match prefix & 0x03 {
+            3 => 4,
+            n => usize::from(n),
+        };
+        let data = descriptor.get((i + 1)..(i + 1 + size))?;
+        let value = data
+            .iter()
+            .rev()
+            .fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+        let item_type = (prefix >> 2) & 0x03;
+        let tag = prefix >> 4;
+        i += 1 + size;
+
+        match (item_type, tag) {
+            (TYPE_GLOBAL, TAG_USAGE_PAGE) => usage_page = value as u16,
+            (TYPE_GLOBAL, TAG_REPORT_SIZE) => report_size = value,
+            (TYPE_GLOBAL, TAG_REPORT_COUNT) => report_count = value,
+            (TYPE_GLOBAL, TAG_REPORT_ID) if fido_depth.is_some() => report_id = true,
+            // the first usage of the item; extended usage (4 bytes) contains the usage page
+            (TYPE_LOCAL, TAG_USAGE) if usage.is_none() => {
+                usage = Some(if size == 4 {
+                    ((value >> 16) as u16, value as u16)
+                } else {
+                    (usage_page, value as u16)
+                });
+            }
+            (TYPE_MAIN, TAG_COLLECTION) => {
+                depth = depth.checked_add(1)?;
+                if fido_depth.is_none() && usage == Some((USAGE_PAGE_FIDO, USAGE_CTAPHID)) {
+                    fido_depth = Some(depth);
+                }
+            }
+            (TYPE_MAIN, TAG_END_COLLECTION) => {
+                if fido_depth == Some(depth) {
+                    return Some(FidoReport {
+                        input_report_size: u16::try_from(input_bits / 8).ok()?,
+                        output_report_size: u16::try_from(output_bits / 8).ok()?,
+                        report_id,
+                    });
+                }
+                // End Collection without Collection
+                depth = depth.checked_sub(1)?;
+            }
+            (TYPE_MAIN, TAG_INPUT) if fido_depth.is_some() => {
+                input_bits = input_bits.checked_add(report_size.checked_mul(report_count)?)?;
+            }
+            (TYPE_MAIN, TAG_OUTPUT) if fido_depth.is_some() => {
+                output_bits = output_bits.checked_add(report_size.checked_mul(report_count)?)?;
+            }
+            _ => {}
+        }
+
+        // local items are cleared after each main item
+        if item_type == TYPE_MAIN {
+            usage = None;
+        }
+    }
+
+    None
+}
+
+/// Read the report descriptor of a hidraw device (path = /dev/hidrawN)
+#[cfg(target_os = "linux")]
+pub fn read_report_descriptor(path: &str) -> Option<Vec<u8>> {
+    let name = std::path::Path::new(path).file_name()?.to_str()?;
+    if !name.starts_with("hidraw") {
+        return None;
+    }
+    std::fs::read(format!("/sys/class/hidraw/{name}/device/report_descriptor")).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_report_descriptor(_path: &str) -> Option<Vec<u8>> {
+    None
+}
+
+/// FIDO collection of the device.
+/// None: the descriptor is not available (Some(None): it is not a FIDO device)
+pub fn fido_report(path: &str) -> Option<Option<FidoReport>> {
+    read_report_descriptor(path).map(|descriptor| parse_fido_report(&descriptor))
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // FIDO U2F/CTAPHID report descriptor
+    const FIDO: [u8; 34] = [
+        0x06, 0xD0, 0xF1, // Usage Page (FIDO Alliance)
+        0x09, 0x01, // Usage (U2F Authenticator Device)
+        0xA1, 0x01, // Collection (Application)
+        0x09, 0x20, //   Usage (Input Report Data)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x75, 0x08, //   Report Size (8)
+        0x95, 0x40, //   Report Count (64)
+        0x81, 0x02, //   Input (Data,Var,Abs)
+        0x09, 0x21, //   Usage (Output Report Data)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x75, 0x08, //   Report Size (8)
+        0x95, 0x40, //   Report Count (64)
+        0x91, 0x02, //   Output (Data,Var,Abs)
+        0xC0, // End Collection
+    ];
+
+    // Keyboard collection
+    const KEYBOARD: [u8; 16] = [
+        0x05, 0x01, // Usage Page (Generic Desktop)
+        0x09, 0x06, // Usage (Keyboard)
+        0xA1, 0x01, // Collection (Application)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x08, //   Report Count (8)
+        0x81, 0x02, //   Input (Data,Var,Abs)
+        0x75, 0x08, //   Report Size (8)
+        0xC0, // End Collection
+        0x00,
+    ];
+
+    #[test]
+    fn test_parse_fido_report() {
+        let report = parse_fido_report(&FIDO).unwrap();
+        assert_eq!(report.input_report_size, 64);
+        assert_eq!(report.output_report_size, 64);
+        assert!(!report.report_id);
+
+        assert_eq!(parse_fido_report(&KEYBOARD), None);
+
+        // composite device: FIDO is not the first collection
+        let composite = [&KEYBOARD[..15], &FIDO[..]].concat();
+        assert_eq!(parse_fido_report(&composite), Some(report));
+
+        // truncated
+        assert_eq!(parse_fido_report(&FIDO[..20]), None);
+    }
+
+    #[test]
+    fn test_malformed() {
+        // End Collection without Collection
+        assert_eq!(parse_fido_report(&[0xC0]), None);
+        let unbalanced = [&FIDO[..33], &[0xC0, 0xC0][..]].concat();
+        assert!(parse_fido_report(&unbalanced).is_some());
+        assert_eq!(parse_fido_report(&[&[0xC0][..], &FIDO[..]].concat()), None);
+
+        // Report Size * Report Count overflows
+        let mut overflow = FIDO[..7].to_vec();
+        overflow.extend_from_slice(&[0x77, 0xFF, 0xFF, 0xFF, 0xFF]); // Report Size
+        overflow.extend_from_slice(&[0x97, 0xFF, 0xFF, 0xFF, 0xFF]); // Report Count
+        overflow.extend_from_slice(&[0x81, 0x02, 0xC0]);
+        assert_eq!(parse_fido_report(&overflow), None);
+
+        // the sum of the reports overflows
+        let mut sum = FIDO[..7].to_vec();
+        for _ in 0..3 {
+            sum.extend_from_slice(&[0x77, 0x00, 0x00, 0x00, 0x80, 0x95, 0x01, 0x81, 0x02]);
+        }
+        sum.push(0xC0);
+        assert_eq!(parse_fido_report(&sum), None);
+
+        // report size does not fit in u16
+        let mut large = FIDO[..7].to_vec();
+        large.extend_from_slice(&[0x75, 0x08, 0x97, 0x00, 0x00, 0x01, 0x00, 0x81, 0x02, 0xC0]);
+        assert_eq!(parse_fido_report(&large), None);
+
+        // long item beyond the end
+        assert_eq!(parse_fido_report(&[0xFE, 0xFF]), None);
+        let long_item = [&[0xFE, 0x02, 0x00, 0x00, 0x00][..], &FIDO[..]].concat();
+        assert!(parse_fido_report(&long_item).is_some());
+    }
+
+    #[test]
+    fn test_extended_usage() {
+        let mut descriptor = vec![0x0B, 0x01, 0x00, 0xD0, 0xF1]; // Usage (0xF1D0:0x0001)
+        descriptor.extend_from_slice(&FIDO[5..]);
+        assert!(parse_fido_report(&descriptor).is_some());
+    }
+}
@@ -0,0 +1,327 @@
+/*!
+FIDO device hotplug monitoring
+
+On Linux with the `udev` feature, hidraw uevents from udev trigger a rescan of the devices.
+Otherwise (or if udev is not available), the devices are rescanned periodically.
+
+```ignore
+let watcher = DeviceWatcher::new()?;
+while let Some(event) = watcher.recv() {
+    match event {
+        DeviceEvent::Arrived(id, info) => println!("arrived {id} {}", info.info),
+        DeviceEvent::Removed(id, _) => println!("removed {id}"),
+    }
+}
+```
+*/
+use super::{has_usage_page, hid_info, HidInfo, USAGE_PAGE_FIDO};
+use anyhow::{anyhow, Result};
+use hidapi::HidApi;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Default interval of the polling fallback
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Interval of checking udev events and the stop request
+const WAKE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Identity of a connected device: vid, pid and serial number.
+/// The HID device path is used only for devices without a serial number,
+/// whose identity changes when they are reconnected to another port.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceId {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+    /// HID device path (None if the device has a serial number)
+    pub path: Option<String>,
+}
+
+impl DeviceId {
+    fn new(vid: u16, pid: u16, serial_number: Option<&str>, path: &str) -> Self {
+        let serial_number = serial_number.filter(|s| !s.is_empty()).map(str::to_string);
+        let path = match serial_number {
+            Some(_) => None,
+            None => Some(path.to_string()),
+        };
+        Self {
+            vid,
+            pid,
+            serial_number,
+            path,
+        }
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(serial_number) = &self.serial_number {
+            write!(f, ":{serial_number}")?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "@{path}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub enum DeviceEvent {
+    /// A FIDO device was connected (or was connected when the watcher started)
+    Arrived(DeviceId, HidInfo),
+    /// A FIDO device was disconnected
+    Removed(DeviceId, HidInfo),
+}
+
+/// Reports arrival and removal of FIDO usage page devices.
+/// Monitoring stops when the watcher is dropped.
+pub struct DeviceWatcher {
+    events: mpsc::Receiver<DeviceEvent>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DeviceWatcher {
+    /// Watch with udev on Linux (`udev` feature), or by polling every `DEFAULT_POLL_INTERVAL`
+    pub fn new() -> Result<Self> {
+        Self::start(true, DEFAULT_POLL_INTERVAL)
+    }
+
+    /// Watch by polling every `interval`
+    pub fn with_poll_interval(interval: Duration) -> Result<Self> {
+        Self::start(false, interval)
+    }
+
+    fn start(use_udev: bool, interval: Duration) -> Result<Self> {
+        let api = HidApi::new().map_err(|e| anyhow!("Failed to create HidApi instance: {e}"))?;
+        let (tx, events) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let stop_thread = stop.clone();
+        let thread = thread::Builder::new()
+            .name("ctap-hid-fido2-watcher".to_string())
+            .spawn(move || watch(api, use_udev, interval, &tx, &stop_thread))?;
+
+        Ok(Self {
+            events,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Wait for the next event
+    pub fn recv(&self) -> Option<DeviceEvent> {
+        self.events.recv().ok()
+    }
+
+    /// Wait for the next event up to `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<DeviceEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// The next event if any
+    pub fn try_recv(&self) -> Option<DeviceEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn watch(
+    mut api: HidApi,
+    use_udev: bool,
+    interval: Duration,
+    tx: &mpsc::Sender<DeviceEvent>,
+    stop: &AtomicBool,
+) {
+    let mut trigger = Trigger::new(use_udev, interval);
+    let mut known: HashMap<DeviceId, HidInfo> = HashMap::new();
+
+    while !stop.load(Ordering::SeqCst) {
+        if trigger.rescan() {
+            if api.refresh_devices().is_ok() {
+                let current = scan(&api);
+
+                for (id, info) in diff(&known, &current) {
+                    let _ = tx.send(DeviceEvent::Removed(id, info));
+                }
+                for (id, info) in diff(&current, &known) {
+                    let _ = tx.send(DeviceEvent::Arrived(id, info));
+                }
+                known = current;
+            } else {
+                trigger.retry();
+            }
+        }
+        thread::sleep(WAKE_INTERVAL);
+    }
+}
+
+fn scan(api: &HidApi) -> HashMap<DeviceId, HidInfo> {
+    api.device_list()
+        .filter(|dev| has_usage_page(dev, USAGE_PAGE_FIDO))
+        .map(|dev| {
+            let id = DeviceId::new(
+                dev.vendor_id(),
+                dev.product_id(),
+                dev.serial_number(),
+                &dev.path().to_string_lossy(),
+            );
+            (id, hid_info(dev))
+        })
+        .collect()
+}
+
+// entries of `a` that are not in `b`
+fn diff(
+    a: &HashMap<DeviceId, HidInfo>,
+    b: &HashMap<DeviceId, HidInfo>,
+) -> Vec<(DeviceId, HidInfo)> {
+    a.iter()
+        .filter(|(id, _)| !b.contains_key(id))
+        .map(|(id, info)| (id.clone(), info.clone()))
+        .collect()
+}
+
+// Decides when to rescan the devices
+struct Trigger {
+    #[cfg(all(target_os = "linux", feature = "udev"))]
+    monitor: Option<udev::MonitorSocket>,
+    interval: Duration,
+    last: Option<Instant>,
+    // the last rescan failed, rescan on the interval even with udev
+    retry: bool,
+}
+
+impl Trigger {
+    #[cfg(all(target_os = "linux", feature = "udev"))]
+    fn new(use_udev: bool, interval: Duration) -> Self {
+        let monitor = if use_udev {
+            udev::MonitorBuilder::new()
+                .and_then(|builder| builder.match_subsystem("hidraw"))
+                .and_then(|builder| builder.listen())
+                .ok()
+        } else {
+            None
+        };
+        Self {
+            monitor,
+            interval,
+            last: None,
+            retry: false,
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "udev")))]
+    fn new(_use_udev: bool, interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+            retry: false,
+        }
+    }
+
+    fn rescan(&mut self) -> bool {
+        let due = match self.last {
+            // initial scan
+            None => true,
+            Some(last) => {
+                let event = self.udev_event();
+                let poll = event.is_none() || self.retry;
+                event == Some(true) || (poll && last.elapsed() >= self.interval)
+            }
+        };
+        if due {
+            self.last = Some(Instant::now());
+            self.retry = false;
+        }
+        due
+    }
+
+    // The rescan failed: rescan again after the interval
+    fn retry(&mut self) {
+        self.retry = true;
+    }
+
+    // None: udev is not used
+    #[cfg(all(target_os = "linux", feature = "udev"))]
+    fn udev_event(&mut self) -> Option<bool> {
+        self.monitor
+            .as_ref()
+            .map(|monitor| monitor.iter().count() > 0)
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "udev")))]
+    fn udev_event(&mut self) -> Option<bool> {
+        None
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyID;
+
+    fn entry(path: &str) -> (DeviceId, HidInfo) {
+        let id = DeviceId::new(0x1050, 0x0407, None, path);
+        let info = HidInfo {
+            pid: 0x0407,
+            vid: 0x1050,
+            product_string: String::new(),
+            info: String::new(),
+            param: KeyID::Path(path.to_string()),
+        };
+        (id, info)
+    }
+
+    #[test]
+    fn test_diff() {
+        let before: HashMap<_, _> = vec![entry("/dev/hidraw0"), entry("/dev/hidraw1")]
+            .into_iter()
+            .collect();
+        let after: HashMap<_, _> = vec![entry("/dev/hidraw1"), entry("/dev/hidraw2")]
+            .into_iter()
+            .collect();
+
+        let removed = diff(&before, &after);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].0.path.as_deref(), Some("/dev/hidraw0"));
+
+        let arrived = diff(&after, &before);
+        assert_eq!(arrived.len(), 1);
+        assert_eq!(arrived[0].0.path.as_deref(), Some("/dev/hidraw2"));
+        assert_eq!(arrived[0].0.to_string(), "1050:0407@/dev/hidraw2");
+    }
+
+    #[test]
+    fn test_device_id() {
+        // the serial number identifies the device on any port
+        let a = DeviceId::new(0x1050, 0x0407, Some("12345"), "/dev/hidraw0");
+        let b = DeviceId::new(0x1050, 0x0407, Some("12345"), "/dev/hidraw3");
+        assert_eq!(a, b);
+        assert_eq!(a.to_string(), "1050:0407:12345");
+
+        // the path is the fallback without a serial number
+        let a = DeviceId::new(0x1050, 0x0407, Some(""), "/dev/hidraw0");
+        let b = DeviceId::new(0x1050, 0x0407, None, "/dev/hidraw3");
+        assert_ne!(a, b);
+        assert_eq!(a.path.as_deref(), Some("/dev/hidraw0"));
+    }
+}
@@ -1,12 +1,16 @@
 use crate::ctaphid;
-use crate::encrypt::enc_aes256_cbc;
-use crate::encrypt::enc_hmac_sha_256;
 use crate::encrypt::shared_secret::SharedSecret;
 use crate::fidokey::pin::{
-    create_payload, parse_cbor_client_pin_get_keyagreement, SubCommand as PinCmd,
+    create_payload_get_keyagreement, parse_cbor_client_pin_get_keyagreement, PinUvAuthProtocol,
 };
 use crate::FidoKeyHid;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use ring::digest;
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+/// (salt1, salt2) or (output1, output2)
+pub type HmacSecretPair = ([u8; 32], Option<[u8; 32]>);
 
 #[derive(Debug, Default, Clone)]
 pub struct HmacExt {
@@ -20,21 +24,16 @@ impl HmacExt {
         &mut self,
         device: &FidoKeyHid,
         cid: &[u8],
+        pin_uv_auth_protocol: PinUvAuthProtocol,
         salt1: &[u8; 32],
-        _salt2: Option<&[u8; 32]>,
+        salt2: Option<&[u8; 32]>,
     ) -> Result<()> {
-        //println!("----------");
-        //println!("{}", StrBuf::bufh("salt1", salt1));
-
-        let send_payload = create_payload(PinCmd::GetKeyAgreement)?;
+        let send_payload = create_payload_get_keyagreement(pin_uv_auth_protocol);
         let response_cbor = ctaphid::ctaphid_cbor(device, cid, &send_payload)?;
 
         let key_agreement = parse_cbor_client_pin_get_keyagreement(&response_cbor)?;
 
-        //println!("key_agreement");
-        //println!("{}", self.key_agreement);
-
-        self.shared_secret = SharedSecret::new(&key_agreement)?;
+        self.shared_secret = SharedSecret::new_with_protocol(&key_agreement, pin_uv_auth_protocol)?;
 
         // saltEnc
         //  Encryption of the one or two salts (called salt1 (32 bytes)
@@ -44,14 +43,126 @@ impl HmacExt {
         //  encrypt(key, demPlaintext) → ciphertext
         //      Encrypts a plaintext to produce a ciphertext, which may be longer than the plaintext.
         //      The plaintext is restricted to being a multiple of the AES block size (16 bytes) in length.
-        self.salt_enc = enc_aes256_cbc::encrypt_message(&self.shared_secret.secret, salt1);
-        //println!("{}", StrBuf::bufh("salt_enc", &self.salt_enc));
+        let mut salts = salt1.to_vec();
+        if let Some(salt2) = salt2 {
+            salts.extend_from_slice(salt2);
+        }
+        self.salt_enc = self.shared_secret.encrypt_message(&salts)?;
 
         // saltAuth
-        let sig = enc_hmac_sha_256::authenticate(&self.shared_secret.secret, &self.salt_enc);
-        self.salt_auth = sig[0..16].to_vec();
-        //println!("{}", StrBuf::bufh("salt_auth", &self.salt_auth));
+        //  authenticate(shared secret, saltEnc)
+        self.salt_auth = self.shared_secret.authenticate(&self.salt_enc);
 
         Ok(())
     }
+
+    /// hmac-secret extension input map
+    /// - 0x01: keyAgreement
+    /// - 0x02: saltEnc
+    /// - 0x03: saltAuth
+    /// - 0x04: pinUvAuthProtocol (omitted for protocol 1)
+    pub fn to_value(&self) -> Result<Value> {
+        let mut param = BTreeMap::new();
+        param.insert(
+            Value::Integer(0x01),
+            self.shared_secret.public_key.to_value()?,
+        );
+        param.insert(Value::Integer(0x02), Value::Bytes(self.salt_enc.clone()));
+        param.insert(Value::Integer(0x03), Value::Bytes(self.salt_auth.clone()));
+        if self.shared_secret.pin_uv_auth_protocol != PinUvAuthProtocol::One {
+            param.insert(
+                Value::Integer(0x04),
+                Value::Integer(self.shared_secret.pin_uv_auth_protocol as i128),
+            );
+        }
+        Ok(Value::Map(param))
+    }
+
+    /// Decrypt the hmac-secret extension output into output1 and (if two salts were sent) output2
+    pub fn decrypt_output(shared_secret: &SharedSecret, enc: &[u8]) -> Result<HmacSecretPair> {
+        let dec = shared_secret.decrypt_message(enc)?;
+
+        let mut output1 = [0u8; 32];
+        match dec.len() {
+            32 => {
+                output1.copy_from_slice(&dec);
+                Ok((output1, None))
+            }
+            64 => {
+                let mut output2 = [0u8; 32];
+                output1.copy_from_slice(&dec[0..32]);
+                output2.copy_from_slice(&dec[32..64]);
+                Ok((output1, Some(output2)))
+            }
+            _ => Err(anyhow!("Invalid hmac-secret output length")),
+        }
+    }
+}
+
+/// WebAuthn PRF extension salt: SHA-256("WebAuthn PRF" || 0x00 || input)
+pub fn prf_salt(input: &[u8]) -> [u8; 32] {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(b"WebAuthn PRF");
+    ctx.update(&[0x00]);
+    ctx.update(input);
+
+    let mut salt = [0u8; 32];
+    salt.copy_from_slice(ctx.finish().as_ref());
+    salt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt::enc_hmac_sha_256;
+
+    fn shared_secret(pin_uv_auth_protocol: PinUvAuthProtocol) -> SharedSecret {
+        SharedSecret {
+            secret: [0x11; 32],
+            hmac_key: [0x22; 32],
+            pin_uv_auth_protocol,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hmac_secret_protocol1() {
+        let ss = shared_secret(PinUvAuthProtocol::One);
+        let salts = [[0xaa; 32], [0xbb; 32]].concat();
+
+        let enc = ss.encrypt_message(&salts).unwrap();
+        assert_eq!(enc.len(), 64);
+        assert_eq!(ss.authenticate(&enc).len(), 16);
+
+        let (o1, o2) = HmacExt::decrypt_output(&ss, &enc).unwrap();
+        assert_eq!(o1, [0xaa; 32]);
+        assert_eq!(o2, Some([0xbb; 32]));
+    }
+
+    #[test]
+    fn test_hmac_secret_protocol2() {
+        let ss = shared_secret(PinUvAuthProtocol::Two);
+
+        // IV || ciphertext
+        let enc = ss.encrypt_message(&[0xaa; 32]).unwrap();
+        assert_eq!(enc.len(), 48);
+
+        // full 32 byte HMAC with the HMAC key
+        let auth = ss.authenticate(&enc);
+        assert_eq!(auth, enc_hmac_sha_256::authenticate(&[0x22; 32], &enc));
+
+        let (o1, o2) = HmacExt::decrypt_output(&ss, &enc).unwrap();
+        assert_eq!(o1, [0xaa; 32]);
+        assert_eq!(o2, None);
+
+        assert!(HmacExt::decrypt_output(&ss, &enc[0..16]).is_err());
+    }
+
+    #[test]
+    fn test_prf_salt() {
+        assert_eq!(
+            hex::encode(prf_salt(b"passkey")),
+            "a297896900d5327446ce5f2da073f7d8db224be271ffc6146a0c032630818be1"
+        );
+    }
 }
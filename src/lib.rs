@@ -7,41 +7,61 @@
 pub mod auth_data;
 mod ctapdef;
 mod ctaphid;
+mod ctaphid_frame;
 mod encrypt {
     pub mod cose;
     pub mod enc_aes256_cbc;
+    pub mod enc_aes256_gcm;
     pub mod enc_hmac_sha_256;
     pub mod p256;
     pub mod shared_secret;
 }
+pub mod error;
 mod hmac_ext;
 pub mod pcsc;
 mod pintoken;
+pub mod quirks;
 pub mod public_key;
 pub mod public_key_credential_descriptor;
 pub mod public_key_credential_rp_entity;
 pub mod public_key_credential_user_entity;
+#[cfg(feature = "serde")]
+mod serde_ext;
 pub mod str_buf;
 pub mod util;
 pub mod verifier;
 
 use anyhow::{anyhow, Result};
+use std::time::Duration;
 
 pub mod fidokey;
 use fidokey::FidoKey;
-pub use fidokey::FidoKeyHid;
+pub use ctaphid::HidDeviceCapabilities;
+pub use fidokey::{select_device, FidoKeyHid, FidoKeyHidShared};
+#[cfg(feature = "async")]
+pub use fidokey::FidoKeyHidAsync;
 
 mod hid;
-pub use hid::{HidInfo, KeyID};
+pub use hid::{
+    get_fido_report, DeviceEvent, DeviceFilter, DeviceId, DeviceInfo, DeviceWatcher, FidoReport,
+    HidInfo, KeyID, DEFAULT_POLL_INTERVAL,
+};
 
 pub type Cfg = LibCfg;
 
 #[derive(Clone)]
 pub struct LibCfg {
     pub enable_log: bool,
-    pub use_pre_bio_enrollment: bool,
-    pub use_pre_credential_management: bool,
+    /// Use the CTAP 2.1-PRE bioEnrollment command (None: decided from authenticatorGetInfo)
+    pub use_pre_bio_enrollment: Option<bool>,
+    /// Use the CTAP 2.1-PRE credentialManagement command (None: decided from authenticatorGetInfo)
+    pub use_pre_credential_management: Option<bool>,
     pub keep_alive_msg: String,
+    /// Timeouts of waiting for a response from the authenticator.
+    /// They can be changed per call with `FidoKeyHid::with_timeout`.
+    pub timeouts: Timeouts,
+    /// Per-device workarounds (the built-in table can be extended)
+    pub quirks: quirks::QuirkTable,
 }
 
 impl LibCfg {
@@ -49,9 +69,58 @@ impl LibCfg {
     pub fn init() -> Self {
         Self {
             enable_log: false,
-            use_pre_bio_enrollment: true,
-            use_pre_credential_management: true,
+            use_pre_bio_enrollment: None,
+            use_pre_credential_management: None,
             keep_alive_msg: "- Touch the sensor on the authenticator".to_string(),
+            timeouts: Timeouts::default(),
+            quirks: quirks::QuirkTable::builtin(),
+        }
+    }
+}
+
+/// Timeouts of waiting for a response from the authenticator (None: wait forever).
+///
+/// The deadline is extended by each keepalive (PROCESSING / UPNEEDED),
+/// so a timeout is the time the authenticator may stay silent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Requests without a specific timeout
+    pub default: Option<Duration>,
+    /// authenticatorGetInfo
+    pub get_info: Option<Duration>,
+    /// authenticatorBioEnrollment (capturing a sample takes a while)
+    pub bio_enrollment: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            default: Some(Duration::from_secs(30)),
+            get_info: Some(Duration::from_secs(5)),
+            bio_enrollment: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl Timeouts {
+    /// The same timeout for all requests
+    #[must_use]
+    pub const fn all(timeout: Option<Duration>) -> Self {
+        Self {
+            default: timeout,
+            get_info: timeout,
+            bio_enrollment: timeout,
+        }
+    }
+
+    /// Timeout of the CTAP command (ex. `AUTHENTICATOR_GET_INFO`)
+    pub(crate) fn for_command(&self, command: u8) -> Option<Duration> {
+        match command {
+            ctapdef::AUTHENTICATOR_GET_INFO => self.get_info,
+            ctapdef::AUTHENTICATOR_BIO_ENROLLMENT | ctapdef::AUTHENTICATOR_BIO_ENROLLMENT_P => {
+                self.bio_enrollment
+            }
+            _ => self.default,
         }
     }
 }
@@ -65,8 +134,47 @@ pub fn get_hid_devices() -> Vec<HidInfo> {
 /// Get HID FIDO devices
 #[must_use]
 pub fn get_fidokey_devices() -> Vec<HidInfo> {
-    
-    hid::get_hid_devices(Some(0xf1d0))
+    hid::get_hid_devices(Some(hid::USAGE_PAGE_FIDO))
+}
+
+/// Get HID FIDO devices with manufacturer, serial number, interface number, etc.
+#[must_use]
+pub fn get_fidokey_device_infos() -> Vec<DeviceInfo> {
+    hid::get_device_infos(hid::USAGE_PAGE_FIDO)
+}
+
+/// `get_fidokey_device_infos` with the AAGUID and versions from authenticatorGetInfo.
+/// They are None for a device that does not respond to getInfo (e.g. CTAP1 only).
+#[must_use]
+pub fn get_fidokey_device_infos_with_get_info(cfg: &LibCfg) -> Vec<DeviceInfo> {
+    open_fidokey_devices_with_get_info(cfg)
+        .into_iter()
+        .map(|(dev, _)| dev)
+        .collect()
+}
+
+// Open the FIDO devices with authenticatorGetInfo once per device
+// (`FidoKeyHid::new` and the AAGUID quirks share the cached one).
+// The device is None if it could not be opened.
+fn open_fidokey_devices_with_get_info(cfg: &LibCfg) -> Vec<(DeviceInfo, Option<FidoKeyHid>)> {
+    get_fidokey_device_infos()
+        .into_iter()
+        .map(|dev| {
+            let device = FidoKeyHid::new(std::slice::from_ref(&dev.param), cfg).ok();
+            let info = device.as_ref().map(|device| device.cached_info());
+            match info {
+                Some(Ok(info)) => (
+                    DeviceInfo {
+                        aaguid: Some(info.aaguid),
+                        versions: Some(info.versions),
+                        ..dev
+                    },
+                    device,
+                ),
+                _ => (dev, device),
+            }
+        })
+        .collect()
 }
 
 /// Simple factory to create `FidoKeyHid`
@@ -91,6 +199,30 @@ impl FidoKeyHidFactory {
     pub fn create_by_params(params: &[KeyID], cfg: &LibCfg) -> Result<FidoKeyHid> {
         FidoKeyHid::new(params, cfg)
     }
+
+    /// Create `FidoKeyHid` of the first device that matches `filter`
+    /// ex. `FidoKeyHidFactory::create_by_filter(&DeviceFilter::Ctap21, &Cfg::init())`
+    pub fn create_by_filter(filter: &DeviceFilter, cfg: &LibCfg) -> Result<FidoKeyHid> {
+        // the devices opened for getInfo are reused
+        let devs = if filter.needs_get_info() {
+            open_fidokey_devices_with_get_info(cfg)
+        } else {
+            get_fidokey_device_infos()
+                .into_iter()
+                .map(|dev| (dev, None))
+                .collect()
+        };
+
+        let (dev, device) = devs
+            .into_iter()
+            .find(|(dev, _)| filter.matches(dev))
+            .ok_or_else(|| anyhow!("FIDO device not found. filter = {:?}", filter))?;
+
+        match device {
+            Some(device) => Ok(device),
+            None => FidoKeyHid::new(&[dev.param], cfg),
+        }
+    }
 }
 
 //
@@ -159,4 +291,24 @@ mod tests {
         print!("- dec_data = {dec_data}");
         assert_eq!(dec_data, message);
     }
+
+    #[test]
+    fn test_timeouts() {
+        let timeouts = Timeouts::default();
+        assert_eq!(
+            timeouts.for_command(ctapdef::AUTHENTICATOR_GET_INFO),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            timeouts.for_command(ctapdef::AUTHENTICATOR_BIO_ENROLLMENT_P),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            timeouts.for_command(ctapdef::AUTHENTICATOR_MAKE_CREDENTIAL),
+            Some(Duration::from_secs(30))
+        );
+
+        let timeouts = Timeouts::all(None);
+        assert_eq!(timeouts.for_command(ctapdef::AUTHENTICATOR_GET_INFO), None);
+    }
 }
@@ -1,5 +1,6 @@
 use std::{ffi::{CStr, CString}, time::Duration};
 
+use crate::quirks::{QuirkTable, SelectApdu};
 use anyhow::{anyhow, bail, Result};
 use pcsc::{
     Card, Context, Disposition, Error, Protocols, Scope, ShareMode, Transaction,
@@ -74,12 +75,29 @@ pub fn send_command(tx: &Transaction, cmd_apdu: &[u8]) -> Result<Vec<u8>> {
     Ok(response_apdu)
 }
 
-//                                     CLA   INS   P1    P2    Le    RID                           AX    AC           AID: RID+AX+AC
-// pub const SELECT_FIDO2_APDU: &[u8] = &[0x00, 0xa4, 0x04, 0x0c, 0x08, 0xa0, 0x00, 0x00, 0x06, 0x47, 0x2f, 0x00, 0x01]; // Standard per https://fidoalliance.org/specs/fido-v2.0-rd-20170927/fido-client-to-authenticator-protocol-v2.0-rd-20170927.html#iso7816-iso14443-and-near-field-communication-nfc
-pub const SELECT_FIDO2_APDU: &[u8] = &[0x00, 0xa4, 0x04, 0x00, 0x08, 0xa0, 0x00, 0x00, 0x06, 0x47, 0x2f, 0x00, 0x01]; // Yubikeys reject the above but accept this one. Other keys I've tried work as well with this so far.
-// pub const SELECT_FIDO2_APDU: &[u8] = &[0x00, 0xa4, 0x04, 0x00, 0x06, 0xa0, 0x00, 0x00, 0x06, 0x47, 0x2f]; // Bypasses windows admin requirement
+// Yubikeys reject the standard SELECT but accept this one. Other keys I've tried work as well with this so far.
+// The variants are in `SelectApdu`, the card specific one is looked up by `select_apdu`.
+pub const SELECT_FIDO2_APDU: &[u8] = SelectApdu::P2Zero.apdu();
+
+/// SELECT command of the FIDO applet for the card (see `Quirks::select_apdu`)
+pub fn select_apdu(quirks: &QuirkTable, atr: &[u8]) -> &'static [u8] {
+    quirks.lookup_atr(atr).select_apdu.unwrap_or_default().apdu()
+}
+
+/// Select the FIDO applet with the SELECT command for the card
+pub fn select(tx: &Transaction, quirks: &QuirkTable) -> Result<Vec<u8>> {
+    let status = tx
+        .status2_owned()
+        .map_err(|err| anyhow!("Failed to get card status: {err}"))?;
+    send_command(tx, select_apdu(quirks, status.atr()))
+}
 
 pub fn get_fido_pcsc_devices() -> Result<Vec<String>> {
+    get_fido_pcsc_devices_with_quirks(&QuirkTable::builtin())
+}
+
+/// `get_fido_pcsc_devices` with the SELECT command looked up in `quirks`
+pub fn get_fido_pcsc_devices_with_quirks(quirks: &QuirkTable) -> Result<Vec<String>> {
     // Get a context.
     let ctx = Context::establish(Scope::User).expect("failed to establish context");
 
@@ -123,7 +141,7 @@ pub fn get_fido_pcsc_devices() -> Result<Vec<String>> {
             .map_err(|err| anyhow!("Failed to get card status on reader \"{reader_str}\": {err}"))?;
 
         if status.protocol2().is_some() {
-            let cmd_apdu = SELECT_FIDO2_APDU;
+            let cmd_apdu = select_apdu(quirks, status.atr());
             let mut response_apdu = vec![0; MAX_BUFFER_SIZE_EXTENDED];
             tx.transmit(cmd_apdu, &mut response_apdu)
                 .map_err(|err| anyhow!("Failed to transmit APDU to reader \"{reader_str}\": {err}"))?;
@@ -3,9 +3,11 @@ use crate::util;
 use serde_cbor::Value;
 use std::fmt;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct PublicKey {
     pub pem: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub der: Vec<u8>,
 }
 impl PublicKey {
@@ -2,6 +2,7 @@ use crate::util;
 use serde_cbor::Value;
 use std::fmt;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone)]
 pub struct PublicKeyCredentialRpEntity {
     pub id: String,
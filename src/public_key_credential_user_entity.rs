@@ -2,8 +2,10 @@ use crate::util;
 use serde_cbor::Value;
 use std::fmt;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct PublicKeyCredentialUserEntity {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_ext::base64url"))]
     pub id: Vec<u8>,
     pub name: String,
     pub display_name: String,
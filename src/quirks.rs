@@ -0,0 +1,323 @@
+/*!
+Per-device quirks
+
+Workarounds for authenticators that deviate from the specification,
+looked up by VID/PID and by AAGUID (from authenticatorGetInfo).
+PC/SC cards have no VID/PID, they are looked up by their ATR.
+
+The built-in table is in `LibCfg::quirks` and can be extended at runtime.
+
+```ignore
+let mut cfg = LibCfg::init();
+cfg.quirks.add(
+    QuirkKey::VidPid { vid: 0x1234, pid: 0x5678 },
+    Quirks {
+        use_pre_credential_management: Some(true),
+        ..Default::default()
+    },
+);
+let device = FidoKeyHidFactory::create(&cfg)?;
+```
+*/
+use crate::ctapdef;
+
+/// Key of a quirk entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuirkKey {
+    VidPid {
+        vid: u16,
+        pid: u16,
+    },
+    /// Requires authenticatorGetInfo when the device is opened
+    Aaguid(Vec<u8>),
+    /// Bytes contained in the ATR of a PC/SC card (ex. the historical bytes)
+    Atr(Vec<u8>),
+}
+
+/// Variant of the SELECT command of the FIDO applet (NFC/CCID)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SelectApdu {
+    /// P2=0x0C, as in the specification
+    Standard,
+    /// P2=0x00, YubiKeys reject the standard one but accept this one.
+    /// Other keys work as well with this one so far.
+    #[default]
+    P2Zero,
+    /// AID without the last 2 bytes, bypasses the windows admin requirement
+    ShortAid,
+}
+
+impl SelectApdu {
+    #[rustfmt::skip]
+    pub const fn apdu(&self) -> &'static [u8] {
+        //        CLA   INS   P1    P2    Lc    RID                           AX    AC
+        match self {
+            Self::Standard => &[0x00, 0xa4, 0x04, 0x0c, 0x08, 0xa0, 0x00, 0x00, 0x06, 0x47, 0x2f, 0x00, 0x01],
+            Self::P2Zero   => &[0x00, 0xa4, 0x04, 0x00, 0x08, 0xa0, 0x00, 0x00, 0x06, 0x47, 0x2f, 0x00, 0x01],
+            Self::ShortAid => &[0x00, 0xa4, 0x04, 0x00, 0x06, 0xa0, 0x00, 0x00, 0x06, 0x47, 0x2f],
+        }
+    }
+}
+
+/// Workarounds for a device (None: the default behavior)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// Use the preview (CTAP 2.1-PRE) bioEnrollment command
+    pub use_pre_bio_enrollment: Option<bool>,
+    /// Use the preview (CTAP 2.1-PRE) credentialManagement command
+    pub use_pre_credential_management: Option<bool>,
+    /// SELECT command of the FIDO applet (PC/SC)
+    pub select_apdu: Option<SelectApdu>,
+    /// Upper limit of the fragment size of largeBlobs
+    pub max_fragment_length: Option<usize>,
+    /// (command, subCommand) that must not be sent
+    pub broken_sub_commands: Vec<(u8, u8)>,
+    /// Messages of vendor specific status codes
+    pub status_messages: Vec<(u8, String)>,
+}
+
+impl Quirks {
+    /// Merge `other` into `self` (the values of `other` take precedence)
+    pub fn merge(&mut self, other: &Quirks) {
+        if other.use_pre_bio_enrollment.is_some() {
+            self.use_pre_bio_enrollment = other.use_pre_bio_enrollment;
+        }
+        if other.use_pre_credential_management.is_some() {
+            self.use_pre_credential_management = other.use_pre_credential_management;
+        }
+        if other.select_apdu.is_some() {
+            self.select_apdu = other.select_apdu;
+        }
+        if other.max_fragment_length.is_some() {
+            self.max_fragment_length = other.max_fragment_length;
+        }
+        self.broken_sub_commands
+            .extend_from_slice(&other.broken_sub_commands);
+        // later messages are found first
+        self.status_messages
+            .splice(0..0, other.status_messages.iter().cloned());
+    }
+
+    pub fn is_broken(&self, command: u8, sub_command: u8) -> bool {
+        self.broken_sub_commands.contains(&(command, sub_command))
+    }
+
+    pub fn status_message(&self, status: u8) -> Option<&str> {
+        self.status_messages
+            .iter()
+            .find(|(code, _)| *code == status)
+            .map(|(_, message)| message.as_str())
+    }
+
+    /// Error if the sub command is known to be broken on this device
+    pub(crate) fn check_sub_command(&self, command: u8, sub_command: u8) -> anyhow::Result<()> {
+        if self.is_broken(command, sub_command) {
+            return Err(anyhow::anyhow!(
+                "{} (command=0x{:02X} subCommand=0x{:02X} is broken on this device)",
+                ctapdef::get_ctap_status_message(0x3E),
+                command,
+                sub_command
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Quirks database
+#[derive(Debug, Clone)]
+pub struct QuirkTable {
+    entries: Vec<(QuirkKey, Quirks)>,
+}
+
+impl Default for QuirkTable {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl QuirkTable {
+    /// Empty table
+    pub fn new() -> Self {
+        Self { entries: vec![] }
+    }
+
+    /// Known devices
+    pub fn builtin() -> Self {
+        let mut table = Self::new();
+
+        // Biopass: CTAP 2.1-PRE and an undocumented status
+        table.add(
+            QuirkKey::VidPid {
+                vid: 0x096E,
+                pid: 0x085D,
+            },
+            Quirks {
+                use_pre_bio_enrollment: Some(true),
+                use_pre_credential_management: Some(true),
+                status_messages: vec![(0x6A, "0x6A BioPass UnKnown Error.".to_string())],
+                ..Default::default()
+            },
+        );
+
+        // YubiKey (USB CCID and NFC): rejects the standard SELECT
+        table.add(
+            QuirkKey::Atr(b"YubiKey".to_vec()),
+            Quirks {
+                select_apdu: Some(SelectApdu::P2Zero),
+                ..Default::default()
+            },
+        );
+
+        table
+    }
+
+    /// Add an entry (it takes precedence over the existing entries)
+    pub fn add(&mut self, key: QuirkKey, quirks: Quirks) {
+        self.entries.push((key, quirks));
+    }
+
+    pub fn entries(&self) -> &[(QuirkKey, Quirks)] {
+        &self.entries
+    }
+
+    /// AAGUID entries need authenticatorGetInfo
+    pub fn has_aaguid_entries(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|(key, _)| matches!(key, QuirkKey::Aaguid(_)))
+    }
+
+    /// Quirks of the device (VID/PID entries, then AAGUID entries)
+    pub fn lookup(&self, vid: u16, pid: u16, aaguid: Option<&[u8]>) -> Quirks {
+        let mut quirks = Quirks::default();
+        for (key, entry) in &self.entries {
+            if *key == (QuirkKey::VidPid { vid, pid }) {
+                quirks.merge(entry);
+            }
+        }
+        for (key, entry) in &self.entries {
+            if let QuirkKey::Aaguid(a) = key {
+                if Some(a.as_slice()) == aaguid {
+                    quirks.merge(entry);
+                }
+            }
+        }
+        quirks
+    }
+
+    /// Quirks of a PC/SC card (ATR entries)
+    pub fn lookup_atr(&self, atr: &[u8]) -> Quirks {
+        let mut quirks = Quirks::default();
+        for (key, entry) in &self.entries {
+            if let QuirkKey::Atr(bytes) = key {
+                if !bytes.is_empty() && atr.windows(bytes.len()).any(|w| w == bytes.as_slice()) {
+                    quirks.merge(entry);
+                }
+            }
+        }
+        quirks
+    }
+}
+
+//
+// test
+//
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        let table = QuirkTable::builtin();
+        let quirks = table.lookup(0x096E, 0x085D, None);
+        assert_eq!(quirks.use_pre_bio_enrollment, Some(true));
+        assert!(quirks.status_message(0x6A).is_some());
+        assert_eq!(quirks.status_message(0x01), None);
+
+        assert_eq!(table.lookup(0x1234, 0x5678, None), Quirks::default());
+        assert!(!table.has_aaguid_entries());
+    }
+
+    #[test]
+    fn test_select_apdu() {
+        // YubiKey 5 over USB CCID and over NFC
+        let yubikey_ccid = [
+            0x3b, 0xfd, 0x13, 0x00, 0x00, 0x81, 0x31, 0xfe, 0x15, 0x80, 0x73, 0xc0, 0x21, 0xc0,
+            0x57, 0x59, 0x75, 0x62, 0x69, 0x4b, 0x65, 0x79, 0x40,
+        ];
+        let yubikey_nfc = [
+            0x3b, 0x8d, 0x80, 0x01, 0x80, 0x73, 0xc0, 0x21, 0xc0, 0x57, 0x59, 0x75, 0x62, 0x69,
+            0x4b, 0x65, 0x79, 0xf9,
+        ];
+        let other = [0x3b, 0x8f, 0x80, 0x01, 0x80, 0x4f, 0x0c, 0xa0, 0x00];
+
+        let mut table = QuirkTable::builtin();
+        for atr in [&yubikey_ccid[..], &yubikey_nfc[..]] {
+            assert_eq!(table.lookup_atr(atr).select_apdu, Some(SelectApdu::P2Zero));
+        }
+        assert_eq!(table.lookup_atr(&other), Quirks::default());
+        // VID/PID lookups do not see ATR entries
+        assert_eq!(table.lookup(0x1050, 0x0407, None).select_apdu, None);
+
+        table.add(
+            QuirkKey::Atr(vec![0x4f, 0x0c]),
+            Quirks {
+                select_apdu: Some(SelectApdu::Standard),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            crate::pcsc::select_apdu(&table, &other),
+            SelectApdu::Standard.apdu()
+        );
+        assert_eq!(crate::pcsc::select_apdu(&table, &other)[3], 0x0c);
+        assert_eq!(
+            crate::pcsc::select_apdu(&table, &yubikey_nfc),
+            SelectApdu::P2Zero.apdu()
+        );
+        assert_eq!(
+            crate::pcsc::select_apdu(&QuirkTable::new(), &other),
+            crate::pcsc::SELECT_FIDO2_APDU
+        );
+    }
+
+    #[test]
+    fn test_runtime_entries() {
+        let mut table = QuirkTable::builtin();
+        table.add(
+            QuirkKey::VidPid {
+                vid: 0x096E,
+                pid: 0x085D,
+            },
+            Quirks {
+                use_pre_bio_enrollment: Some(false),
+                status_messages: vec![(0x6A, "overridden".to_string())],
+                ..Default::default()
+            },
+        );
+        table.add(
+            QuirkKey::Aaguid(vec![0xaa; 16]),
+            Quirks {
+                max_fragment_length: Some(256),
+                broken_sub_commands: vec![(ctapdef::AUTHENTICATOR_CONFIG, 0x02)],
+                ..Default::default()
+            },
+        );
+        assert!(table.has_aaguid_entries());
+
+        let quirks = table.lookup(0x096E, 0x085D, Some(&[0xaa; 16]));
+        assert_eq!(quirks.use_pre_bio_enrollment, Some(false));
+        assert_eq!(quirks.use_pre_credential_management, Some(true));
+        assert_eq!(quirks.status_message(0x6A), Some("overridden"));
+        assert_eq!(quirks.max_fragment_length, Some(256));
+        assert!(quirks
+            .check_sub_command(ctapdef::AUTHENTICATOR_CONFIG, 0x02)
+            .is_err());
+        assert!(quirks
+            .check_sub_command(ctapdef::AUTHENTICATOR_CONFIG, 0x03)
+            .is_ok());
+
+        let quirks = table.lookup(0x1234, 0x5678, Some(&[0xbb; 16]));
+        assert_eq!(quirks, Quirks::default());
+    }
+}
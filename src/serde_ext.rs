@@ -0,0 +1,226 @@
+//! serde helpers for the `serde` feature.
+//!
+//! Byte strings are written as unpadded base64url, except AAGUIDs which are
+//! written as lowercase hex.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode<E: de::Error>(s: &str) -> Result<Vec<u8>, E> {
+    base64::decode_config(s, base64::URL_SAFE_NO_PAD).map_err(E::custom)
+}
+
+fn to_sized<T: TryFrom<Vec<u8>>, E: de::Error>(bytes: Vec<u8>) -> Result<T, E> {
+    let len = bytes.len();
+    T::try_from(bytes).map_err(|_| E::invalid_length(len, &"a byte string of the expected size"))
+}
+
+/// Bytes as unpadded base64url
+pub(crate) mod base64url {
+    use super::*;
+
+    pub fn serialize<T: AsRef<[u8]>, S: Serializer>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&encode(v.as_ref()))
+    }
+
+    pub fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<T, D::Error> {
+        let s = String::deserialize(d)?;
+        to_sized(decode(&s)?)
+    }
+}
+
+/// Optional bytes as unpadded base64url
+pub(crate) mod base64url_option {
+    use super::*;
+
+    pub fn serialize<T: AsRef<[u8]>, S: Serializer>(
+        v: &Option<T>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        v.as_ref().map(|v| encode(v.as_ref())).serialize(s)
+    }
+
+    pub fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Option<T>, D::Error> {
+        match Option::<String>::deserialize(d)? {
+            Some(s) => Ok(Some(to_sized(decode(&s)?)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// List of byte strings as unpadded base64url
+pub(crate) mod base64url_vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(v: &[Vec<u8>], s: S) -> Result<S::Ok, S::Error> {
+        v.iter()
+            .map(|x| encode(x))
+            .collect::<Vec<String>>()
+            .serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .iter()
+            .map(|s| decode(s))
+            .collect()
+    }
+}
+
+/// `(Option<bool>, Option<bytes>)` extension values with base64url bytes
+pub(crate) mod flag_and_base64url {
+    use super::*;
+
+    type Value = (Option<bool>, Option<Vec<u8>>);
+
+    pub fn serialize<S: Serializer>(v: &Value, s: S) -> Result<S::Ok, S::Error> {
+        (v.0, v.1.as_ref().map(|x| encode(x))).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Value, D::Error> {
+        let (flag, bytes) = <(Option<bool>, Option<String>)>::deserialize(d)?;
+        Ok((flag, bytes.map(|s| decode(&s)).transpose()?))
+    }
+}
+
+/// `(Option<bytes>, Option<bool>)` extension values with base64url bytes
+pub(crate) mod base64url_and_flag {
+    use super::*;
+
+    type Value = (Option<Vec<u8>>, Option<bool>);
+
+    pub fn serialize<S: Serializer>(v: &Value, s: S) -> Result<S::Ok, S::Error> {
+        (v.0.as_ref().map(|x| encode(x)), v.1).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Value, D::Error> {
+        let (bytes, flag) = <(Option<String>, Option<bool>)>::deserialize(d)?;
+        Ok((bytes.map(|s| decode(&s)).transpose()?, flag))
+    }
+}
+
+/// Map keyed by byte strings, keys as unpadded base64url
+pub(crate) mod base64url_keys {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    pub fn serialize<V: Serialize, S: Serializer>(
+        v: &BTreeMap<Vec<u8>, V>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        s.collect_map(v.iter().map(|(k, v)| (encode(k), v)))
+    }
+
+    pub fn deserialize<'de, V: Deserialize<'de>, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<BTreeMap<Vec<u8>, V>, D::Error> {
+        BTreeMap::<String, V>::deserialize(d)?
+            .into_iter()
+            .map(|(k, v)| Ok((decode(&k)?, v)))
+            .collect()
+    }
+}
+
+/// Bytes as lowercase hex
+pub(crate) mod hex {
+    use super::*;
+
+    pub fn serialize<T: AsRef<[u8]>, S: Serializer>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&::hex::encode(v.as_ref()))
+    }
+
+    pub fn deserialize<'de, T: TryFrom<Vec<u8>>, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<T, D::Error> {
+        let s = String::deserialize(d)?;
+        to_sized(::hex::decode(s).map_err(de::Error::custom)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fidokey::get_assertion::get_assertion_params::{
+        Assertion, Extension, PrfInputs, PrfValues,
+    };
+    use crate::fidokey::get_info::{Info, InfoOptions, Transport};
+    use crate::public_key_credential_user_entity::PublicKeyCredentialUserEntity;
+
+    #[test]
+    fn test_info_json() {
+        let info = Info {
+            versions: vec!["FIDO_2_0".to_string()],
+            aaguid: vec![0xee, 0x88, 0x28, 0x79],
+            options: InfoOptions {
+                rk: Some(true),
+                others: vec![("vendorOption".to_string(), false)],
+                ..Default::default()
+            },
+            transports: vec![Transport::Usb, Transport::Other("vendor".to_string())],
+            unknown_members: vec![(
+                serde_cbor::Value::Integer(0x20),
+                serde_cbor::Value::Integer(1),
+            )],
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["aaguid"], "ee882879");
+        assert_eq!(json["versions"][0], "FIDO_2_0");
+        assert_eq!(json["transports"][1], "vendor");
+
+        let info2: Info = serde_json::from_value(json).unwrap();
+        assert_eq!(info2.aaguid, info.aaguid);
+        assert_eq!(info2, info);
+    }
+
+    #[test]
+    fn test_assertion_json() {
+        let ass = Assertion {
+            credential_id: vec![0xfb, 0xff, 0x00],
+            user: PublicKeyCredentialUserEntity::new(Some(b"\x01\x02"), Some("gebo"), None),
+            extensions: vec![
+                Extension::HmacSecret(Some([0x11; 32]), None),
+                Extension::CredBlob((None, Some(b"blob".to_vec()))),
+            ],
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&ass).unwrap();
+        assert_eq!(json["credential_id"], "-_8A");
+        assert_eq!(json["user"]["id"], "AQI");
+        assert_eq!(json["extensions"][1]["credBlob"][1], "YmxvYg");
+
+        let ass2: Assertion = serde_json::from_value(json).unwrap();
+        assert_eq!(ass2.credential_id, ass.credential_id);
+        assert_eq!(ass2.user, ass.user);
+        assert!(
+            matches!(ass2.extensions[0], Extension::HmacSecret(Some(x), None) if x == [0x11; 32])
+        );
+    }
+
+    #[test]
+    fn test_prf_json() {
+        let mut inputs = PrfInputs::default();
+        inputs
+            .eval_by_credential
+            .insert(vec![0xfb, 0xff], PrfValues::new(b"first", None));
+        let ext = Extension::Prf(Some(inputs), None);
+
+        let json = serde_json::to_value(&ext).unwrap();
+        assert_eq!(
+            json["prf"][0]["eval_by_credential"]["-_8"]["first"],
+            "Zmlyc3Q"
+        );
+
+        let ext2: Extension = serde_json::from_value(json).unwrap();
+        assert!(
+            matches!(ext2, Extension::Prf(Some(x), None) if x.eval_by_credential[&vec![0xfb, 0xff]].first == b"first")
+        );
+    }
+}